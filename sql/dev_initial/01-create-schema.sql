@@ -5,18 +5,93 @@ CREATE TABLE users
     username varchar(128) NOT NULL UNIQUE,
     pwd varchar(256),
     pwd_salt uuid NOT NULL DEFAULT gen_random_uuid(),
-    token_salt uuid NOT NULL DEFAULT gen_random_uuid()
+    token_salt uuid NOT NULL DEFAULT gen_random_uuid(),
+    -- Bytes of attachments the user may upload, NULL for SERVICE_UPLOAD_QUOTA_USER_BYTES
+    storage_quota_bytes BIGINT CHECK (storage_quota_bytes >= 0)
 );
 
+-- Workspaces
+CREATE TABLE workspaces
+(
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1) PRIMARY KEY,
+    name varchar(128) NOT NULL,
+    -- New users are added to auto_join workspaces on sign up
+    auto_join BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE workspace_members
+(
+    workspace_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    role varchar(16) NOT NULL DEFAULT 'member'
+        CHECK (role IN ('owner', 'admin', 'moderator', 'member', 'guest')),
+    joined_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (workspace_id, user_id),
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_workspace_members_user_id ON workspace_members (user_id);
+
 -- Text Rooms
+CREATE TABLE room_categories
+(
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1) PRIMARY KEY,
+    workspace_id BIGINT NOT NULL,
+    name varchar(128) NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0,
+
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_room_categories_workspace_id ON room_categories (workspace_id);
+
+CREATE TYPE room_kind AS ENUM ('text', 'voice', 'announcement', 'forum', 'stage');
+
 CREATE TABLE rooms
 (
     id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1) PRIMARY KEY,
-    room_type varchar(5) NOT NULL,
-    title varchar(256) NOT NULL
+    workspace_id BIGINT NOT NULL,
+    category_id BIGINT,
+    room_type room_kind NOT NULL DEFAULT 'text',
+    title varchar(256) NOT NULL,
+    topic varchar(1024),
+    visibility varchar(7) NOT NULL DEFAULT 'public' CHECK (visibility IN ('public', 'private')),
+    position INTEGER NOT NULL DEFAULT 0,
+    archived BOOLEAN NOT NULL DEFAULT false,
+    slow_mode_sec INTEGER NOT NULL DEFAULT 0 CHECK (slow_mode_sec >= 0),
+    voice_capacity INTEGER CHECK (voice_capacity > 0),
+    push_to_talk BOOLEAN NOT NULL DEFAULT false,
+    -- Bytes of attachments the room may hold, NULL for SERVICE_UPLOAD_QUOTA_ROOM_BYTES
+    storage_quota_bytes BIGINT CHECK (storage_quota_bytes >= 0),
+    created_by BIGINT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
 
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+    FOREIGN KEY (category_id) REFERENCES room_categories(id) ON DELETE SET NULL,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
 );
 
+CREATE INDEX idx_rooms_workspace_id ON rooms (workspace_id);
+
+-- Room Members
+CREATE TABLE room_members
+(
+    room_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    role varchar(16) NOT NULL DEFAULT 'member'
+        CHECK (role IN ('owner', 'admin', 'moderator', 'member', 'guest')),
+    joined_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (room_id, user_id),
+    FOREIGN KEY (room_id) REFERENCES rooms(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_room_members_user_id ON room_members (user_id);
+
 -- Chat Messages
 CREATE TABLE messages
 (
@@ -30,22 +105,109 @@ CREATE TABLE messages
     FOREIGN KEY (message_user_id) REFERENCES users(id) ON DELETE CASCADE
 );
 
+-- When each user last posted in a slow mode room
+CREATE TABLE room_last_posts
+(
+    room_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    last_posted_at TIMESTAMP WITH TIME ZONE NOT NULL,
+
+    PRIMARY KEY (room_id, user_id),
+    FOREIGN KEY (room_id) REFERENCES rooms(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
 CREATE TABLE room_participants (
     room_id BIGINT NOT NULL,
     user_id BIGINT NOT NULL,
-    joined_at TIMESTAMPTZ DEFAULT now(),
-    PRIMARY KEY (room_id, user_id)
+    muted BOOLEAN NOT NULL DEFAULT false,
+    deafened BOOLEAN NOT NULL DEFAULT false,
+    speaking BOOLEAN NOT NULL DEFAULT false,
+    video BOOLEAN NOT NULL DEFAULT false,
+    speaker BOOLEAN NOT NULL DEFAULT true,
+    hand_raised BOOLEAN NOT NULL DEFAULT false,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (room_id, user_id),
+    FOREIGN KEY (room_id) REFERENCES rooms(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_room_participants_user_id ON room_participants (user_id);
+
+-- Attachments
+-- Stored files, shared by the attachments with the same content
+CREATE TABLE blobs (
+    -- Hex SHA-256 of the stored file
+    sha256 TEXT PRIMARY KEY,
+    storage_key TEXT NOT NULL,
+    thumbnail_key TEXT,
+    preview_key TEXT,
+    size_bytes BIGINT NOT NULL,
+    -- Number of attachments with this content, the files are deleted with the last one
+    ref_count INTEGER NOT NULL,
+    -- True once the files are in the blob store, until then every upload of the content
+    -- stores them
+    stored BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    -- Last time an upload took a reference, the count may be ahead of the attachments until
+    -- that upload is done
+    acquired_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
 );
 
--- Images
-CREATE TABLE images (
+CREATE TYPE attachment_kind AS ENUM ('image', 'document', 'text', 'archive');
+
+CREATE TABLE attachments (
     id UUID PRIMARY KEY,
-    message_id BIGINT REFERENCES messages(id),
+    message_id BIGINT REFERENCES messages(id) ON DELETE CASCADE,
     user_id BIGINT REFERENCES users(id),
-    filename TEXT,
-    content_type TEXT,
-    storage_path TEXT,
-    uploaded_at TIMESTAMP WITH TIME ZONE DEFAULT now()
+    kind attachment_kind NOT NULL,
+    -- Name of the file on the uploader's machine, only used for downloads
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    storage_key TEXT NOT NULL,
+    sha256 TEXT NOT NULL REFERENCES blobs(sha256),
+    -- Images only
+    width INTEGER,
+    height INTEGER,
+    blurhash TEXT,
+    thumbnail_key TEXT,
+    preview_key TEXT,
+    -- True once EXIF/XMP and other metadata have been stripped from the stored file
+    sanitized BOOLEAN NOT NULL DEFAULT false,
+    uploaded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_attachments_message_id ON attachments (message_id);
+CREATE INDEX idx_attachments_sha256 ON attachments (sha256);
+CREATE INDEX idx_attachments_user_id ON attachments (user_id);
+
+-- Uploads flagged by the scanner, kept out of reach of the room for moderators to review
+CREATE TABLE quarantined_files
+(
+    id UUID PRIMARY KEY,
+    user_id BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    room_id BIGINT REFERENCES rooms(id) ON DELETE SET NULL,
+    filename TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    sha256 TEXT NOT NULL,
+    storage_key TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+-- Resumable uploads, the received bytes are staged on disk until the session is finalized
+CREATE TABLE upload_sessions (
+    id UUID PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    filename TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    received_bytes BIGINT NOT NULL DEFAULT 0,
+    -- Hex SHA-256 announced by the client, checked when the session is finalized
+    sha256 TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
 );
 
 -- Friends
@@ -80,3 +242,52 @@ CREATE TABLE private_messages
     FOREIGN KEY (sender_id) REFERENCES users(id) ON DELETE CASCADE,
     FOREIGN KEY (receiver_id) REFERENCES users(id) ON DELETE CASCADE
 );
+
+-- User Blocks
+CREATE TABLE user_blocks
+(
+    blocker_id BIGINT NOT NULL,
+    blocked_id BIGINT NOT NULL,
+    blocked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (blocker_id, blocked_id),
+    CHECK (blocker_id <> blocked_id),
+    FOREIGN KEY (blocker_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (blocked_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_blocks_blocked_id ON user_blocks (blocked_id);
+
+-- Invites (room_id NULL means a workspace-wide invite)
+CREATE TABLE invites
+(
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY (START WITH 1) PRIMARY KEY,
+    code varchar(32) NOT NULL UNIQUE,
+    workspace_id BIGINT NOT NULL,
+    room_id BIGINT,
+    created_by BIGINT NOT NULL,
+    role varchar(16) NOT NULL DEFAULT 'member'
+        CHECK (role IN ('admin', 'moderator', 'member', 'guest')),
+    max_uses INTEGER CHECK (max_uses > 0),
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    revoked BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+    FOREIGN KEY (room_id) REFERENCES rooms(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_invites_workspace_id ON invites (workspace_id);
+
+CREATE TABLE invite_uses
+(
+    invite_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (invite_id, user_id),
+    FOREIGN KEY (invite_id) REFERENCES invites(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
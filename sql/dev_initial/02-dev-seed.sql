@@ -1,2 +1,8 @@
 INSERT INTO users (username) VALUES ( 'dallas');
-INSERT INTO rooms (id, room_type, title) VALUES ( '2', 'text', 'Room 1');
+INSERT INTO workspaces (name, auto_join) VALUES ('Default', true);
+INSERT INTO workspace_members (workspace_id, user_id, role)
+    SELECT w.id, u.id, 'owner' FROM workspaces w, users u
+    WHERE w.name = 'Default' AND u.username = 'dallas';
+INSERT INTO rooms (id, workspace_id, room_type, title)
+    SELECT '2', id, 'text'::room_kind, 'Room 1' FROM workspaces WHERE name = 'Default';
+SELECT setval(pg_get_serial_sequence('rooms', 'id'), (SELECT MAX(id) FROM rooms));
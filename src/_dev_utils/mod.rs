@@ -1,5 +1,6 @@
 mod dev_db;
 use crate::Ctx;
+use crate::model::user::{User, UserBmc};
 use crate::model::{self, ModelManager};
 use log::info;
 use tokio::sync::OnceCell;
@@ -28,3 +29,31 @@ pub async fn init_test() -> ModelManager {
 
     mm.clone()
 }
+
+/// Creates a user with the password `welcome`, returning its id.
+pub async fn seed_user(mm: &ModelManager, username: &str) -> model::Result<i64> {
+    UserBmc::create_user(mm, username, "welcome").await?;
+    let user: Option<User> = UserBmc::first_by_username(&Ctx::root_ctx(), mm, username).await?;
+
+    user.map(|user| user.id)
+        .ok_or_else(|| model::Error::UsernameNotFound {
+            username: username.to_string(),
+        })
+}
+
+/// Creates a public room of `kind` in the `Default` workspace, returning its id.
+pub async fn seed_room(mm: &ModelManager, title: &str, kind: &str) -> model::Result<i64> {
+    let (id,): (i64,) = sqlx::query_as(
+        r#"
+        INSERT INTO rooms (workspace_id, room_type, title)
+        SELECT id, $2::room_kind, $1 FROM workspaces WHERE name = 'Default'
+        RETURNING id
+        "#,
+    )
+    .bind(title)
+    .bind(kind)
+    .fetch_one(mm.db())
+    .await?;
+
+    Ok(id)
+}
@@ -23,6 +23,46 @@ pub struct Config {
     pub DB_URL: String,
     // Web
     pub WEB_FOLDER: String,
+    // Voice
+    pub VOICE_RELAY_ENABLED: bool,
+    // Uploads
+    pub UPLOAD_MAX_IMAGE_BYTES: usize,
+    pub UPLOAD_MAX_DOCUMENT_BYTES: usize,
+    pub UPLOAD_MAX_TEXT_BYTES: usize,
+    pub UPLOAD_MAX_ARCHIVE_BYTES: usize,
+    pub UPLOAD_MAX_IMAGE_DIMENSION: u32,
+    pub UPLOAD_KEEP_ICC_PROFILE: bool,
+    pub UPLOAD_STAGING_DIR: String,
+    pub UPLOAD_SESSION_TTL_SEC: f64,
+    pub UPLOAD_QUOTA_USER_BYTES: i64,
+    pub UPLOAD_QUOTA_ROOM_BYTES: i64,
+    pub UPLOAD_SCANNER: ScannerConfig,
+    pub BLOB_STORE: BlobStoreConfig,
+    // Janitor
+    pub JANITOR_INTERVAL_SEC: u64,
+    pub JANITOR_GRACE_SEC: u64,
+}
+
+pub enum BlobStoreConfig {
+    /// Files under a local directory.
+    Local { root: String },
+    /// Objects of an S3-compatible bucket.
+    S3(S3Config),
+}
+
+pub enum ScannerConfig {
+    /// Uploads are not scanned.
+    None,
+    /// A clamd daemon at `host:port` or a unix socket path.
+    Clamd { addr: String },
+}
+
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: String,
+    pub access_key: String,
+    pub secret_key: String,
 }
 
 impl Config {
@@ -37,6 +77,24 @@ impl Config {
             DB_URL: get_env("SERVICE_DB_URL")?,
             // Web
             WEB_FOLDER: get_env("SERVICE_WEB_FOLDER")?,
+            // Voice
+            VOICE_RELAY_ENABLED: get_env_parse("SERVICE_VOICE_RELAY_ENABLED")?,
+            // Uploads
+            UPLOAD_MAX_IMAGE_BYTES: get_env_parse("SERVICE_UPLOAD_MAX_IMAGE_BYTES")?,
+            UPLOAD_MAX_DOCUMENT_BYTES: get_env_parse("SERVICE_UPLOAD_MAX_DOCUMENT_BYTES")?,
+            UPLOAD_MAX_TEXT_BYTES: get_env_parse("SERVICE_UPLOAD_MAX_TEXT_BYTES")?,
+            UPLOAD_MAX_ARCHIVE_BYTES: get_env_parse("SERVICE_UPLOAD_MAX_ARCHIVE_BYTES")?,
+            UPLOAD_MAX_IMAGE_DIMENSION: get_env_parse("SERVICE_UPLOAD_MAX_IMAGE_DIMENSION")?,
+            UPLOAD_KEEP_ICC_PROFILE: get_env_parse("SERVICE_UPLOAD_KEEP_ICC_PROFILE")?,
+            UPLOAD_STAGING_DIR: get_env("SERVICE_UPLOAD_STAGING_DIR")?,
+            UPLOAD_SESSION_TTL_SEC: get_env_parse("SERVICE_UPLOAD_SESSION_TTL_SEC")?,
+            UPLOAD_QUOTA_USER_BYTES: get_env_parse("SERVICE_UPLOAD_QUOTA_USER_BYTES")?,
+            UPLOAD_QUOTA_ROOM_BYTES: get_env_parse("SERVICE_UPLOAD_QUOTA_ROOM_BYTES")?,
+            UPLOAD_SCANNER: get_env_scanner()?,
+            BLOB_STORE: get_env_blob_store()?,
+            // Janitor
+            JANITOR_INTERVAL_SEC: get_env_parse("SERVICE_JANITOR_INTERVAL_SEC")?,
+            JANITOR_GRACE_SEC: get_env_parse("SERVICE_JANITOR_GRACE_SEC")?,
         })
     }
 }
@@ -50,6 +108,32 @@ fn get_env_parse<T: FromStr>(name: &'static str) -> Result<T> {
     val.parse::<T>().map_err(|_| Error::ConfigWrongFormat(name))
 }
 
+fn get_env_blob_store() -> Result<BlobStoreConfig> {
+    match get_env("SERVICE_BLOB_STORE")?.as_str() {
+        "local" => Ok(BlobStoreConfig::Local {
+            root: get_env("SERVICE_BLOB_LOCAL_ROOT")?,
+        }),
+        "s3" => Ok(BlobStoreConfig::S3(S3Config {
+            bucket: get_env("SERVICE_S3_BUCKET")?,
+            region: get_env("SERVICE_S3_REGION")?,
+            endpoint: get_env("SERVICE_S3_ENDPOINT")?,
+            access_key: get_env("SERVICE_S3_ACCESS_KEY")?,
+            secret_key: get_env("SERVICE_S3_SECRET_KEY")?,
+        })),
+        _ => Err(Error::ConfigWrongFormat("SERVICE_BLOB_STORE")),
+    }
+}
+
+fn get_env_scanner() -> Result<ScannerConfig> {
+    match get_env("SERVICE_UPLOAD_SCANNER")?.as_str() {
+        "none" => Ok(ScannerConfig::None),
+        "clamd" => Ok(ScannerConfig::Clamd {
+            addr: get_env("SERVICE_CLAMD_ADDR")?,
+        }),
+        _ => Err(Error::ConfigWrongFormat("SERVICE_UPLOAD_SCANNER")),
+    }
+}
+
 fn get_env_b64u_as_u8s(name: &'static str) -> Result<Vec<u8>> {
     base64_url::decode(&get_env(name)?).map_err(|_| Error::ConfigWrongFormat(name))
 }
@@ -6,9 +6,16 @@ pub type Result<T> = core::result::Result<T, Error>;
 pub enum Error {
     // Key
     KeyFailHmac,
+    KeyFailArgon2,
 
     // Pwd
     PwdNotMatching,
+    PwdWithSchemeFailedParse,
+    PwdSaltInvalid,
+    PwdFailHash,
+    PwdFailSpawnBlockForHash,
+    PwdFailSpawnBlockForValidate,
+    SchemeNotFound(String),
 
     // Token
     TokenInvalidFormat,
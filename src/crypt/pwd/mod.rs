@@ -0,0 +1,121 @@
+//! Password hashing with versioned schemes. Stored passwords are prefixed with the scheme that
+//! hashed them, e.g. `#02#$argon2id$...`, so hashes from an older scheme still validate and
+//! can be upgraded at the next login.
+
+mod scheme;
+
+use self::scheme::{DEFAULT_SCHEME, get_scheme};
+use crate::crypt::{EncryptContent, Error, Result};
+
+/// Hashes the password with the default scheme.
+pub async fn hash_pwd(to_hash: EncryptContent) -> Result<String> {
+    tokio::task::spawn_blocking(move || hash_for_scheme(DEFAULT_SCHEME, &to_hash))
+        .await
+        .map_err(|_| Error::PwdFailSpawnBlockForHash)?
+}
+
+/// Validates the password against `pwd_ref`. Returns whether `pwd_ref` is from an older scheme
+/// and should be re-hashed.
+pub async fn validate_pwd(to_hash: EncryptContent, pwd_ref: String) -> Result<SchemeStatus> {
+    let PwdParts {
+        scheme_name,
+        hashed,
+    } = pwd_ref.parse()?;
+    let scheme_status = if scheme_name == DEFAULT_SCHEME {
+        SchemeStatus::Ok
+    } else {
+        SchemeStatus::Outdated
+    };
+
+    tokio::task::spawn_blocking(move || get_scheme(&scheme_name)?.validate(&to_hash, &hashed))
+        .await
+        .map_err(|_| Error::PwdFailSpawnBlockForValidate)??;
+
+    Ok(scheme_status)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemeStatus {
+    /// Hashed with the default scheme.
+    Ok,
+    Outdated,
+}
+
+fn hash_for_scheme(scheme_name: &str, to_hash: &EncryptContent) -> Result<String> {
+    let hashed = get_scheme(scheme_name)?.hash(to_hash)?;
+
+    Ok(format!("#{scheme_name}#{hashed}"))
+}
+
+struct PwdParts {
+    scheme_name: String,
+    hashed: String,
+}
+
+impl std::str::FromStr for PwdParts {
+    type Err = Error;
+
+    /// Splits `#<scheme_name>#<hashed>`.
+    fn from_str(pwd_with_scheme: &str) -> Result<Self> {
+        pwd_with_scheme
+            .strip_prefix('#')
+            .and_then(|rest| rest.split_once('#'))
+            .filter(|(scheme_name, _)| !scheme_name.is_empty())
+            .map(|(scheme_name, hashed)| Self {
+                scheme_name: scheme_name.to_string(),
+                hashed: hashed.to_string(),
+            })
+            .ok_or(Error::PwdWithSchemeFailedParse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn fx_content(pwd: &str) -> EncryptContent {
+        EncryptContent {
+            content: pwd.to_string(),
+            salt: "f05e8961-0e5e-4b76-a6f1-bc1ee7e0e3d8".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_scheme_ok() -> Result<()> {
+        let fx_old_pwd = hash_for_scheme("01", &fx_content("welcome"))?;
+        let fx_new_pwd = hash_pwd(fx_content("welcome")).await?;
+        assert!(fx_new_pwd.starts_with("#02#$argon2id$"));
+
+        assert_eq!(
+            validate_pwd(fx_content("welcome"), fx_old_pwd.clone()).await?,
+            SchemeStatus::Outdated
+        );
+        assert_eq!(
+            validate_pwd(fx_content("welcome"), fx_new_pwd.clone()).await?,
+            SchemeStatus::Ok
+        );
+        assert!(matches!(
+            validate_pwd(fx_content("welcome!"), fx_old_pwd).await,
+            Err(Error::PwdNotMatching)
+        ));
+        assert!(matches!(
+            validate_pwd(fx_content("welcome!"), fx_new_pwd).await,
+            Err(Error::PwdNotMatching)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_pwd_bad_scheme() {
+        assert!(matches!(
+            validate_pwd(fx_content("welcome"), "#99#abc".to_string()).await,
+            Err(Error::SchemeNotFound(_))
+        ));
+        assert!(matches!(
+            validate_pwd(fx_content("welcome"), "plain".to_string()).await,
+            Err(Error::PwdWithSchemeFailedParse)
+        ));
+    }
+}
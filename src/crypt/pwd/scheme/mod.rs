@@ -0,0 +1,23 @@
+mod scheme_01;
+mod scheme_02;
+
+use crate::crypt::{EncryptContent, Error, Result};
+
+/// Scheme new passwords are hashed with.
+pub const DEFAULT_SCHEME: &str = "02";
+
+pub trait Scheme {
+    /// The hash, without the scheme prefix.
+    fn hash(&self, to_hash: &EncryptContent) -> Result<String>;
+
+    /// Fails with `Error::PwdNotMatching` unless `to_hash` hashes to `pwd_ref`.
+    fn validate(&self, to_hash: &EncryptContent, pwd_ref: &str) -> Result<()>;
+}
+
+pub fn get_scheme(scheme_name: &str) -> Result<Box<dyn Scheme>> {
+    match scheme_name {
+        "01" => Ok(Box::new(scheme_01::Scheme01)),
+        "02" => Ok(Box::new(scheme_02::Scheme02)),
+        _ => Err(Error::SchemeNotFound(scheme_name.to_string())),
+    }
+}
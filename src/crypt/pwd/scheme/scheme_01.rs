@@ -0,0 +1,21 @@
+use super::Scheme;
+use crate::config;
+use crate::crypt::{EncryptContent, Error, Result, encrypt_into_b64u};
+
+/// HMAC-SHA512 keyed with the server's `SERVICE_PWD_KEY`. Only kept to validate the passwords
+/// stored before `02`, anyone holding the key can brute-force them quickly.
+pub struct Scheme01;
+
+impl Scheme for Scheme01 {
+    fn hash(&self, to_hash: &EncryptContent) -> Result<String> {
+        encrypt_into_b64u(&config().PWD_KEY, to_hash)
+    }
+
+    fn validate(&self, to_hash: &EncryptContent, pwd_ref: &str) -> Result<()> {
+        if self.hash(to_hash)? == pwd_ref {
+            Ok(())
+        } else {
+            Err(Error::PwdNotMatching)
+        }
+    }
+}
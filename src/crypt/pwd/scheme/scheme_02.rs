@@ -0,0 +1,42 @@
+use super::Scheme;
+use crate::config;
+use crate::crypt::{EncryptContent, Error, Result};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+
+/// Argon2id with the default parameters (19 MiB, 2 passes), the user's salt and
+/// `SERVICE_PWD_KEY` as secret. Stored as a PHC string, e.g. `$argon2id$v=19$m=19456,...`.
+pub struct Scheme02;
+
+impl Scheme for Scheme02 {
+    fn hash(&self, to_hash: &EncryptContent) -> Result<String> {
+        let argon2 = argon2()?;
+        let salt =
+            SaltString::encode_b64(to_hash.salt.as_bytes()).map_err(|_| Error::PwdSaltInvalid)?;
+
+        let hashed = argon2
+            .hash_password(to_hash.content.as_bytes(), &salt)
+            .map_err(|_| Error::PwdFailHash)?;
+
+        Ok(hashed.to_string())
+    }
+
+    fn validate(&self, to_hash: &EncryptContent, pwd_ref: &str) -> Result<()> {
+        let argon2 = argon2()?;
+        let pwd_ref = PasswordHash::new(pwd_ref).map_err(|_| Error::PwdWithSchemeFailedParse)?;
+
+        argon2
+            .verify_password(to_hash.content.as_bytes(), &pwd_ref)
+            .map_err(|_| Error::PwdNotMatching)
+    }
+}
+
+fn argon2() -> Result<Argon2<'static>> {
+    Argon2::new_with_secret(
+        &config().PWD_KEY,
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::default(),
+    )
+    .map_err(|_| Error::KeyFailArgon2)
+}
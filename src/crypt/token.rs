@@ -109,7 +109,7 @@ mod tests {
 
     #[test]
     fn test_token_display_ok() -> Result<()> {
-        let fx_token_str = "";
+        let fx_token_str = "ZngtaWRlbnQtMDE.MjAyMy0wNS0xN1QxNTozMDowMFo.some-sign-b64u-encoded";
         let fx_token = Token {
             ident: "fx-ident-01".to_string(),
             exp: "2023-05-17T15:30:00Z".to_string(),
@@ -117,14 +117,13 @@ mod tests {
         };
 
         assert_eq!(fx_token.to_string(), fx_token_str);
-        println!("->> {fx_token}"); //TODO: remove after filling in fx_token_str
 
         Ok(())
     }
 
     #[test]
     fn test_token_from_str_ok() -> Result<()> {
-        let fx_token_str = "";
+        let fx_token_str = "ZngtaWRlbnQtMDE.MjAyMy0wNS0xN1QxNTozMDowMFo.some-sign-b64u-encoded";
         let fx_token = Token {
             ident: "fx-ident-01".to_string(),
             exp: "2023-05-17T15:30:00Z".to_string(),
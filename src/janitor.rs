@@ -0,0 +1,268 @@
+//! Background task reconciling the stored files with the database. Crashes and failed deletes
+//! can leave behind staged uploads, reference counts ahead of the attachments, and stored files
+//! no record points to. Every `SERVICE_JANITOR_INTERVAL_SEC` the janitor removes those older than
+//! `SERVICE_JANITOR_GRACE_SEC`, so uploads in progress are never touched, and logs a report.
+
+use crate::config;
+use crate::ctx::Ctx;
+use crate::model::blob::{BlobBmc, StoredBlob};
+use crate::model::quarantine::QuarantineBmc;
+use crate::model::upload_session::UploadSessionBmc;
+use crate::model::{self, ModelManager};
+use crate::web::staging::staging_path;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// What a run removed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct JanitorReport {
+    pub expired_sessions: usize,
+    pub staged_files: usize,
+    pub ref_counts_fixed: u64,
+    pub purged_blobs: usize,
+    pub orphan_files: usize,
+    pub freed_bytes: u64,
+}
+
+/// Runs the janitor every `SERVICE_JANITOR_INTERVAL_SEC`, starting one interval after boot.
+pub fn spawn(mm: ModelManager) {
+    let period = std::time::Duration::from_secs(config().JANITOR_INTERVAL_SEC.max(1));
+    let grace = Duration::seconds(config().JANITOR_GRACE_SEC as i64);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let report = run(&mm, grace).await;
+            if report == JanitorReport::default() {
+                debug!("{:<12} - nothing to remove", "JANITOR");
+            } else {
+                info!("{:<12} - {report:?}", "JANITOR");
+            }
+        }
+    });
+}
+
+/// One pass. Each step logs its failures and lets the next ones run.
+pub async fn run(mm: &ModelManager, grace: Duration) -> JanitorReport {
+    let ctx = Ctx::root_ctx();
+    let cutoff = Utc::now() - grace;
+    let mut report = JanitorReport::default();
+
+    // -- Expired upload sessions and their staged bytes
+    match UploadSessionBmc::delete_expired(&ctx, mm).await {
+        Ok(ids) => {
+            for id in &ids {
+                let _ = tokio::fs::remove_file(staging_path(*id)).await;
+                debug!("{:<12} - expired upload session {id}", "JANITOR");
+            }
+            report.expired_sessions = ids.len();
+        }
+        Err(err) => warn!(
+            "{:<12} - expiring upload sessions failed: {err:?}",
+            "JANITOR"
+        ),
+    }
+
+    // -- Staged files no live session owns, e.g. left by a crash mid upload
+    match UploadSessionBmc::list_ids(&ctx, mm).await {
+        Ok(live) => match list_staged(Path::new(&config().UPLOAD_STAGING_DIR)).await {
+            Ok(staged) => {
+                for (path, size) in orphan_staged(staged, &live, cutoff) {
+                    match tokio::fs::remove_file(&path).await {
+                        Ok(()) => {
+                            debug!("{:<12} - removed staged file {path}", "JANITOR");
+                            report.staged_files += 1;
+                            report.freed_bytes += size;
+                        }
+                        Err(err) => warn!("{:<12} - removing {path} failed: {err}", "JANITOR"),
+                    }
+                }
+            }
+            Err(err) => warn!("{:<12} - listing staged files failed: {err}", "JANITOR"),
+        },
+        Err(err) => warn!(
+            "{:<12} - listing upload sessions failed: {err:?}",
+            "JANITOR"
+        ),
+    }
+
+    // -- Reference counts, then the contents left without references
+    let grace_sec = grace.num_seconds() as f64;
+    match BlobBmc::reconcile_ref_counts(&ctx, mm, grace_sec).await {
+        Ok(fixed) => report.ref_counts_fixed = fixed,
+        Err(err) => warn!("{:<12} - reconciling ref counts failed: {err:?}", "JANITOR"),
+    }
+    match BlobBmc::list_unreferenced(&ctx, mm).await {
+        Ok(blobs) => {
+            for blob in blobs {
+                let sha256 = &blob.sha256;
+                match BlobBmc::purge(&ctx, mm, sha256).await {
+                    Ok(true) => {
+                        debug!("{:<12} - purged unreferenced blob {sha256}", "JANITOR");
+                        report.purged_blobs += 1;
+                        report.freed_bytes += blob.size_bytes.max(0) as u64;
+                    }
+                    Ok(false) => {}
+                    Err(err) => warn!("{:<12} - purging {sha256} failed: {err:?}", "JANITOR"),
+                }
+            }
+        }
+        Err(err) => warn!(
+            "{:<12} - listing unreferenced blobs failed: {err:?}",
+            "JANITOR"
+        ),
+    }
+
+    // -- Stored files no record points to, quarantined files included
+    // Listed before the keys, so a file stored in between is seen as referenced
+    let store = mm.blob_store();
+    match store.list().await {
+        Ok(stored) => match referenced_keys(&ctx, mm).await {
+            Ok(referenced) => {
+                for blob in orphan_blobs(stored, &referenced, cutoff) {
+                    match store.delete(&blob.key).await {
+                        Ok(()) => {
+                            debug!("{:<12} - removed orphan file {}", "JANITOR", blob.key);
+                            report.orphan_files += 1;
+                            report.freed_bytes += blob.size_bytes;
+                        }
+                        Err(err) => {
+                            warn!("{:<12} - removing {} failed: {err:?}", "JANITOR", blob.key)
+                        }
+                    }
+                }
+            }
+            Err(err) => warn!("{:<12} - listing blob keys failed: {err:?}", "JANITOR"),
+        },
+        Err(err) => warn!("{:<12} - listing stored files failed: {err:?}", "JANITOR"),
+    }
+
+    report
+}
+
+async fn referenced_keys(ctx: &Ctx, mm: &ModelManager) -> model::Result<HashSet<String>> {
+    let mut keys = BlobBmc::list_keys(ctx, mm).await?;
+    keys.extend(QuarantineBmc::list_keys(ctx, mm).await?);
+
+    Ok(keys)
+}
+
+/// Stored files older than `cutoff` that no record references.
+fn orphan_blobs(
+    stored: Vec<StoredBlob>,
+    referenced: &HashSet<String>,
+    cutoff: DateTime<Utc>,
+) -> Vec<StoredBlob> {
+    stored
+        .into_iter()
+        .filter(|blob| blob.modified_at < cutoff && !referenced.contains(&blob.key))
+        .collect()
+}
+
+/// A staged file, its path and size in bytes, with its last modification.
+type Staged = (String, u64, DateTime<Utc>);
+
+/// Staged files older than `cutoff` whose name is not the id of a live session, the files of
+/// direct uploads included.
+fn orphan_staged(
+    staged: Vec<Staged>,
+    live: &HashSet<Uuid>,
+    cutoff: DateTime<Utc>,
+) -> Vec<(String, u64)> {
+    staged
+        .into_iter()
+        .filter(|(path, _, modified_at)| {
+            let session = Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Uuid::parse_str(stem).ok());
+            *modified_at < cutoff && !session.is_some_and(|id| live.contains(&id))
+        })
+        .map(|(path, size, _)| (path, size))
+        .collect()
+}
+
+async fn list_staged(dir: &Path) -> std::io::Result<Vec<Staged>> {
+    let mut staged = Vec::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(staged),
+        Err(err) => return Err(err),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            staged.push((
+                entry.path().to_string_lossy().into_owned(),
+                metadata.len(),
+                metadata.modified()?.into(),
+            ));
+        }
+    }
+
+    Ok(staged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orphan_blobs() {
+        let now = Utc::now();
+        let cutoff = now - Duration::hours(1);
+        let fx_blob = |key: &str, modified_at| StoredBlob {
+            key: key.to_string(),
+            size_bytes: 3,
+            modified_at,
+        };
+        let referenced = HashSet::from(["blobs/3f/3f.png".to_string()]);
+
+        let orphans = orphan_blobs(
+            vec![
+                fx_blob("blobs/3f/3f.png", now - Duration::days(2)),
+                fx_blob("images/old.png", now - Duration::days(2)),
+                fx_blob("blobs/a0/a0.pdf", now),
+            ],
+            &referenced,
+            cutoff,
+        );
+
+        let keys: Vec<&str> = orphans.iter().map(|blob| blob.key.as_str()).collect();
+        assert_eq!(keys, ["images/old.png"]);
+    }
+
+    #[test]
+    fn test_orphan_staged() {
+        let now = Utc::now();
+        let cutoff = now - Duration::hours(1);
+        let old = now - Duration::days(2);
+        let fx_live = Uuid::new_v4();
+        let fx_dead = Uuid::new_v4();
+        let live = HashSet::from([fx_live]);
+
+        let orphans = orphan_staged(
+            vec![
+                (format!("staging/{fx_live}.part"), 1, old),
+                (format!("staging/{fx_dead}.part"), 2, old),
+                ("staging/stray.tmp".to_string(), 3, old),
+                (format!("staging/{}.part", Uuid::new_v4()), 4, now),
+            ],
+            &live,
+            cutoff,
+        );
+
+        assert_eq!(
+            orphans,
+            [
+                (format!("staging/{fx_dead}.part"), 2),
+                ("staging/stray.tmp".to_string(), 3)
+            ]
+        );
+    }
+}
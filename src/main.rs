@@ -1,15 +1,20 @@
 use crate::ctx::Ctx;
 use crate::model::ModelManager;
+use crate::web::download_attachments::download_attachment;
 use crate::web::middleware::auth::{mw_ctx_require, mw_ctx_resolve};
 use crate::web::middleware::res_map::mw_response_map;
 use crate::web::routes::{login::routes, r#static};
 use crate::web::rpc;
-use crate::web::upload_images::upload_image;
+use crate::web::upload_attachments::{upload_attachment, upload_body_limit};
+use crate::web::upload_sessions::{
+    UPLOAD_OFFSET, create_upload_session, delete_upload_session, finalize_upload_session,
+    get_upload_session, put_upload_chunk,
+};
 use crate::web::websockets::ws_handler;
-use axum::routing::get_service;
+use axum::extract::DefaultBodyLimit;
 use axum::{
     Router,
-    http::{HeaderValue, Method},
+    http::{HeaderName, HeaderValue, Method},
     middleware::{from_fn, from_fn_with_state},
     routing::{get, post},
     serve,
@@ -19,7 +24,6 @@ use tokio::net::TcpListener;
 use tower::ServiceBuilder;
 use tower_cookies::CookieManagerLayer;
 use tower_http::cors::CorsLayer;
-use tower_http::services::ServeDir;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
@@ -28,7 +32,9 @@ mod config;
 mod crypt;
 mod ctx;
 mod error;
+mod janitor;
 mod log;
+mod media;
 mod model;
 mod utils;
 pub mod web;
@@ -59,11 +65,22 @@ async fn main() -> Result<()> {
         mm: mm.clone().into(),
     };
 
+    janitor::spawn(mm.clone());
+
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
         .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers([axum::http::header::CONTENT_TYPE]);
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([
+            axum::http::header::CONTENT_TYPE,
+            HeaderName::from_static(UPLOAD_OFFSET),
+        ]);
 
     let login_routes = routes(state.mm.clone());
 
@@ -73,17 +90,34 @@ async fn main() -> Result<()> {
             .layer(CookieManagerLayer::new()),
     );
 
-    let images = Router::new()
-        .nest_service("/uploads", get_service(ServeDir::new("uploads")))
+    let attachments = Router::new()
+        .route(
+            "/uploads/attachments/:id/:file_name",
+            get(download_attachment),
+        )
+        .route("/uploads/sessions", post(create_upload_session))
+        .route(
+            "/uploads/sessions/:id",
+            get(get_upload_session)
+                .put(put_upload_chunk)
+                .delete(delete_upload_session),
+        )
+        .route(
+            "/uploads/sessions/:id/finalize",
+            post(finalize_upload_session),
+        )
         .with_state(state.clone())
         .layer(
             ServiceBuilder::new()
-                .layer(from_fn_with_state(state.mm.clone(), mw_ctx_resolve))
+                .layer(from_fn_with_state(state.mm.clone(), mw_ctx_require))
                 .layer(CookieManagerLayer::new()),
         );
 
-    let image_uploads = Router::new()
-        .route("/upload_image", post(upload_image))
+    // `/upload_image` predates generic attachments, kept for older clients
+    let attachment_uploads = Router::new()
+        .route("/upload_attachment", post(upload_attachment))
+        .route("/upload_image", post(upload_attachment))
+        .layer(DefaultBodyLimit::max(upload_body_limit()))
         .with_state(state.clone())
         .layer(
             ServiceBuilder::new()
@@ -104,8 +138,8 @@ async fn main() -> Result<()> {
         .merge(login_routes)
         .nest("/api", routes_rpc)
         .merge(ws_route)
-        .merge(image_uploads)
-        .merge(images)
+        .merge(attachment_uploads)
+        .merge(attachments)
         .layer(from_fn(mw_response_map))
         .layer(from_fn_with_state(state.mm.clone(), mw_ctx_resolve))
         .layer(CookieManagerLayer::new())
@@ -0,0 +1,223 @@
+//! Kind detection of uploaded files from their content.
+
+use super::{ALLOWED_IMAGE_FORMATS, Error, Result};
+use crate::model::attachment::AttachmentKind;
+use image::ImageFormat;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedFile {
+    pub kind: AttachmentKind,
+    pub content_type: &'static str,
+    /// Extension of the stored file.
+    pub extension: &'static str,
+    pub image_format: Option<ImageFormat>,
+}
+
+/// Leading bytes of the accepted non-image formats.
+const SIGNATURES: [(&[u8], AttachmentKind, &str, &str); 9] = [
+    (b"%PDF-", AttachmentKind::Document, "application/pdf", "pdf"),
+    (
+        b"PK\x03\x04",
+        AttachmentKind::Archive,
+        "application/zip",
+        "zip",
+    ),
+    // Empty zip
+    (
+        b"PK\x05\x06",
+        AttachmentKind::Archive,
+        "application/zip",
+        "zip",
+    ),
+    (
+        b"\x1f\x8b",
+        AttachmentKind::Archive,
+        "application/gzip",
+        "gz",
+    ),
+    (
+        b"BZh",
+        AttachmentKind::Archive,
+        "application/x-bzip2",
+        "bz2",
+    ),
+    (
+        b"\xfd7zXZ\x00",
+        AttachmentKind::Archive,
+        "application/x-xz",
+        "xz",
+    ),
+    (
+        b"\x28\xb5\x2f\xfd",
+        AttachmentKind::Archive,
+        "application/zstd",
+        "zst",
+    ),
+    (
+        b"7z\xbc\xaf\x27\x1c",
+        AttachmentKind::Archive,
+        "application/x-7z-compressed",
+        "7z",
+    ),
+    (
+        b"Rar!\x1a\x07",
+        AttachmentKind::Archive,
+        "application/vnd.rar",
+        "rar",
+    ),
+];
+
+/// Tar has no leading magic, its marker sits in the first header block.
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Leading bytes of a file enough to detect its kind, tar's marker included.
+pub const DETECT_HEAD_BYTES: usize = 8 * 1024;
+
+/// Detects the kind of a file from its content, failing unless it is an allow-listed image,
+/// a PDF, an archive or UTF-8 text.
+pub fn detect_file(bytes: &[u8]) -> Result<DetectedFile> {
+    let guessed_image = image::guess_format(bytes).ok();
+    if let Some(format) = guessed_image
+        && ALLOWED_IMAGE_FORMATS.contains(&format)
+    {
+        return Ok(DetectedFile {
+            kind: AttachmentKind::Image,
+            content_type: format.to_mime_type(),
+            extension: format.extensions_str().first().copied().unwrap_or("bin"),
+            image_format: Some(format),
+        });
+    }
+
+    let signature = SIGNATURES
+        .iter()
+        .find(|(magic, ..)| bytes.starts_with(magic))
+        .map(|(_, kind, content_type, extension)| (*kind, *content_type, *extension));
+    let tar = bytes
+        .get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len())
+        .is_some_and(|magic| magic == TAR_MAGIC)
+        .then_some((AttachmentKind::Archive, "application/x-tar", "tar"));
+    let text = is_text(bytes).then_some((AttachmentKind::Text, TEXT_CONTENT_TYPE, "txt"));
+
+    match signature.or(tar).or(text) {
+        Some((kind, content_type, extension)) => Ok(DetectedFile {
+            kind,
+            content_type,
+            extension,
+            image_format: None,
+        }),
+        None => Err(Error::UnsupportedFormat {
+            detected: guessed_image.map(|format| format.to_mime_type().to_string()),
+        }),
+    }
+}
+
+/// `detect_file` on the first bytes of a larger file, a UTF-8 sequence cut at the end of
+/// `head` does not make the file binary.
+pub fn detect_file_head(head: &[u8]) -> Result<DetectedFile> {
+    let head = match std::str::from_utf8(head) {
+        Err(err) if err.error_len().is_none() => &head[..err.valid_up_to()],
+        _ => head,
+    };
+    detect_file(head)
+}
+
+/// The accepted MIME types, for error reporting.
+pub fn allowed_types() -> Vec<&'static str> {
+    let mut types: Vec<&'static str> = ALLOWED_IMAGE_FORMATS
+        .iter()
+        .map(|format| format.to_mime_type())
+        .chain(
+            SIGNATURES
+                .iter()
+                .map(|(_, _, content_type, _)| *content_type),
+        )
+        .chain(["application/x-tar", TEXT_CONTENT_TYPE])
+        .collect();
+    types.dedup();
+    types
+}
+
+/// Non-empty UTF-8 without control characters other than whitespace and the escape used by
+/// terminal colours in logs.
+fn is_text(bytes: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return false;
+    };
+
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t' | '\x0c' | '\x1b'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_file_by_content() {
+        let fx_cases: [(&[u8], AttachmentKind, &str); 5] = [
+            (b"%PDF-1.7\n", AttachmentKind::Document, "application/pdf"),
+            (
+                b"PK\x03\x04rest",
+                AttachmentKind::Archive,
+                "application/zip",
+            ),
+            (
+                b"\x1f\x8b\x08\x00",
+                AttachmentKind::Archive,
+                "application/gzip",
+            ),
+            (
+                b"2024-01-01 \x1b[31mERROR\x1b[0m boom\r\n",
+                AttachmentKind::Text,
+                TEXT_CONTENT_TYPE,
+            ),
+            ("héllo".as_bytes(), AttachmentKind::Text, TEXT_CONTENT_TYPE),
+        ];
+
+        for (bytes, kind, content_type) in fx_cases {
+            let file = detect_file(bytes).unwrap();
+            assert_eq!((file.kind, file.content_type), (kind, content_type));
+            assert_eq!(file.image_format, None);
+        }
+    }
+
+    #[test]
+    fn test_detect_file_tar() {
+        let mut fx_tar = vec![0u8; 512];
+        fx_tar[..5].copy_from_slice(b"a.log");
+        fx_tar[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 5].copy_from_slice(TAR_MAGIC);
+
+        assert_eq!(detect_file(&fx_tar).unwrap().extension, "tar");
+    }
+
+    #[test]
+    fn test_detect_file_head_ignores_cut_characters() {
+        let fx_head = &"log: été".as_bytes()[..9];
+
+        assert!(detect_file(fx_head).is_err());
+        assert_eq!(
+            detect_file_head(fx_head).unwrap().kind,
+            AttachmentKind::Text
+        );
+    }
+
+    #[test]
+    fn test_detect_file_rejects_unknown_binaries() {
+        for fx_bytes in [
+            &b"MZ\x90\x00\x03"[..],
+            b"",
+            b"\x00\x01\x02",
+            b"BM\x00\x00\x00\x00",
+        ] {
+            assert!(
+                matches!(detect_file(fx_bytes), Err(Error::UnsupportedFormat { .. })),
+                "{fx_bytes:?} should be rejected"
+            );
+        }
+    }
+}
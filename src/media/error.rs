@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Clone, Debug, Serialize)]
+pub enum Error {
+    // Limits
+    TooLarge { max_bytes: usize },
+    DimensionsTooLarge { max_dimension: u32 },
+
+    // Content
+    UnsupportedFormat { detected: Option<String> },
+    Undecodable(String),
+
+    // Renditions
+    Encode(String),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{self:?}")
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,239 @@
+//! Validation and processing of uploaded files. Nothing here trusts the client: the kind is
+//! sniffed from the leading bytes and images are fully decoded before they are accepted.
+
+pub mod detect;
+mod error;
+pub mod sanitize;
+pub mod scan;
+pub mod thumbnail;
+
+pub use self::detect::{
+    DETECT_HEAD_BYTES, DetectedFile, allowed_types, detect_file, detect_file_head,
+};
+pub use self::error::{Error, Result};
+
+use crate::config;
+use crate::model::attachment::AttachmentKind;
+use image::{DynamicImage, ImageError, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+
+/// Image formats accepted for upload.
+pub const ALLOWED_IMAGE_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct UploadLimits {
+    pub max_image_bytes: usize,
+    pub max_document_bytes: usize,
+    pub max_text_bytes: usize,
+    pub max_archive_bytes: usize,
+    /// Largest accepted image width or height, in pixels.
+    pub max_image_dimension: u32,
+}
+
+impl UploadLimits {
+    pub fn from_config() -> Self {
+        let config = config();
+        Self {
+            max_image_bytes: config.UPLOAD_MAX_IMAGE_BYTES,
+            max_document_bytes: config.UPLOAD_MAX_DOCUMENT_BYTES,
+            max_text_bytes: config.UPLOAD_MAX_TEXT_BYTES,
+            max_archive_bytes: config.UPLOAD_MAX_ARCHIVE_BYTES,
+            max_image_dimension: config.UPLOAD_MAX_IMAGE_DIMENSION,
+        }
+    }
+
+    pub fn max_bytes(&self, kind: AttachmentKind) -> usize {
+        match kind {
+            AttachmentKind::Image => self.max_image_bytes,
+            AttachmentKind::Document => self.max_document_bytes,
+            AttachmentKind::Text => self.max_text_bytes,
+            AttachmentKind::Archive => self.max_archive_bytes,
+        }
+    }
+
+    /// What an upload may weigh before its kind is known.
+    pub fn max_upload_bytes(&self) -> usize {
+        [
+            self.max_image_bytes,
+            self.max_document_bytes,
+            self.max_text_bytes,
+            self.max_archive_bytes,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or_default()
+    }
+}
+
+/// Whether files of `kind` go through `process_upload`, which holds them in memory. Other
+/// kinds are recognized from their first bytes and stored as received.
+pub fn is_inspected(kind: AttachmentKind) -> bool {
+    matches!(kind, AttachmentKind::Image | AttachmentKind::Text)
+}
+
+/// An upload ready to be stored.
+#[derive(Debug)]
+pub struct ProcessedUpload {
+    pub file: DetectedFile,
+    /// The bytes to store, stripped of their metadata for images.
+    pub bytes: Vec<u8>,
+    pub sanitized: bool,
+    pub image: Option<ProcessedImage>,
+}
+
+#[derive(Debug)]
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub previews: thumbnail::Previews,
+}
+
+/// Detects the kind of the upload, enforces its size limit and, for images, decodes them,
+/// builds the previews and strips the metadata.
+///
+/// Decoding and encoding are CPU bound, call it from a blocking task.
+pub fn process_upload(
+    bytes: Vec<u8>,
+    limits: &UploadLimits,
+    keep_icc: bool,
+) -> Result<ProcessedUpload> {
+    let file = detect_file(&bytes)?;
+    let max_bytes = limits.max_bytes(file.kind);
+    if bytes.len() > max_bytes {
+        return Err(Error::TooLarge { max_bytes });
+    }
+
+    let Some(format) = file.image_format else {
+        return Ok(ProcessedUpload {
+            file,
+            bytes,
+            sanitized: false,
+            image: None,
+        });
+    };
+
+    let image = validate_image(&bytes, format, limits.max_image_dimension)?;
+    let previews = thumbnail::generate_previews(&image.decoded)?;
+    let bytes = sanitize::strip_metadata(bytes, format, keep_icc)?;
+
+    Ok(ProcessedUpload {
+        file,
+        bytes,
+        sanitized: true,
+        image: Some(ProcessedImage {
+            width: image.width,
+            height: image.height,
+            previews,
+        }),
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidatedImage {
+    pub width: u32,
+    pub height: u32,
+    pub decoded: DynamicImage,
+}
+
+/// Decodes the whole image, failing when it is corrupt or larger than `max_dimension`.
+pub fn validate_image(
+    bytes: &[u8],
+    format: ImageFormat,
+    max_dimension: u32,
+) -> Result<ValidatedImage> {
+    let mut decode_limits = Limits::default();
+    decode_limits.max_image_width = Some(max_dimension);
+    decode_limits.max_image_height = Some(max_dimension);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(decode_limits);
+    let image = reader.decode().map_err(|err| match err {
+        ImageError::Limits(_) => Error::DimensionsTooLarge { max_dimension },
+        err => Error::Undecodable(err.to_string()),
+    })?;
+
+    Ok(ValidatedImage {
+        width: image.width(),
+        height: image.height(),
+        decoded: image,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    const FX_LIMITS: UploadLimits = UploadLimits {
+        max_image_bytes: 1024 * 1024,
+        max_document_bytes: 1024 * 1024,
+        max_text_bytes: 16,
+        max_archive_bytes: 1024 * 1024,
+        max_image_dimension: 64,
+    };
+
+    fn fx_image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut bytes, format)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_process_upload_image_ok() {
+        let fx_png = fx_image(16, 8, ImageFormat::Png);
+
+        let upload = process_upload(fx_png, &FX_LIMITS, true).unwrap();
+
+        assert_eq!(upload.file.kind, AttachmentKind::Image);
+        assert_eq!(upload.file.content_type, "image/png");
+        assert_eq!(upload.file.extension, "png");
+        assert!(upload.sanitized);
+        let image = upload.image.unwrap();
+        assert_eq!((image.width, image.height), (16, 8));
+    }
+
+    #[test]
+    fn test_process_upload_text_ok() {
+        let upload = process_upload(b"INFO started\n".to_vec(), &FX_LIMITS, true).unwrap();
+
+        assert_eq!(upload.file.kind, AttachmentKind::Text);
+        assert!(!upload.sanitized);
+        assert!(upload.image.is_none());
+        assert_eq!(upload.bytes, b"INFO started\n");
+    }
+
+    #[test]
+    fn test_process_upload_rejects_corrupt_images() {
+        let mut fx_truncated = fx_image(16, 16, ImageFormat::Png);
+        fx_truncated.truncate(40);
+
+        let res = process_upload(fx_truncated, &FX_LIMITS, true);
+
+        assert!(matches!(res, Err(Error::Undecodable(_))));
+    }
+
+    #[test]
+    fn test_process_upload_limits() {
+        let res = process_upload(fx_image(65, 1, ImageFormat::Png), &FX_LIMITS, true);
+        assert!(matches!(
+            res,
+            Err(Error::DimensionsTooLarge { max_dimension: 64 })
+        ));
+
+        let res = process_upload(
+            b"a log line longer than 16 bytes".to_vec(),
+            &FX_LIMITS,
+            true,
+        );
+        assert!(matches!(res, Err(Error::TooLarge { max_bytes: 16 })));
+
+        assert_eq!(FX_LIMITS.max_upload_bytes(), 1024 * 1024);
+    }
+}
@@ -0,0 +1,254 @@
+//! Metadata stripping for uploaded images.
+//!
+//! Only the containers are rewritten, the pixel data is copied as is so stripping never costs
+//! quality. EXIF, XMP, text comments and vendor blocks are dropped; the ICC colour profile is
+//! kept when asked for since without it colours shift on wide-gamut photos.
+
+use super::{Error, Result};
+use bytes::Bytes;
+use image::ImageFormat;
+use img_parts::jpeg::{Jpeg, markers};
+use img_parts::png::Png;
+use img_parts::webp::WebP;
+use img_parts::{ImageEXIF, ImageICC};
+
+const JFIF_PREFIX: &[u8] = b"JFIF\0";
+const ICC_PREFIX: &[u8] = b"ICC_PROFILE\0";
+/// Adobe segment, it tells decoders how to interpret CMYK and YCCK data.
+const ADOBE_PREFIX: &[u8] = b"Adobe";
+
+/// PNG chunks carrying text, EXIF or timestamps.
+const PNG_METADATA_CHUNKS: [[u8; 4]; 5] = [*b"eXIf", *b"tEXt", *b"zTXt", *b"iTXt", *b"tIME"];
+const PNG_ICC_CHUNK: [u8; 4] = *b"iCCP";
+const WEBP_XMP_CHUNK: [u8; 4] = *b"XMP ";
+
+/// GIF application extensions needed for playback (looping).
+const GIF_ANIMATION_APPS: [&[u8; 11]; 2] = [b"NETSCAPE2.0", b"ANIMEXTS1.0"];
+
+/// Returns `bytes` without its metadata. `bytes` must already be a valid `format` image.
+pub fn strip_metadata(bytes: Vec<u8>, format: ImageFormat, keep_icc: bool) -> Result<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => strip_jpeg(bytes, keep_icc),
+        ImageFormat::Png => strip_png(bytes, keep_icc),
+        ImageFormat::WebP => strip_webp(bytes, keep_icc),
+        ImageFormat::Gif => strip_gif(&bytes),
+        format => Err(Error::UnsupportedFormat {
+            detected: Some(format.to_mime_type().to_string()),
+        }),
+    }
+}
+
+fn strip_jpeg(bytes: Vec<u8>, keep_icc: bool) -> Result<Vec<u8>> {
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(bytes)).map_err(malformed)?;
+
+    jpeg.segments_mut().retain(|segment| {
+        let contents = segment.contents();
+        match segment.marker() {
+            markers::APP0 => contents.starts_with(JFIF_PREFIX),
+            markers::APP2 => keep_icc && contents.starts_with(ICC_PREFIX),
+            markers::APP14 => contents.starts_with(ADOBE_PREFIX),
+            markers::APP1..=markers::APP15 | markers::COM => false,
+            _ => true,
+        }
+    });
+
+    Ok(jpeg.encoder().bytes().to_vec())
+}
+
+fn strip_png(bytes: Vec<u8>, keep_icc: bool) -> Result<Vec<u8>> {
+    let mut png = Png::from_bytes(Bytes::from(bytes)).map_err(malformed)?;
+
+    for kind in PNG_METADATA_CHUNKS {
+        png.remove_chunks_by_type(kind);
+    }
+    if !keep_icc {
+        png.remove_chunks_by_type(PNG_ICC_CHUNK);
+    }
+
+    Ok(png.encoder().bytes().to_vec())
+}
+
+fn strip_webp(bytes: Vec<u8>, keep_icc: bool) -> Result<Vec<u8>> {
+    let mut webp = WebP::from_bytes(Bytes::from(bytes)).map_err(malformed)?;
+
+    webp.remove_chunks_by_id(WEBP_XMP_CHUNK);
+    // The setters also rewrite the VP8X feature flags
+    webp.set_exif(None);
+    if !keep_icc {
+        webp.set_icc_profile(None);
+    }
+
+    Ok(webp.encoder().bytes().to_vec())
+}
+
+/// Copies the GIF block by block, leaving out comments and application extensions (where XMP
+/// lives) other than the animation ones, as well as anything after the trailer.
+fn strip_gif(bytes: &[u8]) -> Result<Vec<u8>> {
+    // Header (6 bytes) and logical screen descriptor (7 bytes)
+    let flags = *bytes.get(10).ok_or_else(truncated_gif)?;
+    let mut pos = 13 + color_table_len(flags);
+    let mut out = bytes.get(..pos).ok_or_else(truncated_gif)?.to_vec();
+
+    loop {
+        match *bytes.get(pos).ok_or_else(truncated_gif)? {
+            // Trailer
+            0x3B => {
+                out.push(0x3B);
+                return Ok(out);
+            }
+            // Extension: label, then data sub-blocks
+            0x21 => {
+                let label = *bytes.get(pos + 1).ok_or_else(truncated_gif)?;
+                let end = skip_sub_blocks(bytes, pos + 2)?;
+                let keep = match label {
+                    0xFE => false,
+                    0xFF => bytes
+                        .get(pos + 3..pos + 14)
+                        .is_some_and(|app| GIF_ANIMATION_APPS.iter().any(|a| a[..] == *app)),
+                    _ => true,
+                };
+                if keep {
+                    out.extend_from_slice(&bytes[pos..end]);
+                }
+                pos = end;
+            }
+            // Image descriptor (10 bytes), local colour table, LZW code size, data sub-blocks
+            0x2C => {
+                let flags = *bytes.get(pos + 9).ok_or_else(truncated_gif)?;
+                let data = pos + 10 + color_table_len(flags);
+                let end = skip_sub_blocks(bytes, data + 1)?;
+                out.extend_from_slice(&bytes[pos..end]);
+                pos = end;
+            }
+            block => {
+                return Err(Error::Undecodable(format!(
+                    "unexpected GIF block {block:#04x}"
+                )));
+            }
+        }
+    }
+}
+
+fn color_table_len(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+/// Returns the position after the sub-block chain starting at `pos`.
+fn skip_sub_blocks(bytes: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let len = *bytes.get(pos).ok_or_else(truncated_gif)? as usize;
+        pos += 1 + len;
+        if len == 0 {
+            return Ok(pos);
+        }
+    }
+}
+
+fn truncated_gif() -> Error {
+    Error::Undecodable("truncated GIF".to_string())
+}
+
+fn malformed(err: img_parts::Error) -> Error {
+    Error::Undecodable(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+    use img_parts::jpeg::JpegSegment;
+    use img_parts::png::PngChunk;
+    use std::io::Cursor;
+
+    const FX_SECRET: &[u8] = b"GPS 48.8584 N 2.2945 E";
+
+    fn fx_image(format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(8, 8))
+            .write_to(&mut bytes, format)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn assert_clean(stripped: &[u8], format: ImageFormat) {
+        assert!(
+            !contains(stripped, FX_SECRET),
+            "metadata left in {format:?}"
+        );
+        image::load_from_memory_with_format(stripped, format).unwrap();
+    }
+
+    #[test]
+    fn test_strip_jpeg_keeps_icc_when_asked() {
+        let mut jpeg = Jpeg::from_bytes(fx_image(ImageFormat::Jpeg).into()).unwrap();
+        let exif = [b"Exif\0\0".as_slice(), FX_SECRET].concat();
+        let xmp = [b"http://ns.adobe.com/xap/1.0/\0".as_slice(), FX_SECRET].concat();
+        let icc = [ICC_PREFIX, &[1, 1], b"profile"].concat();
+        for (marker, contents) in [
+            (markers::APP1, exif),
+            (markers::APP1, xmp),
+            (markers::COM, FX_SECRET.to_vec()),
+            (markers::APP2, icc),
+        ] {
+            let segment = JpegSegment::new_with_contents(marker, contents.into());
+            jpeg.segments_mut().insert(1, segment);
+        }
+        let fx_jpeg = jpeg.encoder().bytes().to_vec();
+
+        let stripped = strip_metadata(fx_jpeg.clone(), ImageFormat::Jpeg, true).unwrap();
+        assert_clean(&stripped, ImageFormat::Jpeg);
+        assert!(contains(&stripped, b"profile"));
+
+        let stripped = strip_metadata(fx_jpeg, ImageFormat::Jpeg, false).unwrap();
+        assert!(!contains(&stripped, b"profile"));
+    }
+
+    #[test]
+    fn test_strip_png_ok() {
+        let mut png = Png::from_bytes(fx_image(ImageFormat::Png).into()).unwrap();
+        let text = [b"Comment\0".as_slice(), FX_SECRET].concat();
+        png.chunks_mut()
+            .insert(1, PngChunk::new(*b"tEXt", text.into()));
+        png.chunks_mut()
+            .insert(1, PngChunk::new(*b"eXIf", FX_SECRET.to_vec().into()));
+        let fx_png = png.encoder().bytes().to_vec();
+
+        let stripped = strip_metadata(fx_png, ImageFormat::Png, true).unwrap();
+
+        assert_clean(&stripped, ImageFormat::Png);
+    }
+
+    #[test]
+    fn test_strip_gif_ok() {
+        let mut fx_gif = fx_image(ImageFormat::Gif);
+        let trailer = fx_gif.pop();
+        assert_eq!(trailer, Some(0x3B));
+        fx_gif.extend_from_slice(&[0x21, 0xFE, FX_SECRET.len() as u8]);
+        fx_gif.extend_from_slice(FX_SECRET);
+        fx_gif.extend_from_slice(&[0x00, 0x3B]);
+        // Data smuggled after the trailer
+        fx_gif.extend_from_slice(FX_SECRET);
+
+        let stripped = strip_metadata(fx_gif, ImageFormat::Gif, true).unwrap();
+
+        assert_clean(&stripped, ImageFormat::Gif);
+    }
+
+    #[test]
+    fn test_strip_gif_truncated_err() {
+        let mut fx_gif = fx_image(ImageFormat::Gif);
+        fx_gif.truncate(fx_gif.len() - 4);
+
+        let res = strip_metadata(fx_gif, ImageFormat::Gif, true);
+
+        assert!(matches!(res, Err(Error::Undecodable(_))));
+    }
+}
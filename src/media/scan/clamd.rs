@@ -0,0 +1,181 @@
+use super::{ContentScanner, Error, Result, ScanVerdict};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+/// Bytes sent per `INSTREAM` chunk.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Longest a scan may take, large archives included.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Scans with a clamd daemon, or anything speaking its protocol, streaming the file over
+/// `INSTREAM`.
+pub struct ClamdScanner {
+    addr: ClamdAddr,
+}
+
+enum ClamdAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl ClamdScanner {
+    /// `addr` is a `host:port`, or the path of a unix socket when it starts with `/`.
+    pub fn new(addr: &str) -> Self {
+        let addr = if addr.starts_with('/') {
+            ClamdAddr::Unix(PathBuf::from(addr))
+        } else {
+            ClamdAddr::Tcp(addr.to_string())
+        };
+
+        Self { addr }
+    }
+}
+
+#[async_trait]
+impl ContentScanner for ClamdScanner {
+    async fn scan(&self, path: &Path) -> Result<ScanVerdict> {
+        let scan = async {
+            match &self.addr {
+                ClamdAddr::Tcp(addr) => {
+                    let stream = TcpStream::connect(addr).await.map_err(io_error)?;
+                    instream(stream, path).await
+                }
+                ClamdAddr::Unix(socket) => {
+                    let stream = UnixStream::connect(socket).await.map_err(io_error)?;
+                    instream(stream, path).await
+                }
+            }
+        };
+
+        tokio::time::timeout(SCAN_TIMEOUT, scan)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+}
+
+/// Sends the file as length-prefixed chunks ended by an empty one, then reads the verdict.
+async fn instream<S>(mut stream: S, path: &Path) -> Result<ScanVerdict>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await.map_err(io_error)?;
+
+    let mut file = File::open(path).await.map_err(io_error)?;
+    let mut buf = vec![0; CHUNK_BYTES];
+    loop {
+        let read = file.read(&mut buf).await.map_err(io_error)?;
+        stream
+            .write_all(&(read as u32).to_be_bytes())
+            .await
+            .map_err(io_error)?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&buf[..read]).await.map_err(io_error)?;
+    }
+    stream.flush().await.map_err(io_error)?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.map_err(io_error)?;
+
+    parse_reply(&String::from_utf8_lossy(&reply))
+}
+
+/// `stream: OK` or `stream: <signature> FOUND`, anything else (size limit, errors) is not a
+/// verdict.
+fn parse_reply(reply: &str) -> Result<ScanVerdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected {
+            signature: signature.to_string(),
+        })
+    } else {
+        Err(Error::Unexpected {
+            reply: reply.to_string(),
+        })
+    }
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::Io(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Answers like clamd, flagging any stream that contains `needle`.
+    async fn fx_clamd(needle: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut command = [0; 10];
+                socket.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+
+                let mut content = Vec::new();
+                loop {
+                    let len = socket.read_u32().await.unwrap() as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0; len];
+                    socket.read_exact(&mut chunk).await.unwrap();
+                    content.extend(chunk);
+                }
+
+                let found = content.windows(needle.len()).any(|w| w == needle);
+                let reply: &[u8] = if found {
+                    b"stream: Eicar-Test-Signature FOUND\0"
+                } else {
+                    b"stream: OK\0"
+                };
+                socket.write_all(reply).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_clamd_scan_verdicts() {
+        let scanner = ClamdScanner::new(&fx_clamd(b"EICAR").await);
+        let dir = std::env::temp_dir().join(format!("clamd-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let clean = dir.join("clean.txt");
+        let infected = dir.join("infected.txt");
+        std::fs::write(&clean, vec![b'a'; CHUNK_BYTES + 10]).unwrap();
+        std::fs::write(&infected, b"X5O!P%@AP...EICAR-STANDARD-ANTIVIRUS-TEST-FILE").unwrap();
+
+        assert_eq!(scanner.scan(&clean).await.unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            scanner.scan(&infected).await.unwrap(),
+            ScanVerdict::Infected {
+                signature: "Eicar-Test-Signature".to_string()
+            }
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert!(matches!(
+            parse_reply("stream: INSTREAM size limit exceeded. ERROR\0"),
+            Err(Error::Unexpected { .. })
+        ));
+        assert_eq!(parse_reply("stream: OK\n").unwrap(), ScanVerdict::Clean);
+    }
+}
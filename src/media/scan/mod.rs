@@ -0,0 +1,49 @@
+//! Malware scanning of uploads, before they are stored and attached. The configured
+//! `ContentScanner` is a clamd daemon or a no-op, a file it flags is quarantined instead.
+
+mod clamd;
+mod error;
+
+pub use self::clamd::ClamdScanner;
+pub use self::error::{Error, Result};
+
+use crate::config::{ScannerConfig, config};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum ScanVerdict {
+    Clean,
+    /// Flagged by the scanner, `signature` names what it matched.
+    Infected {
+        signature: String,
+    },
+}
+
+#[async_trait]
+pub trait ContentScanner: Send + Sync {
+    /// Scans the local file at `path`. Fails when the scanner cannot give a verdict, the
+    /// caller must then reject the file rather than let it through.
+    async fn scan(&self, path: &Path) -> Result<ScanVerdict>;
+}
+
+/// Lets every file through, for deployments without a scanner.
+pub struct NoopScanner;
+
+#[async_trait]
+impl ContentScanner for NoopScanner {
+    async fn scan(&self, _path: &Path) -> Result<ScanVerdict> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+/// Builds the scanner selected by `SERVICE_UPLOAD_SCANNER`.
+pub fn new_content_scanner() -> Arc<dyn ContentScanner> {
+    match &config().UPLOAD_SCANNER {
+        ScannerConfig::None => Arc::new(NoopScanner),
+        ScannerConfig::Clamd { addr } => Arc::new(ClamdScanner::new(addr)),
+    }
+}
@@ -0,0 +1,115 @@
+//! Resized WebP renditions and blurhash placeholders of uploaded images.
+
+use super::{Error, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::io::Cursor;
+
+/// Longest side of the thumbnail shown inline in the message list.
+pub const THUMBNAIL_SIZE: u32 = 256;
+/// Longest side of the preview shown when the image is opened.
+pub const PREVIEW_SIZE: u32 = 1024;
+pub const RENDITION_CONTENT_TYPE: &str = "image/webp";
+
+/// Blurhash components, 4x3 is the usual choice for landscape photos.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+/// Side of the tiny image the blurhash is computed from, it only keeps low frequencies.
+const BLURHASH_SOURCE_SIZE: u32 = 32;
+
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    /// WebP encoded image.
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Previews {
+    pub thumbnail: Rendition,
+    pub preview: Rendition,
+    pub blurhash: String,
+}
+
+/// Builds the thumbnail, the preview and the blurhash of a decoded image.
+///
+/// Resizing and encoding are CPU bound, call it from a blocking task.
+pub fn generate_previews(image: &DynamicImage) -> Result<Previews> {
+    Ok(Previews {
+        thumbnail: webp_rendition(image, THUMBNAIL_SIZE)?,
+        preview: webp_rendition(image, PREVIEW_SIZE)?,
+        blurhash: blurhash(image)?,
+    })
+}
+
+/// Scales the image down to fit `max_size` (never up) and encodes it as WebP.
+pub fn webp_rendition(image: &DynamicImage, max_size: u32) -> Result<Rendition> {
+    let resized = fit_within(image, max_size);
+    // The WebP encoder only takes 8-bit RGB(A)
+    let resized = if resized.color().has_alpha() {
+        DynamicImage::ImageRgba8(resized.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(resized.to_rgb8())
+    };
+
+    let mut bytes = Cursor::new(Vec::new());
+    resized
+        .write_to(&mut bytes, ImageFormat::WebP)
+        .map_err(|err| Error::Encode(err.to_string()))?;
+
+    Ok(Rendition {
+        bytes: bytes.into_inner(),
+    })
+}
+
+pub fn blurhash(image: &DynamicImage) -> Result<String> {
+    let small = fit_within(image, BLURHASH_SOURCE_SIZE).to_rgba8();
+    let (components_x, components_y) = BLURHASH_COMPONENTS;
+
+    blurhash::encode(
+        components_x,
+        components_y,
+        small.width(),
+        small.height(),
+        small.as_raw(),
+    )
+    .map_err(|err| Error::Encode(err.to_string()))
+}
+
+fn fit_within(image: &DynamicImage, max_size: u32) -> DynamicImage {
+    if image.width() <= max_size && image.height() <= max_size {
+        image.clone()
+    } else {
+        image.resize(max_size, max_size, FilterType::Lanczos3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    fn dimensions(rendition: &Rendition) -> (u32, u32) {
+        image::load_from_memory_with_format(&rendition.bytes, ImageFormat::WebP)
+            .unwrap()
+            .dimensions()
+    }
+
+    #[test]
+    fn test_generate_previews_ok() {
+        let fx_image = DynamicImage::ImageRgba8(RgbaImage::new(2048, 512));
+
+        let previews = generate_previews(&fx_image).unwrap();
+
+        assert_eq!(dimensions(&previews.thumbnail), (256, 64));
+        assert_eq!(dimensions(&previews.preview), (1024, 256));
+        assert!(!previews.blurhash.is_empty());
+    }
+
+    #[test]
+    fn test_webp_rendition_never_upscales() {
+        let fx_image = DynamicImage::ImageRgba8(RgbaImage::new(100, 40));
+
+        let rendition = webp_rendition(&fx_image, PREVIEW_SIZE).unwrap();
+
+        assert_eq!(dimensions(&rendition), (100, 40));
+    }
+}
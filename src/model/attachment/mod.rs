@@ -0,0 +1,331 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::blob::BlobBmc;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use uuid::Uuid;
+
+mod usage;
+
+pub use self::usage::{QuotaScope, RoomUploads, StorageUsage};
+
+type UtcDateTime = DateTime<Utc>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "attachment_kind", rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Image,
+    /// PDF documents.
+    Document,
+    /// UTF-8 text such as logs, served as `text/plain`.
+    Text,
+    /// Zip, tar and compressed files.
+    Archive,
+}
+
+/// A file attached to a message. Serializes with the fields of its `details`, tagged by
+/// `kind`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub message_id: i64,
+    pub user_id: i64,
+    /// Name of the file on the uploader's machine.
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub storage_key: String,
+    /// Hex SHA-256 of the stored file.
+    pub sha256: String,
+    /// Whether metadata (EXIF, XMP, ...) was stripped from the stored file.
+    pub sanitized: bool,
+    pub uploaded_at: UtcDateTime,
+    #[serde(flatten)]
+    pub details: AttachmentDetails,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AttachmentDetails {
+    Image(ImageDetails),
+    Document,
+    Text,
+    Archive,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImageDetails {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+    /// WebP, at most 256px on its longest side.
+    pub thumbnail_key: Option<String>,
+    /// WebP, at most 1024px on its longest side.
+    pub preview_key: Option<String>,
+}
+
+impl AttachmentDetails {
+    pub fn kind(&self) -> AttachmentKind {
+        match self {
+            Self::Image(_) => AttachmentKind::Image,
+            Self::Document => AttachmentKind::Document,
+            Self::Text => AttachmentKind::Text,
+            Self::Archive => AttachmentKind::Archive,
+        }
+    }
+}
+
+impl Attachment {
+    /// The keys of the stored file and of its renditions.
+    pub fn storage_keys(&self) -> Vec<&str> {
+        let mut keys = vec![self.storage_key.as_str()];
+        if let AttachmentDetails::Image(image) = &self.details {
+            keys.extend(
+                [&image.thumbnail_key, &image.preview_key]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str),
+            );
+        }
+        keys
+    }
+}
+
+impl FromRow<'_, PgRow> for Attachment {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let details = match row.try_get("kind")? {
+            AttachmentKind::Image => AttachmentDetails::Image(ImageDetails {
+                width: row.try_get("width")?,
+                height: row.try_get("height")?,
+                blurhash: row.try_get("blurhash")?,
+                thumbnail_key: row.try_get("thumbnail_key")?,
+                preview_key: row.try_get("preview_key")?,
+            }),
+            AttachmentKind::Document => AttachmentDetails::Document,
+            AttachmentKind::Text => AttachmentDetails::Text,
+            AttachmentKind::Archive => AttachmentDetails::Archive,
+        };
+
+        Ok(Self {
+            id: row.try_get("id")?,
+            message_id: row.try_get("message_id")?,
+            user_id: row.try_get("user_id")?,
+            filename: row.try_get("filename")?,
+            content_type: row.try_get("content_type")?,
+            size_bytes: row.try_get("size_bytes")?,
+            storage_key: row.try_get("storage_key")?,
+            sha256: row.try_get("sha256")?,
+            sanitized: row.try_get("sanitized")?,
+            uploaded_at: row.try_get("uploaded_at")?,
+            details,
+        })
+    }
+}
+
+pub struct AttachmentForCreate {
+    pub id: Uuid,
+    pub message_id: i64,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub storage_key: String,
+    pub sha256: String,
+    pub sanitized: bool,
+    pub details: AttachmentDetails,
+}
+
+pub struct AttachmentBmc;
+
+impl DbBmc for AttachmentBmc {
+    const TABLE: &'static str = "attachments";
+}
+
+/// The attachment columns, also selected by the message listing.
+pub const ATTACHMENT_COLUMNS: &str = "id, message_id, user_id, kind, filename, content_type, \
+                                      size_bytes, storage_key, sha256, width, height, \
+                                      blurhash, thumbnail_key, preview_key, sanitized, uploaded_at";
+
+impl AttachmentBmc {
+    /// Records an attachment of the ctx user, failing with `Error::StorageQuotaExceeded` when
+    /// it does not fit in the quotas of the user or of the room.
+    pub async fn create(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        attachment_c: AttachmentForCreate,
+    ) -> Result<Attachment> {
+        let AttachmentForCreate {
+            id,
+            message_id,
+            filename,
+            content_type,
+            size_bytes,
+            storage_key,
+            sha256,
+            sanitized,
+            details,
+        } = attachment_c;
+        let image = match &details {
+            AttachmentDetails::Image(image) => image.clone(),
+            _ => ImageDetails::default(),
+        };
+
+        let mut tx = mm.db().begin().await?;
+        usage::require_quota_in_tx(&mut tx, ctx.user_id(), message_id, size_bytes).await?;
+
+        let attachment = sqlx::query_as::<_, Attachment>(&format!(
+            r#"
+            INSERT INTO attachments (id, message_id, user_id, kind, filename, content_type,
+                                     size_bytes, storage_key, sha256, width, height,
+                                     blurhash, thumbnail_key, preview_key, sanitized)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+            RETURNING {ATTACHMENT_COLUMNS}
+            "#
+        ))
+        .bind(id)
+        .bind(message_id)
+        .bind(ctx.user_id())
+        .bind(details.kind())
+        .bind(filename)
+        .bind(content_type)
+        .bind(size_bytes)
+        .bind(storage_key)
+        .bind(sha256)
+        .bind(image.width)
+        .bind(image.height)
+        .bind(image.blurhash)
+        .bind(image.thumbnail_key)
+        .bind(image.preview_key)
+        .bind(sanitized)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(attachment)
+    }
+
+    pub async fn get(_ctx: &Ctx, mm: &ModelManager, id: Uuid) -> Result<Attachment> {
+        sqlx::query_as::<_, Attachment>(&format!(
+            "SELECT {ATTACHMENT_COLUMNS} FROM attachments WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::AttachmentNotFound { id })
+    }
+
+    /// Deletes the attachments of a message and releases their content. Called before the
+    /// message is deleted, as the cascade would not update the reference counts.
+    pub async fn delete_by_message(ctx: &Ctx, mm: &ModelManager, message_id: i64) -> Result<()> {
+        Self::delete_for_messages(ctx, mm, "SELECT $1::BIGINT", message_id).await
+    }
+
+    pub async fn delete_by_room(ctx: &Ctx, mm: &ModelManager, room_id: i64) -> Result<()> {
+        Self::delete_for_messages(
+            ctx,
+            mm,
+            "SELECT id FROM messages WHERE message_room_id = $1",
+            room_id,
+        )
+        .await
+    }
+
+    pub async fn delete_by_workspace(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+    ) -> Result<()> {
+        Self::delete_for_messages(
+            ctx,
+            mm,
+            "SELECT m.id FROM messages m JOIN rooms r ON r.id = m.message_room_id \
+             WHERE r.workspace_id = $1",
+            workspace_id,
+        )
+        .await
+    }
+
+    /// Deletes the attachments of the messages selected by `message_ids` (bound to `id`),
+    /// dropping their references in the same statement, and purges the content left
+    /// unreferenced.
+    async fn delete_for_messages(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        message_ids: &str,
+        id: i64,
+    ) -> Result<()> {
+        let released = sqlx::query_as::<_, (String, i32)>(&format!(
+            r#"
+            WITH deleted AS (
+                DELETE FROM attachments WHERE message_id IN ({message_ids})
+                RETURNING sha256
+            ), released AS (
+                SELECT sha256, COUNT(*)::INTEGER AS refs FROM deleted GROUP BY sha256
+            )
+            UPDATE blobs SET ref_count = blobs.ref_count - released.refs
+            FROM released
+            WHERE blobs.sha256 = released.sha256
+            RETURNING blobs.sha256, blobs.ref_count
+            "#
+        ))
+        .bind(id)
+        .fetch_all(mm.db())
+        .await?;
+
+        for (sha256, ref_count) in released {
+            // The attachments are gone either way, an unpurged blob only costs storage
+            if ref_count <= 0
+                && let Err(err) = BlobBmc::purge(ctx, mm, &sha256).await
+            {
+                tracing::warn!("Failed to purge blob {sha256}: {err}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fx_attachment(details: AttachmentDetails) -> Attachment {
+        Attachment {
+            id: Uuid::nil(),
+            message_id: 1,
+            user_id: 2,
+            filename: "a.bin".to_string(),
+            content_type: "application/octet-stream".to_string(),
+            size_bytes: 3,
+            storage_key: "attachments/a".to_string(),
+            sha256: String::new(),
+            sanitized: false,
+            uploaded_at: Utc::now(),
+            details,
+        }
+    }
+
+    #[test]
+    fn test_attachment_serializes_typed_details() {
+        let image = fx_attachment(AttachmentDetails::Image(ImageDetails {
+            width: Some(4),
+            thumbnail_key: Some("attachments/a_256.webp".to_string()),
+            ..Default::default()
+        }));
+        let value = serde_json::to_value(&image).unwrap();
+        assert_eq!(value["kind"], json!("image"));
+        assert_eq!(value["width"], json!(4));
+        assert_eq!(
+            image.storage_keys(),
+            vec!["attachments/a", "attachments/a_256.webp"]
+        );
+
+        let value = serde_json::to_value(fx_attachment(AttachmentDetails::Archive)).unwrap();
+        assert_eq!(value["kind"], json!("archive"));
+        assert!(value.get("width").is_none());
+    }
+}
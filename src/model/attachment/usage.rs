@@ -0,0 +1,356 @@
+use super::AttachmentBmc;
+use crate::config;
+use crate::ctx::Ctx;
+use crate::model::messages::MessageBmc;
+use crate::model::{Error, ModelManager, Result};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor, Postgres, Transaction};
+
+/// What a storage quota applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaScope {
+    /// Everything a user uploaded, in every room.
+    User,
+    /// Everything uploaded to a room, by every member.
+    Room,
+}
+
+/// Bytes used against a quota. Attachments count with the size of their file, even when the
+/// content is shared with other attachments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StorageUsage {
+    pub used_bytes: i64,
+    /// `None` when there is no quota.
+    pub quota_bytes: Option<i64>,
+}
+
+impl StorageUsage {
+    /// Builds the usage against the `override_bytes` quota of the user or room, falling back
+    /// to `default_bytes` where 0 means no quota.
+    fn new(used_bytes: i64, override_bytes: Option<i64>, default_bytes: i64) -> Self {
+        let quota_bytes = override_bytes.or((default_bytes > 0).then_some(default_bytes));
+        Self {
+            used_bytes,
+            quota_bytes,
+        }
+    }
+
+    pub fn remaining_bytes(&self) -> Option<i64> {
+        self.quota_bytes
+            .map(|quota| quota.saturating_sub(self.used_bytes).max(0))
+    }
+
+    /// Whether `size_bytes` more fit in the quota.
+    pub fn allows(&self, size_bytes: i64) -> bool {
+        self.remaining_bytes()
+            .is_none_or(|remaining| size_bytes <= remaining)
+    }
+}
+
+/// The ctx user's uploads in one room.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct RoomUploads {
+    pub room_id: i64,
+    pub room_title: String,
+    pub attachments: i64,
+    pub used_bytes: i64,
+}
+
+impl AttachmentBmc {
+    /// Bytes uploaded by `user_id`, against their quota.
+    pub async fn user_usage(_ctx: &Ctx, mm: &ModelManager, user_id: i64) -> Result<StorageUsage> {
+        user_usage(mm.db(), user_id).await
+    }
+
+    /// Bytes attached to the messages of `room_id`, against its quota.
+    pub async fn room_usage(_ctx: &Ctx, mm: &ModelManager, room_id: i64) -> Result<StorageUsage> {
+        room_usage(mm.db(), room_id).await
+    }
+
+    /// The ctx user's uploads grouped by room, largest first.
+    pub async fn list_uploads_by_room(ctx: &Ctx, mm: &ModelManager) -> Result<Vec<RoomUploads>> {
+        let rooms = sqlx::query_as::<_, RoomUploads>(
+            r#"
+            SELECT r.id AS room_id, r.title AS room_title, COUNT(a.id) AS attachments,
+                   SUM(a.size_bytes)::BIGINT AS used_bytes
+            FROM attachments a
+            JOIN messages m ON m.id = a.message_id
+            JOIN rooms r ON r.id = m.message_room_id
+            WHERE a.user_id = $1
+            GROUP BY r.id, r.title
+            ORDER BY used_bytes DESC, r.id
+            "#,
+        )
+        .bind(ctx.user_id())
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rooms)
+    }
+
+    /// Fails with `Error::StorageQuotaExceeded` when `size_bytes` more would take the ctx user
+    /// or the room of `message_id` over quota.
+    ///
+    /// Only an early check before the file is processed, `create` checks again when it
+    /// inserts the attachment.
+    pub async fn require_quota(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        message_id: i64,
+        size_bytes: i64,
+    ) -> Result<()> {
+        let (room_id, _) = MessageBmc::room_and_author(ctx, mm, message_id).await?;
+        let user = user_usage(mm.db(), ctx.user_id()).await?;
+        let room = room_usage(mm.db(), room_id).await?;
+
+        require_fits(ctx.user_id(), user, room_id, room, size_bytes)
+    }
+}
+
+/// Checks the quotas for an attachment inserted in `tx`. The user and room rows stay locked
+/// until `tx` ends, so uploads checked at the same time count each other.
+pub(super) async fn require_quota_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+    message_id: i64,
+    size_bytes: i64,
+) -> Result<()> {
+    // Locked user first, then room, the same order for every upload
+    sqlx::query("SELECT id FROM users WHERE id = $1 FOR NO KEY UPDATE")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let room_id = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT r.id FROM messages m JOIN rooms r ON r.id = m.message_room_id
+        WHERE m.id = $1
+        FOR NO KEY UPDATE OF r
+        "#,
+    )
+    .bind(message_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::EntityNotFound {
+        entity: "messages",
+        id: message_id,
+    })?;
+
+    let user = user_usage(&mut *tx, user_id).await?;
+    let room = room_usage(&mut *tx, room_id).await?;
+
+    require_fits(user_id, user, room_id, room, size_bytes)
+}
+
+fn require_fits(
+    user_id: i64,
+    user: StorageUsage,
+    room_id: i64,
+    room: StorageUsage,
+    size_bytes: i64,
+) -> Result<()> {
+    for (scope, id, usage) in [
+        (QuotaScope::User, user_id, user),
+        (QuotaScope::Room, room_id, room),
+    ] {
+        if !usage.allows(size_bytes)
+            && let Some(quota_bytes) = usage.quota_bytes
+        {
+            return Err(Error::StorageQuotaExceeded {
+                scope,
+                id,
+                used_bytes: usage.used_bytes,
+                quota_bytes,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+async fn user_usage<'e>(db: impl PgExecutor<'e>, user_id: i64) -> Result<StorageUsage> {
+    let (used_bytes, override_bytes) = sqlx::query_as::<_, (i64, Option<i64>)>(
+        r#"
+        SELECT COALESCE(SUM(a.size_bytes), 0)::BIGINT, u.storage_quota_bytes
+        FROM users u LEFT JOIN attachments a ON a.user_id = u.id
+        WHERE u.id = $1
+        GROUP BY u.id
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::EntityNotFound {
+        entity: "users",
+        id: user_id,
+    })?;
+
+    Ok(StorageUsage::new(
+        used_bytes,
+        override_bytes,
+        config().UPLOAD_QUOTA_USER_BYTES,
+    ))
+}
+
+async fn room_usage<'e>(db: impl PgExecutor<'e>, room_id: i64) -> Result<StorageUsage> {
+    let (used_bytes, override_bytes) = sqlx::query_as::<_, (i64, Option<i64>)>(
+        r#"
+        SELECT COALESCE(SUM(a.size_bytes), 0)::BIGINT, r.storage_quota_bytes
+        FROM rooms r
+        LEFT JOIN messages m ON m.message_room_id = r.id
+        LEFT JOIN attachments a ON a.message_id = m.id
+        WHERE r.id = $1
+        GROUP BY r.id
+        "#,
+    )
+    .bind(room_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or(Error::EntityNotFound {
+        entity: "rooms",
+        id: room_id,
+    })?;
+
+    Ok(StorageUsage::new(
+        used_bytes,
+        override_bytes,
+        config().UPLOAD_QUOTA_ROOM_BYTES,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::attachment::{AttachmentDetails, AttachmentForCreate};
+    use crate::model::blob::{BlobBmc, BlobForCreate, content_key};
+    use serial_test::serial;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_storage_usage_quota() {
+        // The default applies without an override, 0 meaning no quota
+        assert_eq!(StorageUsage::new(5, None, 10).quota_bytes, Some(10));
+        assert_eq!(StorageUsage::new(5, None, 0).quota_bytes, None);
+        assert_eq!(StorageUsage::new(5, Some(0), 10).quota_bytes, Some(0));
+
+        let usage = StorageUsage::new(7, Some(10), 0);
+        assert_eq!(usage.remaining_bytes(), Some(3));
+        assert!(usage.allows(3));
+        assert!(!usage.allows(4));
+
+        // Lowered below what is already used
+        let usage = StorageUsage::new(12, Some(10), 0);
+        assert_eq!(usage.remaining_bytes(), Some(0));
+        assert!(!usage.allows(1));
+
+        assert!(StorageUsage::new(i64::MAX, None, 0).allows(i64::MAX));
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_concurrent_uploads_within_quota() -> anyhow::Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let fx_user_id = _dev_utils::seed_user(&mm, "quota_racer").await?;
+        let fx_room_id = _dev_utils::seed_room(&mm, "quota_racer", "text").await?;
+        let (fx_message_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO messages (message_text, message_room_id, message_user_id)
+            VALUES ('files', $1, $2) RETURNING id
+            "#,
+        )
+        .bind(fx_room_id)
+        .bind(fx_user_id)
+        .fetch_one(mm.db())
+        .await?;
+        sqlx::query("UPDATE users SET storage_quota_bytes = 10 WHERE id = $1")
+            .bind(fx_user_id)
+            .execute(mm.db())
+            .await?;
+        let ctx = Ctx::new(fx_user_id)?;
+        let fx_sha256 = "8d777f385d3dfec8815d20f7496026dc";
+        let fx_storage_key = content_key(fx_sha256, ".txt");
+        for _ in 0..2 {
+            BlobBmc::acquire(
+                &ctx,
+                &mm,
+                BlobForCreate {
+                    sha256: fx_sha256.to_string(),
+                    storage_key: fx_storage_key.clone(),
+                    thumbnail_key: None,
+                    preview_key: None,
+                    size_bytes: 6,
+                },
+            )
+            .await?;
+        }
+        let fx_attachment_c = || AttachmentForCreate {
+            id: Uuid::new_v4(),
+            message_id: fx_message_id,
+            filename: "a.txt".to_string(),
+            content_type: "text/plain".to_string(),
+            size_bytes: 6,
+            storage_key: fx_storage_key.clone(),
+            sha256: fx_sha256.to_string(),
+            sanitized: false,
+            details: AttachmentDetails::Text,
+        };
+
+        // Execute
+        // An upload holding the quota locks until its attachment is in
+        let mut tx = mm.db().begin().await?;
+        require_quota_in_tx(&mut tx, fx_user_id, fx_message_id, 6).await?;
+        let racing = tokio::spawn({
+            let (ctx, mm, attachment_c) = (ctx.clone(), mm.clone(), fx_attachment_c());
+            async move { AttachmentBmc::create(&ctx, &mm, attachment_c).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        sqlx::query(
+            r#"
+            INSERT INTO attachments (id, message_id, user_id, kind, filename, content_type,
+                                     size_bytes, storage_key, sha256)
+            VALUES ($1, $2, $3, 'text', 'a.txt', 'text/plain', 6, $4, $5)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(fx_message_id)
+        .bind(fx_user_id)
+        .bind(&fx_storage_key)
+        .bind(fx_sha256)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        let res = racing.await?;
+
+        // Check
+        assert!(
+            matches!(
+                res,
+                Err(Error::StorageQuotaExceeded {
+                    scope: QuotaScope::User,
+                    ..
+                })
+            ),
+            "the second upload must count the first one, got {res:?}"
+        );
+        assert_eq!(
+            AttachmentBmc::user_usage(&ctx, &mm, fx_user_id)
+                .await?
+                .used_bytes,
+            6
+        );
+
+        // Clean
+        sqlx::query("DELETE FROM attachments WHERE sha256 = $1")
+            .bind(fx_sha256)
+            .execute(mm.db())
+            .await?;
+        sqlx::query("DELETE FROM blobs WHERE sha256 = $1")
+            .bind(fx_sha256)
+            .execute(mm.db())
+            .await?;
+
+        Ok(())
+    }
+}
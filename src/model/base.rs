@@ -68,24 +68,6 @@ where
     Ok(entity)
 }
 
-pub async fn list<MC, E>(_ctx: &Ctx, mm: &ModelManager) -> Result<Vec<E>>
-where
-    MC: DbBmc,
-    E: for<'r> FromRow<'r, PgRow> + Unpin + Send,
-    E: HasFields,
-{
-    let db = mm.db();
-
-    let entities: Vec<E> = sqlb::select()
-        .table(MC::TABLE)
-        .columns(E::field_names())
-        .order_by("id")
-        .fetch_all(db)
-        .await?;
-
-    Ok(entities)
-}
-
 pub async fn update<MC, E>(_ctx: &Ctx, mm: &ModelManager, id: i64, data: E) -> Result<()>
 where
     MC: DbBmc,
@@ -0,0 +1,21 @@
+use serde::Serialize;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Clone, Debug, Serialize)]
+pub enum Error {
+    KeyInvalid { key: String },
+    NotFound { key: String },
+
+    // -- Backends
+    Io(String),
+    S3(String),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{self:?}")
+    }
+}
+
+impl std::error::Error for Error {}
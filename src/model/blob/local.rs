@@ -0,0 +1,211 @@
+use super::{BlobStore, BlobStream, Error, Result, StoredBlob, validate_key};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// Stores blobs as files under a root directory, the key being the relative path.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> Result<()> {
+        let path = self.path(key)?;
+        let tmp = tmp_path(&path).await?;
+        let res = tokio::fs::write(&tmp, bytes).await;
+
+        commit(res, &tmp, &path).await
+    }
+
+    async fn put_file(&self, key: &str, src: &Path, _content_type: &str) -> Result<()> {
+        let path = self.path(key)?;
+        let tmp = tmp_path(&path).await?;
+        let res = tokio::fs::copy(src, &tmp).await.map(|_| ());
+
+        commit(res, &tmp, &path).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path(key)?)
+            .await
+            .map_err(|err| not_found_or_io(key, err))
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        match tokio::fs::metadata(self.path(key)?).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) => Err(not_found_or_io(key, err)),
+        }
+    }
+
+    async fn get_stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<BlobStream> {
+        let mut file = tokio::fs::File::open(self.path(key)?)
+            .await
+            .map_err(|err| not_found_or_io(key, err))?;
+        let stream: BlobStream = match range {
+            Some((start, end)) => {
+                file.seek(SeekFrom::Start(start)).await.map_err(io_error)?;
+                Box::pin(ReaderStream::new(file.take(end - start + 1)).map_err(io_error))
+            }
+            None => Box::pin(ReaderStream::new(file).map_err(io_error)),
+        };
+
+        Ok(stream)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(io_error(err)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredBlob>> {
+        let mut blobs = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(io_error(err)),
+            };
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                let metadata = entry.metadata().await.map_err(io_error)?;
+                let path = entry.path();
+                if metadata.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+
+                let Some(key) = path
+                    .strip_prefix(&self.root)
+                    .ok()
+                    .and_then(|rel| rel.to_str())
+                else {
+                    continue;
+                };
+                blobs.push(StoredBlob {
+                    key: key.replace(std::path::MAIN_SEPARATOR, "/"),
+                    size_bytes: metadata.len(),
+                    modified_at: metadata.modified().map_err(io_error)?.into(),
+                });
+            }
+        }
+
+        Ok(blobs)
+    }
+}
+
+/// A temporary sibling of `path`, creating the parent directories.
+async fn tmp_path(path: &Path) -> Result<PathBuf> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(io_error)?;
+    }
+    Ok(path.with_file_name(format!(".{}.tmp", Uuid::new_v4())))
+}
+
+/// Renames the written `tmp` file to `path` so readers never see a partial file.
+async fn commit(written: std::io::Result<()>, tmp: &Path, path: &Path) -> Result<()> {
+    let res = match written {
+        Ok(()) => tokio::fs::rename(tmp, path).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        let _ = tokio::fs::remove_file(tmp).await;
+        return Err(io_error(err));
+    }
+
+    Ok(())
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::Io(err.to_string())
+}
+
+fn not_found_or_io(key: &str, err: std::io::Error) -> Error {
+    if err.kind() == ErrorKind::NotFound {
+        Error::NotFound {
+            key: key.to_string(),
+        }
+    } else {
+        io_error(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_blob_store_roundtrip() {
+        let fx_root = std::env::temp_dir().join(format!("blob-test-{}", Uuid::new_v4()));
+        let store = LocalBlobStore::new(&fx_root);
+
+        store
+            .put("images/a.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        assert_eq!(store.get("images/a.png").await.unwrap(), b"png");
+        assert_eq!(store.size("images/a.png").await.unwrap(), 3);
+        let part: Vec<_> = store
+            .get_stream("images/a.png", Some((1, 2)))
+            .await
+            .unwrap()
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(part, b"ng");
+
+        store.delete("images/a.png").await.unwrap();
+        store.delete("images/a.png").await.unwrap();
+        assert!(matches!(
+            store.get("images/a.png").await,
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            store.size("images/a.png").await,
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            store.get("../a.png").await,
+            Err(Error::KeyInvalid { .. })
+        ));
+
+        let fx_src = fx_root.join("src.txt");
+        std::fs::write(&fx_src, b"text").unwrap();
+        store
+            .put_file("files/b.txt", &fx_src, "text/plain")
+            .await
+            .unwrap();
+        assert_eq!(store.get("files/b.txt").await.unwrap(), b"text");
+
+        let mut keys: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|blob| blob.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["files/b.txt", "src.txt"]);
+
+        let _ = std::fs::remove_dir_all(fx_root);
+    }
+}
@@ -0,0 +1,113 @@
+//! Storage of uploaded files. Rows reference files by a backend-agnostic key such as
+//! `blobs/3f/<sha256>.png`, the configured `BlobStore` maps keys to the local disk or a bucket.
+//! Keys are derived from the content, `BlobBmc` counts the attachments sharing each one.
+
+mod error;
+mod local;
+mod record;
+mod s3;
+
+pub use self::error::{Error, Result};
+pub use self::local::LocalBlobStore;
+pub use self::record::{Blob, BlobBmc, BlobForCreate, content_key};
+pub use self::s3::S3BlobStore;
+
+use crate::config::{BlobStoreConfig, config};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// The bytes of a blob, read as they are sent.
+pub type BlobStream = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores `bytes` under `key`, replacing any previous blob.
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()>;
+
+    /// Stores the content of the local file at `path` under `key`, streaming it rather than
+    /// loading it in memory.
+    async fn put_file(&self, key: &str, path: &Path, content_type: &str) -> Result<()>;
+
+    /// Fails with `Error::NotFound` when nothing is stored under `key`.
+    async fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// Size in bytes of the blob under `key`. Fails with `Error::NotFound` when nothing is
+    /// stored under it.
+    async fn size(&self, key: &str) -> Result<u64>;
+
+    /// Streams the blob under `key`, or only its bytes `start..=end` for `Some((start, end))`.
+    /// The range must lie within the blob.
+    async fn get_stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<BlobStream>;
+
+    /// Deleting a missing blob is not an error.
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Every stored blob, in no particular order.
+    async fn list(&self) -> Result<Vec<StoredBlob>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredBlob {
+    pub key: String,
+    pub size_bytes: u64,
+    pub modified_at: DateTime<Utc>,
+}
+
+/// Builds the store selected by `SERVICE_BLOB_STORE`.
+pub fn new_blob_store() -> Result<Arc<dyn BlobStore>> {
+    match &config().BLOB_STORE {
+        BlobStoreConfig::Local { root } => Ok(Arc::new(LocalBlobStore::new(root))),
+        BlobStoreConfig::S3(s3_config) => Ok(Arc::new(S3BlobStore::new(s3_config)?)),
+    }
+}
+
+/// Keys are relative, `/` separated paths without empty, `.` or `..` segments, so they map
+/// to the same object on every backend and cannot escape the local root.
+pub fn validate_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::KeyInvalid {
+            key: key.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("images/0b9e1a52-1c3a-4b8e-9a53-3c0c3f1c2a11_256.webp").is_ok());
+
+        for fx_key in [
+            "",
+            "/etc/passwd",
+            "images/../secret",
+            "images//a",
+            "a\\b",
+            "a b",
+        ] {
+            assert!(
+                validate_key(fx_key).is_err(),
+                "{fx_key:?} should be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::{ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashSet;
+type UtcDateTime = DateTime<Utc>;
+
+/// A stored file shared by every attachment with the same content, with its renditions for
+/// images. `ref_count` is the number of those attachments, the files go with the last one.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Blob {
+    /// Hex SHA-256 of the stored file.
+    pub sha256: String,
+    pub storage_key: String,
+    pub thumbnail_key: Option<String>,
+    pub preview_key: Option<String>,
+    pub size_bytes: i64,
+    pub ref_count: i32,
+    /// False until the files are in the blob store.
+    pub stored: bool,
+    pub created_at: UtcDateTime,
+}
+
+impl Blob {
+    /// The keys of the stored file and of its renditions.
+    pub fn keys(&self) -> Vec<&str> {
+        [
+            Some(&self.storage_key),
+            self.thumbnail_key.as_ref(),
+            self.preview_key.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect()
+    }
+}
+
+pub struct BlobForCreate {
+    pub sha256: String,
+    pub storage_key: String,
+    pub thumbnail_key: Option<String>,
+    pub preview_key: Option<String>,
+    pub size_bytes: i64,
+}
+
+/// Content-addressed key of a file derived from the content hashed `sha256`, e.g.
+/// `blobs/3f/3f2a...c1.png` for `suffix` `.png`. Keys are spread over 256 prefixes to keep
+/// local directories small.
+pub fn content_key(sha256: &str, suffix: &str) -> String {
+    let prefix = sha256.get(..2).unwrap_or("00");
+    format!("blobs/{prefix}/{sha256}{suffix}")
+}
+
+pub struct BlobBmc;
+
+impl DbBmc for BlobBmc {
+    const TABLE: &'static str = "blobs";
+}
+
+const BLOB_COLUMNS: &str =
+    "sha256, storage_key, thumbnail_key, preview_key, size_bytes, ref_count, stored, created_at";
+
+impl BlobBmc {
+    /// Takes a reference on the content, recording it when it is new. Until the blob is
+    /// `stored` the caller stores the files, then calls `mark_stored`, or `release` if that
+    /// fails. Concurrent uploads of a content not stored yet all store the same files.
+    pub async fn acquire(_ctx: &Ctx, mm: &ModelManager, blob_c: BlobForCreate) -> Result<Blob> {
+        let BlobForCreate {
+            sha256,
+            storage_key,
+            thumbnail_key,
+            preview_key,
+            size_bytes,
+        } = blob_c;
+
+        // Waits on a concurrent `purge` of the same content, then records it anew. A record
+        // left unreferenced, e.g. by a `purge` that failed halfway, may have lost files, so
+        // they are stored again
+        let blob = sqlx::query_as::<_, Blob>(&format!(
+            r#"
+            INSERT INTO blobs (sha256, storage_key, thumbnail_key, preview_key, size_bytes,
+                               ref_count)
+            VALUES ($1, $2, $3, $4, $5, 1)
+            ON CONFLICT (sha256) DO UPDATE
+            SET ref_count = blobs.ref_count + 1, acquired_at = now(),
+                stored = blobs.stored AND blobs.ref_count > 0
+            RETURNING {BLOB_COLUMNS}
+            "#
+        ))
+        .bind(sha256)
+        .bind(storage_key)
+        .bind(thumbnail_key)
+        .bind(preview_key)
+        .bind(size_bytes)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(blob)
+    }
+
+    /// Records that the files of an acquired content are in the blob store, later uploads
+    /// of it skip storing them.
+    pub async fn mark_stored(_ctx: &Ctx, mm: &ModelManager, sha256: &str) -> Result<()> {
+        sqlx::query("UPDATE blobs SET stored = true WHERE sha256 = $1")
+            .bind(sha256)
+            .execute(mm.db())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Drops a reference taken by `acquire`, removing the files with the last one.
+    pub async fn release(ctx: &Ctx, mm: &ModelManager, sha256: &str) -> Result<()> {
+        let ref_count = sqlx::query_scalar::<_, i32>(
+            "UPDATE blobs SET ref_count = ref_count - 1 WHERE sha256 = $1 RETURNING ref_count",
+        )
+        .bind(sha256)
+        .fetch_optional(mm.db())
+        .await?;
+
+        if ref_count.is_some_and(|count| count <= 0) {
+            Self::purge(ctx, mm, sha256).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the files and the record of an unreferenced content. Returns false when it is
+    /// referenced again or already gone.
+    ///
+    /// The record stays locked while the files are deleted, so a concurrent `acquire` of the
+    /// same content waits and then stores the files again.
+    pub async fn purge(_ctx: &Ctx, mm: &ModelManager, sha256: &str) -> Result<bool> {
+        let mut tx = mm.db().begin().await?;
+
+        let blob = sqlx::query_as::<_, Blob>(&format!(
+            "SELECT {BLOB_COLUMNS} FROM blobs WHERE sha256 = $1 AND ref_count <= 0 FOR UPDATE"
+        ))
+        .bind(sha256)
+        .fetch_optional(&mut tx)
+        .await?;
+        let Some(blob) = blob else {
+            return Ok(false);
+        };
+
+        for key in blob.keys() {
+            mm.blob_store().delete(key).await?;
+        }
+        sqlx::query("DELETE FROM blobs WHERE sha256 = $1")
+            .bind(sha256)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Resets the reference counts that drifted from the number of attachments, e.g. after a
+    /// crash between `acquire` and the attachment insert. Contents acquired in the last
+    /// `grace_sec` are left alone, their uploads may still be running. Returns the number of
+    /// counts fixed.
+    pub async fn reconcile_ref_counts(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        grace_sec: f64,
+    ) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            WITH counted AS (
+                SELECT b.sha256, COUNT(a.id)::INTEGER AS refs
+                FROM blobs b LEFT JOIN attachments a ON a.sha256 = b.sha256
+                WHERE b.acquired_at < now() - make_interval(secs => $1)
+                GROUP BY b.sha256
+            )
+            UPDATE blobs SET ref_count = counted.refs
+            FROM counted
+            WHERE blobs.sha256 = counted.sha256 AND blobs.ref_count <> counted.refs
+            "#,
+        )
+        .bind(grace_sec)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Contents left without references, normally purged right away.
+    pub async fn list_unreferenced(_ctx: &Ctx, mm: &ModelManager) -> Result<Vec<Blob>> {
+        let blobs = sqlx::query_as::<_, Blob>(&format!(
+            "SELECT {BLOB_COLUMNS} FROM blobs WHERE ref_count <= 0"
+        ))
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(blobs)
+    }
+
+    /// Every key a content refers to, files missing from it are orphans.
+    pub async fn list_keys(_ctx: &Ctx, mm: &ModelManager) -> Result<HashSet<String>> {
+        let rows = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+            "SELECT storage_key, thumbnail_key, preview_key FROM blobs",
+        )
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .flat_map(|(storage_key, thumbnail_key, preview_key)| {
+                [Some(storage_key), thumbnail_key, preview_key]
+            })
+            .flatten()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use anyhow::Result;
+    use serial_test::serial;
+
+    #[test]
+    fn test_content_key() {
+        let fx_sha256 = "3f2a0c1d";
+
+        assert_eq!(content_key(fx_sha256, ".png"), "blobs/3f/3f2a0c1d.png");
+        assert_eq!(
+            content_key(fx_sha256, "_256.webp"),
+            "blobs/3f/3f2a0c1d_256.webp"
+        );
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_acquire_until_stored() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let ctx = Ctx::root_ctx();
+        let fx_sha256 = "0b9e1a521c3a4b8e9a533c0c3f1c2a11";
+        let fx_blob_c = || BlobForCreate {
+            sha256: fx_sha256.to_string(),
+            storage_key: content_key(fx_sha256, ".txt"),
+            thumbnail_key: None,
+            preview_key: None,
+            size_bytes: 4,
+        };
+
+        // Execute
+        let first = BlobBmc::acquire(&ctx, &mm, fx_blob_c()).await?;
+        // Concurrent upload while the first one is still storing the files
+        let second = BlobBmc::acquire(&ctx, &mm, fx_blob_c()).await?;
+
+        // Check
+        assert!(!first.stored);
+        assert!(!second.stored, "the second upload must store the files too");
+        assert_eq!(second.ref_count, 2);
+
+        // Execute
+        BlobBmc::mark_stored(&ctx, &mm, fx_sha256).await?;
+        let third = BlobBmc::acquire(&ctx, &mm, fx_blob_c()).await?;
+
+        // Check
+        assert!(third.stored);
+        assert_eq!(third.ref_count, 3);
+
+        // Clean
+        for _ in 0..3 {
+            BlobBmc::release(&ctx, &mm, fx_sha256).await?;
+        }
+        assert!(BlobBmc::list_keys(&ctx, &mm).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_acquire_after_failed_purge() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let ctx = Ctx::root_ctx();
+        let fx_sha256 = "5d41402abc4b2a76b9719d911017c592";
+        let fx_blob_c = || BlobForCreate {
+            sha256: fx_sha256.to_string(),
+            storage_key: content_key(fx_sha256, ".txt"),
+            thumbnail_key: None,
+            preview_key: None,
+            size_bytes: 5,
+        };
+        BlobBmc::acquire(&ctx, &mm, fx_blob_c()).await?;
+        BlobBmc::mark_stored(&ctx, &mm, fx_sha256).await?;
+        // A purge that deleted some files then rolled back leaves the record unreferenced
+        sqlx::query("UPDATE blobs SET ref_count = 0 WHERE sha256 = $1")
+            .bind(fx_sha256)
+            .execute(mm.db())
+            .await?;
+
+        // Execute
+        let blob = BlobBmc::acquire(&ctx, &mm, fx_blob_c()).await?;
+
+        // Check
+        assert!(!blob.stored, "the files must be stored again");
+        assert_eq!(blob.ref_count, 1);
+
+        // Clean
+        BlobBmc::release(&ctx, &mm, fx_sha256).await?;
+
+        Ok(())
+    }
+}
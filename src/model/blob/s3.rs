@@ -0,0 +1,207 @@
+use super::{BlobStore, BlobStream, Error, Result, StoredBlob, validate_key};
+use crate::config::S3Config;
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures::TryStreamExt;
+use s3::command::Command;
+use s3::creds::Credentials;
+use s3::request::Request;
+use s3::request::tokio_backend::ReqwestRequest;
+use s3::{Bucket, Region};
+use std::path::Path;
+
+/// Stores blobs as objects of an S3-compatible bucket (AWS, MinIO, R2...), the key being the
+/// object key. Uses path-style URLs so custom endpoints work without wildcard DNS.
+pub struct S3BlobStore {
+    bucket: Box<Bucket>,
+}
+
+impl S3BlobStore {
+    pub fn new(s3_config: &S3Config) -> Result<Self> {
+        let region = Region::Custom {
+            region: s3_config.region.clone(),
+            endpoint: s3_config.endpoint.clone(),
+        };
+        let credentials = Credentials::new(
+            Some(&s3_config.access_key),
+            Some(&s3_config.secret_key),
+            None,
+            None,
+            None,
+        )
+        .map_err(|err| Error::S3(err.to_string()))?;
+        let bucket = Bucket::new(&s3_config.bucket, region, credentials)
+            .map_err(s3_error)?
+            .with_path_style();
+
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()> {
+        validate_key(key)?;
+        let res = self
+            .bucket
+            .put_object_with_content_type(key, &bytes, content_type)
+            .await
+            .map_err(s3_error)?;
+
+        check_status(key, res.status_code())
+    }
+
+    async fn put_file(&self, key: &str, path: &Path, content_type: &str) -> Result<()> {
+        validate_key(key)?;
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| Error::Io(err.to_string()))?;
+        // Sent as a multipart upload when larger than a part
+        let res = self
+            .bucket
+            .put_object_stream_with_content_type(&mut file, key, content_type)
+            .await
+            .map_err(s3_error)?;
+
+        check_status(key, res.status_code())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        validate_key(key)?;
+        let res = self.bucket.get_object(key).await.map_err(s3_error)?;
+        check_status(key, res.status_code())?;
+
+        Ok(res.to_vec())
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        validate_key(key)?;
+        let (head, status) = self.bucket.head_object(key).await.map_err(s3_error)?;
+        check_status(key, status)?;
+
+        head.content_length
+            .and_then(|len| u64::try_from(len).ok())
+            .ok_or_else(|| Error::S3(format!("missing content length for {key}")))
+    }
+
+    async fn get_stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<BlobStream> {
+        validate_key(key)?;
+        let res = match range {
+            // Sent with a `Range: bytes=start-end` header
+            Some((start, end)) => {
+                let command = Command::GetObjectRange {
+                    start,
+                    end: Some(end),
+                };
+                ReqwestRequest::new(&self.bucket, key, command)
+                    .await
+                    .map_err(s3_error)?
+                    .response_data_to_stream()
+                    .await
+            }
+            None => self.bucket.get_object_stream(key).await,
+        }
+        .map_err(s3_error)?;
+        check_status(key, res.status_code)?;
+
+        Ok(Box::pin(res.bytes.map_err(s3_error)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        validate_key(key)?;
+        let res = self.bucket.delete_object(key).await.map_err(s3_error)?;
+
+        match check_status(key, res.status_code()) {
+            Err(Error::NotFound { .. }) => Ok(()),
+            other => other,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredBlob>> {
+        let pages = self
+            .bucket
+            .list(String::new(), None)
+            .await
+            .map_err(s3_error)?;
+
+        pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| {
+                let modified_at = DateTime::parse_from_rfc3339(&object.last_modified)
+                    .map_err(|err| Error::S3(format!("{}: {err}", object.key)))?;
+                Ok(StoredBlob {
+                    key: object.key,
+                    size_bytes: object.size,
+                    modified_at: modified_at.into(),
+                })
+            })
+            .collect()
+    }
+}
+
+fn check_status(key: &str, status: u16) -> Result<()> {
+    match status {
+        200..=299 => Ok(()),
+        404 => Err(Error::NotFound {
+            key: key.to_string(),
+        }),
+        status => Err(Error::S3(format!("unexpected status {status} for {key}"))),
+    }
+}
+
+fn s3_error(err: s3::error::S3Error) -> Error {
+    Error::S3(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against a local MinIO-style server, e.g.
+    /// `docker run -p 9000:9000 minio/minio server /data` with a `test-blobs` bucket, then
+    /// `cargo test s3_blob_store -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_s3_blob_store_roundtrip() {
+        let env = |name: &str, default: &str| std::env::var(name).unwrap_or(default.to_string());
+        let fx_config = S3Config {
+            bucket: env("TEST_S3_BUCKET", "test-blobs"),
+            region: env("TEST_S3_REGION", "us-east-1"),
+            endpoint: env("TEST_S3_ENDPOINT", "http://localhost:9000"),
+            access_key: env("TEST_S3_ACCESS_KEY", "minioadmin"),
+            secret_key: env("TEST_S3_SECRET_KEY", "minioadmin"),
+        };
+        let store = S3BlobStore::new(&fx_config).unwrap();
+
+        store
+            .put("images/roundtrip.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        assert_eq!(store.get("images/roundtrip.png").await.unwrap(), b"png");
+        assert_eq!(store.size("images/roundtrip.png").await.unwrap(), 3);
+        let part: Vec<_> = store
+            .get_stream("images/roundtrip.png", Some((1, 2)))
+            .await
+            .unwrap()
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(part, b"ng");
+        assert!(
+            store
+                .list()
+                .await
+                .unwrap()
+                .iter()
+                .any(|blob| blob.key == "images/roundtrip.png")
+        );
+
+        store.delete("images/roundtrip.png").await.unwrap();
+        assert!(matches!(
+            store.get("images/roundtrip.png").await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+type UtcDateTime = DateTime<Utc>;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct BlockedUser {
+    pub user_id: i64,
+    pub username: String,
+    pub blocked_at: UtcDateTime,
+}
+
+pub struct UserBlockBmc;
+
+impl DbBmc for UserBlockBmc {
+    const TABLE: &'static str = "user_blocks";
+}
+
+impl UserBlockBmc {
+    pub async fn block(ctx: &Ctx, mm: &ModelManager, blocked_id: i64) -> Result<()> {
+        if blocked_id == ctx.user_id() {
+            return Err(Error::CannotBlockSelf);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_blocks (blocker_id, blocked_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(ctx.user_id())
+        .bind(blocked_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn unblock(ctx: &Ctx, mm: &ModelManager, blocked_id: i64) -> Result<()> {
+        let count = sqlb::delete()
+            .table(Self::TABLE)
+            .and_where("blocker_id", "=", ctx.user_id())
+            .and_where("blocked_id", "=", blocked_id)
+            .exec(mm.db())
+            .await?;
+
+        if count == 0 {
+            Err(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id: blocked_id,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn list_blocked(ctx: &Ctx, mm: &ModelManager) -> Result<Vec<BlockedUser>> {
+        let blocked = sqlx::query_as::<_, BlockedUser>(
+            r#"
+            SELECT u.id AS user_id, u.username, b.blocked_at
+            FROM user_blocks b
+            JOIN users u ON u.id = b.blocked_id
+            WHERE b.blocker_id = $1
+            ORDER BY b.blocked_at
+            "#,
+        )
+        .bind(ctx.user_id())
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(blocked)
+    }
+
+    pub async fn is_blocked(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        blocker_id: i64,
+        blocked_id: i64,
+    ) -> Result<bool> {
+        let (blocked,): (bool,) = sqlx::query_as(
+            "SELECT EXISTS (SELECT 1 FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2)",
+        )
+        .bind(blocker_id)
+        .bind(blocked_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(blocked)
+    }
+
+    /// Fails with `Error::UserBlocked` when `target_id` has blocked the ctx user.
+    pub async fn require_not_blocked_by(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        target_id: i64,
+    ) -> Result<()> {
+        if Self::is_blocked(ctx, mm, target_id, ctx.user_id()).await? {
+            Err(Error::UserBlocked { user_id: target_id })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the subset of `user_ids` that have blocked the ctx user.
+    pub async fn list_blockers_among(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        user_ids: &[i64],
+    ) -> Result<Vec<i64>> {
+        let blockers: Vec<(i64,)> = sqlx::query_as(
+            "SELECT blocker_id FROM user_blocks WHERE blocked_id = $1 AND blocker_id = ANY($2)",
+        )
+        .bind(ctx.user_id())
+        .bind(user_ids)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(blockers.into_iter().map(|(id,)| id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use anyhow::Result;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_block_unblock_ok() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let fx_blocker_id = _dev_utils::seed_user(&mm, "block_blocker").await?;
+        let fx_blocked_id = _dev_utils::seed_user(&mm, "block_blocked").await?;
+        let blocker_ctx = Ctx::new(fx_blocker_id)?;
+        let blocked_ctx = Ctx::new(fx_blocked_id)?;
+
+        // Execute
+        UserBlockBmc::block(&blocker_ctx, &mm, fx_blocked_id).await?;
+        // Blocking twice is a no-op
+        UserBlockBmc::block(&blocker_ctx, &mm, fx_blocked_id).await?;
+
+        // Check
+        let blocked = UserBlockBmc::list_blocked(&blocker_ctx, &mm).await?;
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].user_id, fx_blocked_id);
+        assert!(matches!(
+            UserBlockBmc::require_not_blocked_by(&blocked_ctx, &mm, fx_blocker_id).await,
+            Err(Error::UserBlocked { user_id }) if user_id == fx_blocker_id
+        ));
+        // Blocks are one way
+        UserBlockBmc::require_not_blocked_by(&blocker_ctx, &mm, fx_blocked_id).await?;
+        assert_eq!(
+            UserBlockBmc::list_blockers_among(&blocked_ctx, &mm, &[fx_blocker_id, fx_blocked_id])
+                .await?,
+            [fx_blocker_id]
+        );
+
+        // Execute
+        UserBlockBmc::unblock(&blocker_ctx, &mm, fx_blocked_id).await?;
+
+        // Check
+        UserBlockBmc::require_not_blocked_by(&blocked_ctx, &mm, fx_blocker_id).await?;
+        assert!(matches!(
+            UserBlockBmc::unblock(&blocker_ctx, &mm, fx_blocked_id).await,
+            Err(Error::EntityNotFound { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_block_self_err() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let fx_user_id = _dev_utils::seed_user(&mm, "block_self").await?;
+        let ctx = Ctx::new(fx_user_id)?;
+
+        // Execute
+        let res = UserBlockBmc::block(&ctx, &mm, fx_user_id).await;
+
+        // Check
+        assert!(matches!(res, Err(Error::CannotBlockSelf)));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 use crate::crypt;
-use crate::model::store;
+use crate::model::attachment::QuotaScope;
+use crate::model::permission::Permissions;
+use crate::model::room::RoomKind;
+use crate::model::{blob, store};
 use axum::body::Body;
 use axum::response::Response;
 use hyper::StatusCode;
@@ -17,11 +20,145 @@ pub enum Error {
         id: i64,
     },
     Store(store::Error),
+    Blob(blob::Error),
     TicketDeleteFailIdNotFound {
         id: u64,
     },
     Crypt(crypt::Error),
 
+    UsernameNotFound {
+        username: String,
+    },
+
+    // -- Rooms
+    RoomAccessDenied {
+        room_id: i64,
+    },
+    RoomInvalidVisibility {
+        visibility: String,
+    },
+    RoomMemberNotFound {
+        room_id: i64,
+        user_id: i64,
+    },
+    RoomCategoryInvalid {
+        category_id: i64,
+        workspace_id: i64,
+    },
+    RoomOrderInvalid {
+        expected: Vec<i64>,
+    },
+    RoomArchived {
+        room_id: i64,
+    },
+    RoomKindUnsupported {
+        room_id: i64,
+        kind: RoomKind,
+    },
+    RoomInvalidVoiceCapacity {
+        capacity: i32,
+    },
+    RoomInvalidSlowMode {
+        slow_mode_sec: i32,
+    },
+    RoomSlowMode {
+        room_id: i64,
+        retry_after_sec: i64,
+    },
+
+    // -- Workspaces
+    WorkspaceAccessDenied {
+        workspace_id: i64,
+    },
+    WorkspaceMemberNotFound {
+        workspace_id: i64,
+        user_id: i64,
+    },
+
+    // -- Permissions
+    PermissionDenied {
+        room_id: i64,
+        missing: Permissions,
+    },
+    WorkspacePermissionDenied {
+        workspace_id: i64,
+        missing: Permissions,
+    },
+    RoleInvalid {
+        role: String,
+    },
+
+    // -- Blocks
+    CannotBlockSelf,
+    UserBlocked {
+        user_id: i64,
+    },
+
+    // -- Voice
+    VoiceNotJoined {
+        room_id: i64,
+    },
+    VoicePeerNotJoined {
+        room_id: i64,
+        user_id: i64,
+    },
+    VoiceRoomFull {
+        room_id: i64,
+        capacity: i32,
+    },
+    VoiceSpeakerRequired {
+        room_id: i64,
+    },
+    VoiceNotStage {
+        room_id: i64,
+    },
+
+    // -- Invites
+    InviteNotFound {
+        code: String,
+    },
+    InviteExpired {
+        code: String,
+    },
+    InviteRevoked {
+        code: String,
+    },
+    InviteUsedUp {
+        code: String,
+    },
+    InviteAlreadyUsed {
+        code: String,
+    },
+    InviteInvalidMaxUses {
+        max_uses: i32,
+    },
+    InviteInvalidExpiry {
+        expires_in_sec: i64,
+    },
+
+    // -- Attachments
+    AttachmentNotFound {
+        id: uuid::Uuid,
+    },
+    UploadSessionNotFound {
+        id: uuid::Uuid,
+    },
+    UploadOffsetMismatch {
+        id: uuid::Uuid,
+        expected: i64,
+    },
+    UploadSessionIncomplete {
+        id: uuid::Uuid,
+        received_bytes: i64,
+        size_bytes: i64,
+    },
+    StorageQuotaExceeded {
+        scope: QuotaScope,
+        id: i64,
+        used_bytes: i64,
+        quota_bytes: i64,
+    },
+
     // -- Externals
     Sqlx(#[serde_as(as = "DisplayFromStr")] Arc<sqlx::Error>),
 
@@ -68,6 +205,12 @@ impl From<store::Error> for Error {
     }
 }
 
+impl From<blob::Error> for Error {
+    fn from(val: blob::Error) -> Self {
+        Self::Blob(val)
+    }
+}
+
 impl core::fmt::Display for Error {
     fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
         write!(fmt, "{self:?}")
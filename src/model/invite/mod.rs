@@ -0,0 +1,415 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::permission::Role;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+type UtcDateTime = DateTime<Utc>;
+
+const INVITE_CODE_LEN: usize = 10;
+pub const MAX_INVITE_EXPIRY_SEC: i64 = 365 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Invite {
+    pub id: i64,
+    pub code: String,
+    pub workspace_id: i64,
+    pub room_id: Option<i64>,
+    pub created_by: i64,
+    pub role: String,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: Option<UtcDateTime>,
+    pub revoked: bool,
+    pub created_at: UtcDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct InviteForCreate {
+    pub workspace_id: i64,
+    pub room_id: Option<i64>,
+    pub role: Option<Role>,
+    pub max_uses: Option<i32>,
+    pub expires_in_sec: Option<i64>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct InviteUse {
+    pub user_id: i64,
+    pub username: String,
+    pub used_at: UtcDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AcceptedInvite {
+    pub workspace_id: i64,
+    pub room_id: Option<i64>,
+    pub role: String,
+}
+
+pub struct InviteBmc;
+
+impl DbBmc for InviteBmc {
+    const TABLE: &'static str = "invites";
+}
+
+const INVITE_COLUMNS: &str = "id, code, workspace_id, room_id, created_by, role, max_uses, uses, \
+                              expires_at, revoked, created_at";
+
+impl InviteBmc {
+    pub async fn create(ctx: &Ctx, mm: &ModelManager, invite_c: InviteForCreate) -> Result<Invite> {
+        let InviteForCreate {
+            workspace_id,
+            room_id,
+            role,
+            max_uses,
+            expires_in_sec,
+        } = invite_c;
+        validate_limits(max_uses, expires_in_sec)?;
+        let role = role.unwrap_or(Role::Member);
+
+        let invite = sqlx::query_as::<_, Invite>(&format!(
+            r#"
+            INSERT INTO invites (code, workspace_id, room_id, created_by, role, max_uses, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))
+            RETURNING {INVITE_COLUMNS}
+            "#
+        ))
+        .bind(generate_code())
+        .bind(workspace_id)
+        .bind(room_id)
+        .bind(ctx.user_id())
+        .bind(role.as_ref())
+        .bind(max_uses)
+        .bind(expires_in_sec.map(|sec| sec as f64))
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(invite)
+    }
+
+    pub async fn get(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Invite> {
+        sqlx::query_as::<_, Invite>(&format!(
+            "SELECT {INVITE_COLUMNS} FROM invites WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::EntityNotFound {
+            entity: Self::TABLE,
+            id,
+        })
+    }
+
+    /// Lists the invites of a workspace, or only those of one of its rooms.
+    pub async fn list(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        room_id: Option<i64>,
+    ) -> Result<Vec<Invite>> {
+        let invites = sqlx::query_as::<_, Invite>(&format!(
+            r#"
+            SELECT {INVITE_COLUMNS} FROM invites
+            WHERE workspace_id = $1 AND ($2::BIGINT IS NULL OR room_id = $2)
+            ORDER BY created_at DESC
+            "#
+        ))
+        .bind(workspace_id)
+        .bind(room_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(invites)
+    }
+
+    pub async fn revoke(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        let count = sqlb::update()
+            .table(Self::TABLE)
+            .and_where("id", "=", id)
+            .data(vec![("revoked", true).into()])
+            .exec(mm.db())
+            .await?;
+
+        if count == 0 {
+            Err(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn list_uses(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Vec<InviteUse>> {
+        let uses = sqlx::query_as::<_, InviteUse>(
+            r#"
+            SELECT iu.user_id, u.username, iu.used_at
+            FROM invite_uses iu
+            JOIN users u ON u.id = iu.user_id
+            WHERE iu.invite_id = $1
+            ORDER BY iu.used_at
+            "#,
+        )
+        .bind(id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(uses)
+    }
+
+    /// Redeems an invite code for the ctx user: adds workspace (and room) membership with the
+    /// invite's role and records the use. Redeeming the same code again is a no-op while the
+    /// user is still a member, and fails with `InviteAlreadyUsed` once they left.
+    pub async fn accept(ctx: &Ctx, mm: &ModelManager, code: &str) -> Result<AcceptedInvite> {
+        let mut tx = mm.db().begin().await?;
+
+        let invite = sqlx::query_as::<_, Invite>(&format!(
+            "SELECT {INVITE_COLUMNS} FROM invites WHERE code = $1 FOR UPDATE"
+        ))
+        .bind(code)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| Error::InviteNotFound {
+            code: code.to_string(),
+        })?;
+
+        let first_use = sqlx::query(
+            "INSERT INTO invite_uses (invite_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(invite.id)
+        .bind(ctx.user_id())
+        .execute(&mut tx)
+        .await?
+        .rows_affected()
+            == 1;
+
+        if !first_use {
+            let still_member = sqlx::query_scalar::<_, bool>(
+                r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM workspace_members WHERE workspace_id = $1 AND user_id = $3
+                ) AND ($2::BIGINT IS NULL OR EXISTS (
+                    SELECT 1 FROM room_members WHERE room_id = $2 AND user_id = $3
+                ))
+                "#,
+            )
+            .bind(invite.workspace_id)
+            .bind(invite.room_id)
+            .bind(ctx.user_id())
+            .fetch_one(&mut tx)
+            .await?;
+            if !still_member {
+                return Err(Error::InviteAlreadyUsed { code: invite.code });
+            }
+        } else {
+            check_redeemable(&invite, Utc::now())?;
+
+            sqlx::query("UPDATE invites SET uses = uses + 1 WHERE id = $1")
+                .bind(invite.id)
+                .execute(&mut tx)
+                .await?;
+
+            // Room invites bring the user into the workspace as a plain member
+            let workspace_role = match invite.room_id {
+                Some(_) => Role::Member.as_ref(),
+                None => invite.role.as_str(),
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO workspace_members (workspace_id, user_id, role)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(invite.workspace_id)
+            .bind(ctx.user_id())
+            .bind(workspace_role)
+            .execute(&mut tx)
+            .await?;
+
+            if let Some(room_id) = invite.room_id {
+                sqlx::query(
+                    r#"
+                    INSERT INTO room_members (room_id, user_id, role)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                )
+                .bind(room_id)
+                .bind(ctx.user_id())
+                .bind(&invite.role)
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(AcceptedInvite {
+            workspace_id: invite.workspace_id,
+            room_id: invite.room_id,
+            role: invite.role,
+        })
+    }
+}
+
+/// Checks an invite can still be redeemed at `now`.
+fn validate_limits(max_uses: Option<i32>, expires_in_sec: Option<i64>) -> Result<()> {
+    if let Some(max_uses) = max_uses
+        && max_uses < 1
+    {
+        return Err(Error::InviteInvalidMaxUses { max_uses });
+    }
+    if let Some(sec) = expires_in_sec
+        && !(1..=MAX_INVITE_EXPIRY_SEC).contains(&sec)
+    {
+        return Err(Error::InviteInvalidExpiry {
+            expires_in_sec: sec,
+        });
+    }
+
+    Ok(())
+}
+
+fn check_redeemable(invite: &Invite, now: UtcDateTime) -> Result<()> {
+    let code = || invite.code.clone();
+
+    if invite.revoked {
+        return Err(Error::InviteRevoked { code: code() });
+    }
+    if invite.expires_at.is_some_and(|exp| exp <= now) {
+        return Err(Error::InviteExpired { code: code() });
+    }
+    if invite.max_uses.is_some_and(|max| invite.uses >= max) {
+        return Err(Error::InviteUsedUp { code: code() });
+    }
+
+    Ok(())
+}
+
+fn generate_code() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_CODE_LEN)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::workspace::member::WorkspaceMemberBmc;
+    use chrono::Duration;
+    use serial_test::serial;
+
+    fn fx_invite() -> Invite {
+        Invite {
+            id: 1,
+            code: "fx-code".to_string(),
+            workspace_id: 1,
+            room_id: None,
+            created_by: 1,
+            role: "member".to_string(),
+            max_uses: Some(2),
+            uses: 0,
+            expires_at: Some(Utc::now() + Duration::hours(1)),
+            revoked: false,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_check_redeemable_ok() {
+        let invite = fx_invite();
+
+        assert!(check_redeemable(&invite, Utc::now()).is_ok());
+    }
+
+    #[test]
+    fn test_check_redeemable_err() {
+        let now = Utc::now();
+
+        let mut expired = fx_invite();
+        expired.expires_at = Some(now - Duration::seconds(1));
+        assert!(matches!(
+            check_redeemable(&expired, now),
+            Err(Error::InviteExpired { .. })
+        ));
+
+        let mut used_up = fx_invite();
+        used_up.uses = 2;
+        assert!(matches!(
+            check_redeemable(&used_up, now),
+            Err(Error::InviteUsedUp { .. })
+        ));
+
+        let mut revoked = fx_invite();
+        revoked.revoked = true;
+        assert!(matches!(
+            check_redeemable(&revoked, now),
+            Err(Error::InviteRevoked { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate_limits() {
+        assert!(validate_limits(None, None).is_ok());
+        assert!(validate_limits(Some(1), Some(MAX_INVITE_EXPIRY_SEC)).is_ok());
+        assert!(matches!(
+            validate_limits(Some(0), None),
+            Err(Error::InviteInvalidMaxUses { max_uses: 0 })
+        ));
+        assert!(matches!(
+            validate_limits(None, Some(0)),
+            Err(Error::InviteInvalidExpiry { .. })
+        ));
+        assert!(matches!(
+            validate_limits(None, Some(MAX_INVITE_EXPIRY_SEC + 1)),
+            Err(Error::InviteInvalidExpiry { .. })
+        ));
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_accept_again_after_leaving_err() -> anyhow::Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let fx_creator_id = _dev_utils::seed_user(&mm, "invite_creator").await?;
+        let fx_user_id = _dev_utils::seed_user(&mm, "invite_user").await?;
+        let (fx_workspace_id,): (i64,) =
+            sqlx::query_as("SELECT id FROM workspaces WHERE name = 'Default'")
+                .fetch_one(mm.db())
+                .await?;
+        let invite = InviteBmc::create(
+            &Ctx::new(fx_creator_id)?,
+            &mm,
+            InviteForCreate {
+                workspace_id: fx_workspace_id,
+                room_id: None,
+                role: None,
+                max_uses: None,
+                expires_in_sec: None,
+            },
+        )
+        .await?;
+        let ctx = Ctx::new(fx_user_id)?;
+
+        // Execute
+        InviteBmc::accept(&ctx, &mm, &invite.code).await?;
+        // Redeeming again while still a member is a no-op
+        InviteBmc::accept(&ctx, &mm, &invite.code).await?;
+        WorkspaceMemberBmc::remove(&ctx, &mm, fx_workspace_id, fx_user_id).await?;
+        let res = InviteBmc::accept(&ctx, &mm, &invite.code).await;
+
+        // Check
+        assert!(matches!(res, Err(Error::InviteAlreadyUsed { .. })));
+        assert_eq!(InviteBmc::get(&ctx, &mm, invite.id).await?.uses, 1);
+
+        Ok(())
+    }
+}
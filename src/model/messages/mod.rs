@@ -1,33 +1,29 @@
 use crate::Ctx;
+use crate::model::attachment::{Attachment, AttachmentBmc};
 use crate::model::base;
 use crate::model::base::DbBmc;
+use crate::model::block::UserBlockBmc;
+use crate::model::permission::{PermissionBmc, Permissions};
+use crate::model::room::Room;
 use crate::model::user::{User, UserBmc};
-use crate::model::{ModelManager, Result};
+use crate::model::{Error, ModelManager, Result};
 use chrono::{DateTime, Utc};
+use lazy_regex::regex;
 use serde::{Deserialize, Serialize};
 use sqlb::{Fields, HasFields};
-use sqlx::{FromRow, Row};
+use sqlx::{FromRow, Postgres, Row, Transaction};
 type UtcDateTime = DateTime<Utc>;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct Image {
-    pub id: uuid::Uuid,
-    pub message_id: i64,
-    pub user_id: i64,
-    pub filename: String,
-    pub content_type: String,
-    pub storage_path: String,
-    pub uploaded_at: UtcDateTime,
-}
-
 #[derive(Debug, Clone, Serialize)]
-pub struct MessageWithImages {
+pub struct MessageWithAttachments {
     pub message_id: i64,
     pub message_text: String,
     pub message_room_id: i64,
     pub message_user_id: i64,
     pub message_datetime: UtcDateTime,
-    pub images: Vec<Image>,
+    /// True when the author is blocked by the requesting user, so the client can collapse it.
+    pub author_blocked: bool,
+    pub attachments: Vec<Attachment>,
 }
 
 #[derive(Debug, Clone, Fields, Deserialize, FromRow, Serialize)]
@@ -67,8 +63,33 @@ impl DbBmc for MessageBmc {
 }
 
 impl MessageBmc {
-    pub async fn send_message(ctx: &Ctx, mm: &ModelManager, message: Message) -> Result<i64> {
-        base::create::<Self, _>(ctx, mm, message).await
+    /// Posts `message` to `room`. In slow mode rooms this fails with `Error::RoomSlowMode`
+    /// while the ctx user is still cooling down, unless they hold `BYPASS_SLOW_MODE`
+    /// (moderators and up).
+    pub async fn send_message(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room: &Room,
+        message: Message,
+    ) -> Result<i64> {
+        let slow_mode = room.slow_mode_sec > 0
+            && !PermissionBmc::permissions_in_room(ctx, mm, room.id)
+                .await?
+                .contains(Permissions::BYPASS_SLOW_MODE);
+
+        let mut tx = mm.db().begin().await?;
+        if slow_mode {
+            Self::claim_slow_mode_post(&mut tx, room, ctx.user_id()).await?;
+        }
+        let (id,) = sqlb::insert()
+            .table(Self::TABLE)
+            .data(message.not_none_fields())
+            .returning(&["id"])
+            .fetch_one::<_, (i64,)>(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(id)
     }
 
     pub async fn send_private_message(
@@ -76,6 +97,13 @@ impl MessageBmc {
         mm: &ModelManager,
         task_c: MessageToFriend,
     ) -> Result<i64> {
+        let receiver: User = UserBmc::first_by_username(ctx, mm, &task_c.receiver_name)
+            .await?
+            .ok_or_else(|| Error::UsernameNotFound {
+                username: task_c.receiver_name.clone(),
+            })?;
+        UserBlockBmc::require_not_blocked_by(ctx, mm, receiver.id).await?;
+
         base::create_private::<Self, _>(ctx, mm, task_c).await
     }
 
@@ -99,11 +127,99 @@ impl MessageBmc {
         Ok(rooms)
     }
 
-    pub async fn list_with_images_by_room_id(
-        _ctx: &Ctx,
+    /// Returns the `(room_id, author_id)` of a message.
+    pub async fn room_and_author(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<(i64, i64)> {
+        sqlx::query_as("SELECT message_room_id, message_user_id FROM messages WHERE id = $1")
+            .bind(id)
+            .fetch_optional(mm.db())
+            .await?
+            .ok_or(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id,
+            })
+    }
+
+    /// Deletes a room message. Authors may delete their own messages; anyone else needs
+    /// `DELETE_MESSAGES` in the message's room. Returns the room id of the deleted message.
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<i64> {
+        let (room_id, author_id) = Self::room_and_author(ctx, mm, id).await?;
+
+        if author_id != ctx.user_id() {
+            PermissionBmc::require(ctx, mm, room_id, Permissions::DELETE_MESSAGES).await?;
+        }
+
+        AttachmentBmc::delete_by_message(ctx, mm, id).await?;
+        base::delete::<Self>(ctx, mm, id).await?;
+
+        Ok(room_id)
+    }
+
+    /// Records that `user_id` posts in `room` now, unless they posted less than
+    /// `slow_mode_sec` ago. The row lock serializes concurrent posts of the same user, and
+    /// deleting messages does not reset the cooldown.
+    async fn claim_slow_mode_post(
+        tx: &mut Transaction<'_, Postgres>,
+        room: &Room,
+        user_id: i64,
+    ) -> Result<()> {
+        let claimed: Option<(UtcDateTime,)> = sqlx::query_as(
+            r#"
+            INSERT INTO room_last_posts (room_id, user_id, last_posted_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT (room_id, user_id) DO UPDATE SET last_posted_at = now()
+            WHERE room_last_posts.last_posted_at <= now() - make_interval(secs => $3)
+            RETURNING last_posted_at
+            "#,
+        )
+        .bind(room.id)
+        .bind(user_id)
+        .bind(f64::from(room.slow_mode_sec))
+        .fetch_optional(&mut *tx)
+        .await?;
+        if claimed.is_some() {
+            return Ok(());
+        }
+
+        let (last,): (UtcDateTime,) = sqlx::query_as(
+            "SELECT last_posted_at FROM room_last_posts WHERE room_id = $1 AND user_id = $2",
+        )
+        .bind(room.id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Err(Error::RoomSlowMode {
+            room_id: room.id,
+            retry_after_sec: slow_mode_wait(room.slow_mode_sec, Some(last), Utc::now())
+                .unwrap_or(1),
+        })
+    }
+
+    /// Resolves the `@username` mentions in `text` to the users that should be notified,
+    /// skipping the sender and anyone who has blocked them.
+    pub async fn mention_recipients(ctx: &Ctx, mm: &ModelManager, text: &str) -> Result<Vec<User>> {
+        let usernames = extract_mentions(text);
+        if usernames.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut users = UserBmc::list_by_usernames(ctx, mm, &usernames).await?;
+        users.retain(|u| u.id != ctx.user_id());
+
+        let ids: Vec<i64> = users.iter().map(|u| u.id).collect();
+        let blockers = UserBlockBmc::list_blockers_among(ctx, mm, &ids).await?;
+        users.retain(|u| !blockers.contains(&u.id));
+
+        Ok(users)
+    }
+
+    pub async fn list_with_attachments_by_room_id(
+        ctx: &Ctx,
         mm: &ModelManager,
         room_id: i64,
-    ) -> Result<Vec<MessageWithImages>> {
+    ) -> Result<Vec<MessageWithAttachments>> {
+        // The attachment columns keep their names for `Attachment::from_row`, `message_id`
+        // being the same on both sides of the join
         let query = r#"
         SELECT
             m.id AS message_id,
@@ -111,47 +227,56 @@ impl MessageBmc {
             m.message_room_id,
             m.message_user_id,
             m.message_datetime,
-            i.id AS image_id,
-            i.message_id AS image_message_id,
-            i.user_id AS image_user_id,
-            i.filename,
-            i.content_type,
-            i.storage_path,
-            i.uploaded_at
+            EXISTS (
+                SELECT 1 FROM user_blocks b
+                WHERE b.blocker_id = $2 AND b.blocked_id = m.message_user_id
+            ) AS author_blocked,
+            a.id,
+            a.user_id,
+            a.kind,
+            a.filename,
+            a.content_type,
+            a.size_bytes,
+            a.storage_key,
+            a.sha256,
+            a.width,
+            a.height,
+            a.blurhash,
+            a.thumbnail_key,
+            a.preview_key,
+            a.sanitized,
+            a.uploaded_at
         FROM messages m
-        LEFT JOIN images i ON m.id = i.message_id
+        LEFT JOIN attachments a ON m.id = a.message_id
         WHERE m.message_room_id = $1
-        ORDER BY m.message_datetime ASC, i.uploaded_at ASC, m.id ASC
+        ORDER BY m.message_datetime ASC, a.uploaded_at ASC, m.id ASC
     "#;
 
-        let rows = sqlx::query(query).bind(room_id).fetch_all(mm.db()).await?;
+        let rows = sqlx::query(query)
+            .bind(room_id)
+            .bind(ctx.user_id())
+            .fetch_all(mm.db())
+            .await?;
 
         use std::collections::HashMap;
 
-        let mut map: HashMap<i64, MessageWithImages> = HashMap::new();
+        let mut map: HashMap<i64, MessageWithAttachments> = HashMap::new();
 
         for row in rows {
             let msg_id: i64 = row.get("message_id");
 
-            let entry = map.entry(msg_id).or_insert_with(|| MessageWithImages {
+            let entry = map.entry(msg_id).or_insert_with(|| MessageWithAttachments {
                 message_id: msg_id,
                 message_text: row.get("message_text"),
                 message_room_id: row.get("message_room_id"),
                 message_user_id: row.get("message_user_id"),
                 message_datetime: row.get("message_datetime"),
-                images: vec![],
+                author_blocked: row.get("author_blocked"),
+                attachments: vec![],
             });
 
-            if let Some(image_id) = row.try_get::<uuid::Uuid, _>("image_id").ok() {
-                entry.images.push(Image {
-                    id: image_id,
-                    message_id: row.get("image_message_id"),
-                    user_id: row.get("image_user_id"),
-                    filename: row.get("filename"),
-                    content_type: row.get("content_type"),
-                    storage_path: row.get("storage_path"),
-                    uploaded_at: row.get("uploaded_at"),
-                });
+            if row.try_get::<Option<uuid::Uuid>, _>("id")?.is_some() {
+                entry.attachments.push(Attachment::from_row(&row)?);
             }
         }
 
@@ -193,3 +318,91 @@ impl MessageBmc {
         Ok(combined_messages)
     }
 }
+
+/// Returns the distinct usernames referenced as `@username` in a message.
+pub fn extract_mentions(text: &str) -> Vec<String> {
+    let mut usernames: Vec<String> = regex!(r"@([A-Za-z0-9_.\-]+)")
+        .captures_iter(text)
+        .map(|c| c[1].to_string())
+        .collect();
+    usernames.sort();
+    usernames.dedup();
+    usernames
+}
+
+/// Returns the whole seconds left before a user who last posted at `last` may post again.
+fn slow_mode_wait(slow_mode_sec: i32, last: Option<UtcDateTime>, now: UtcDateTime) -> Option<i64> {
+    let elapsed_ms = (now - last?).num_milliseconds();
+    let remaining_ms = i64::from(slow_mode_sec) * 1000 - elapsed_ms;
+
+    (remaining_ms > 0).then(|| (remaining_ms + 999) / 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::room::RoomBmc;
+    use chrono::Duration;
+    use serial_test::serial;
+
+    #[test]
+    fn test_slow_mode_wait() {
+        let now = Utc::now();
+
+        assert_eq!(slow_mode_wait(30, None, now), None);
+        assert_eq!(
+            slow_mode_wait(30, Some(now - Duration::seconds(31)), now),
+            None
+        );
+        assert_eq!(
+            slow_mode_wait(30, Some(now - Duration::milliseconds(10_500)), now),
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn test_extract_mentions_ok() {
+        let fx_text = "hey @dallas and @friend_2, ping @dallas again (not an email: a@)";
+
+        let mentions = extract_mentions(fx_text);
+
+        assert_eq!(mentions, vec!["dallas".to_string(), "friend_2".to_string()]);
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_send_message_slow_mode() -> anyhow::Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let fx_user_id = _dev_utils::seed_user(&mm, "slow_mode_poster").await?;
+        let fx_room_id = _dev_utils::seed_room(&mm, "Slow mode", "text").await?;
+        sqlx::query("UPDATE rooms SET slow_mode_sec = 60 WHERE id = $1")
+            .bind(fx_room_id)
+            .execute(mm.db())
+            .await?;
+        let ctx = Ctx::new(fx_user_id)?;
+        let room = RoomBmc::get(&ctx, &mm, fx_room_id).await?;
+        let fx_message = Message {
+            message_text: "hello".to_string(),
+            message_room_id: fx_room_id,
+            message_user_id: fx_user_id,
+        };
+
+        // Execute
+        let id = MessageBmc::send_message(&ctx, &mm, &room, fx_message.clone()).await?;
+        let res = MessageBmc::send_message(&ctx, &mm, &room, fx_message.clone()).await;
+
+        // Check
+        assert!(matches!(
+            res,
+            Err(Error::RoomSlowMode { retry_after_sec, .. }) if retry_after_sec > 0
+        ));
+        // Deleting the last message keeps the cooldown
+        MessageBmc::delete(&ctx, &mm, id).await?;
+        let res = MessageBmc::send_message(&ctx, &mm, &room, fx_message).await;
+        assert!(matches!(res, Err(Error::RoomSlowMode { .. })));
+
+        Ok(())
+    }
+}
@@ -1,41 +1,74 @@
 use crate::model::store::{Db, new_db_pool};
 use axum::extract::ws::Message;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::{RwLock, mpsc::UnboundedSender};
 mod error;
 mod store;
 
+pub mod attachment;
 pub mod base;
+pub mod blob;
+pub mod block;
+pub mod invite;
 pub mod messages;
+pub mod permission;
+pub mod quarantine;
 pub mod room;
+pub mod upload_session;
 pub mod user;
+pub mod voice;
+pub mod workspace;
+use self::blob::{BlobStore, new_blob_store};
 pub use self::error::{Error, Result};
+use self::voice::relay::AudioRelay;
+use crate::media::scan::{ContentScanner, new_content_scanner};
 
 #[derive(Clone)]
 pub struct ModelManager {
     db: Db,
+    blob_store: Arc<dyn BlobStore>,
+    content_scanner: Arc<dyn ContentScanner>,
     pub ws_broadcast: WsManager,
+    pub audio_relay: AudioRelay,
 }
 
 impl ModelManager {
     pub async fn new() -> Result<Self> {
         let db = new_db_pool().await?;
+        let blob_store = new_blob_store()?;
         Ok(ModelManager {
             db,
+            blob_store,
+            content_scanner: new_content_scanner(),
             ws_broadcast: WsManager::new(),
+            audio_relay: AudioRelay::new(),
         })
     }
 
     pub fn db(&self) -> &Db {
         &self.db
     }
+
+    pub fn blob_store(&self) -> &dyn BlobStore {
+        self.blob_store.as_ref()
+    }
+
+    pub fn content_scanner(&self) -> &dyn ContentScanner {
+        self.content_scanner.as_ref()
+    }
 }
 
+/// A user's websocket, with the id `register_user` gave it.
+type WsConnection = (u64, UnboundedSender<Message>);
+
 #[derive(Clone)]
 pub struct WsManager {
-    users: Arc<RwLock<HashMap<String, UnboundedSender<Message>>>>,
+    /// The latest connection of each user.
+    users: Arc<RwLock<HashMap<i64, WsConnection>>>,
+    next_connection_id: Arc<AtomicU64>,
 }
 
 #[derive(Serialize)]
@@ -46,66 +79,167 @@ pub enum WsEvent {
         room_id: i64,
         from: String,
         content: String,
+        /// True when the recipient blocked the author, so the client can collapse it.
+        author_blocked: bool,
     },
     VoiceJoin {
         room_id: i64,
         user_id: i64,
         username: String,
     },
+    VoiceLeave {
+        room_id: i64,
+        user_id: i64,
+    },
+    VoiceStateChanged {
+        room_id: i64,
+        user_id: i64,
+        muted: bool,
+        deafened: bool,
+        speaking: bool,
+        video: bool,
+    },
+    MessageDeleted {
+        room_id: i64,
+        message_id: i64,
+    },
+    Mention {
+        room_id: i64,
+        message_id: i64,
+        from: String,
+    },
+    VoiceHandRaised {
+        room_id: i64,
+        user_id: i64,
+        raised: bool,
+    },
+    VoiceSpeakerChanged {
+        room_id: i64,
+        user_id: i64,
+        speaker: bool,
+    },
+    /// WebRTC signaling (SDP offer/answer or ICE candidate) relayed from another participant.
+    RtcSignal {
+        room_id: i64,
+        from_user: i64,
+        payload: serde_json::Value,
+    },
+    /// Sent to the moderators of a room when an upload to it was flagged by the scanner.
+    UploadQuarantined {
+        room_id: i64,
+        quarantine_id: uuid::Uuid,
+        user_id: i64,
+        filename: String,
+        signature: String,
+    },
+    /// Reply to the sender of a `WsCommand` that could not be carried out.
+    CommandFailed {
+        error: serde_json::Value,
+    },
+}
+
+/// Commands a client can send as JSON text frames on its websocket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum WsCommand {
+    SendMessage {
+        room_id: i64,
+        content: String,
+    },
+    /// Relays an opaque WebRTC signaling `payload` to another participant of the voice room.
+    RtcSignal {
+        room_id: i64,
+        to_user: i64,
+        payload: serde_json::Value,
+    },
 }
 
 impl WsManager {
     pub fn new() -> Self {
         Self {
             users: Arc::new(RwLock::new(HashMap::new())),
+            next_connection_id: Arc::new(AtomicU64::new(1)),
         }
     }
 
-    pub async fn register_user(&self, user_id: String, tx: UnboundedSender<Message>) {
-        self.users.write().await.insert(user_id, tx);
+    /// Makes `tx` the connection events for `user_id` are sent to, replacing any previous
+    /// one. Returns the id of the connection to pass to `unregister_user`.
+    pub async fn register_user(&self, user_id: i64, tx: UnboundedSender<Message>) -> u64 {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        self.users
+            .write()
+            .await
+            .insert(user_id, (connection_id, tx));
+        connection_id
     }
 
-    pub async fn unregister_user(&self, user_id: &String) {
-        self.users.write().await.remove(user_id);
+    /// Removes the connection `connection_id` of `user_id`, unless a newer one replaced it.
+    /// Returns whether the user has no connection left.
+    pub async fn unregister_user(&self, user_id: i64, connection_id: u64) -> bool {
+        let mut users = self.users.write().await;
+        match users.get(&user_id) {
+            Some((registered_id, _)) if *registered_id != connection_id => false,
+            _ => {
+                users.remove(&user_id);
+                true
+            }
+        }
     }
 
-    pub async fn broadcast_to_user(&self, user_id: &String, msg: &String) {
+    pub async fn broadcast_to_user(&self, user_id: i64, msg: &str) {
         let users = self.users.read().await;
-        if let Some(tx) = users.get(user_id) {
+        if let Some((_, tx)) = users.get(&user_id) {
             let _ = tx.send(Message::Text(msg.to_string()));
         }
     }
 
-    pub async fn broadcast_to_room(&self, user_id: &String, msg: &String) {
+    pub async fn broadcast_to_users(&self, user_ids: &[i64], msg: &str) {
         let users = self.users.read().await;
 
-        for (user, tx) in users.iter() {
-            if let Err(e) = tx.send(Message::Text(msg.to_string())) {
-                tracing::warn!("WebSocket send failed for {user}: {e}");
+        for user_id in user_ids {
+            if let Some((_, tx)) = users.get(user_id)
+                && let Err(e) = tx.send(Message::Text(msg.to_string()))
+            {
+                tracing::warn!("WebSocket send failed for {user_id}: {e}");
             }
         }
     }
 
-    pub async fn broadcast_voice(&self, room_id: i64, user_id: i64, username: &String) {
+    pub async fn send_binary_to_users(&self, user_ids: &[i64], frame: &[u8]) {
         let users = self.users.read().await;
-        let msg = WsEvent::VoiceJoin {
-            room_id: room_id,
-            user_id: user_id,
-            username: username.to_string(),
-        };
-
-        let json = match serde_json::to_string(&msg) {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to serialize WsEvent::VoiceJoin: {e}");
-                return;
-            }
-        };
 
-        for (user, tx) in users.iter() {
-            if let Err(e) = tx.send(Message::Text(json.clone())) {
-                tracing::warn!("WebSocket send failed for {user}: {e}");
+        for user_id in user_ids {
+            if let Some((_, tx)) = users.get(user_id)
+                && let Err(e) = tx.send(Message::Binary(frame.to_vec()))
+            {
+                tracing::warn!("WebSocket binary send failed for {user_id}: {e}");
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[tokio::test]
+    async fn test_unregister_user_keeps_newer_connection() {
+        // Setup
+        let ws = WsManager::new();
+        let fx_user_id = 1000;
+        let (old_tx, _old_rx) = unbounded_channel();
+        let (new_tx, mut new_rx) = unbounded_channel();
+
+        // Execute
+        let old_id = ws.register_user(fx_user_id, old_tx).await;
+        let new_id = ws.register_user(fx_user_id, new_tx).await;
+
+        // Check
+        assert!(!ws.unregister_user(fx_user_id, old_id).await);
+        ws.broadcast_to_user(fx_user_id, "still here").await;
+        assert!(matches!(new_rx.try_recv(), Ok(Message::Text(text)) if text == "still here"));
+        assert!(ws.unregister_user(fx_user_id, new_id).await);
+        assert!(ws.users.read().await.is_empty());
+    }
+}
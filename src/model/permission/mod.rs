@@ -0,0 +1,324 @@
+use crate::ctx::Ctx;
+use crate::model::room::RoomBmc;
+use crate::model::workspace::member::WorkspaceMemberBmc;
+use crate::model::{Error, ModelManager, Result};
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Permissions: i64 {
+        const SEND_MESSAGES = 1 << 0;
+        const INVITE_MEMBERS = 1 << 1;
+        const DELETE_MESSAGES = 1 << 2;
+        const PIN_MESSAGES = 1 << 3;
+        const KICK_MEMBERS = 1 << 4;
+        const MANAGE_VOICE = 1 << 5;
+        const MANAGE_ROOM = 1 << 6;
+        const MANAGE_ROLES = 1 << 7;
+        const CREATE_ROOMS = 1 << 8;
+        const MANAGE_WORKSPACE = 1 << 9;
+        const BYPASS_SLOW_MODE = 1 << 10;
+        const POST_ANNOUNCEMENTS = 1 << 11;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Admin,
+    Moderator,
+    Member,
+    Guest,
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(role: &str) -> core::result::Result<Self, Self::Err> {
+        match role {
+            "owner" => Ok(Role::Owner),
+            "admin" => Ok(Role::Admin),
+            "moderator" => Ok(Role::Moderator),
+            "member" => Ok(Role::Member),
+            "guest" => Ok(Role::Guest),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Role {
+    pub fn permissions(&self) -> Permissions {
+        match self {
+            Role::Owner => Permissions::all(),
+            Role::Admin => {
+                Role::Moderator.permissions()
+                    | Permissions::MANAGE_ROOM
+                    | Permissions::MANAGE_ROLES
+                    | Permissions::MANAGE_WORKSPACE
+            }
+            Role::Moderator => {
+                Role::Member.permissions()
+                    | Permissions::DELETE_MESSAGES
+                    | Permissions::PIN_MESSAGES
+                    | Permissions::KICK_MEMBERS
+                    | Permissions::MANAGE_VOICE
+                    | Permissions::BYPASS_SLOW_MODE
+                    | Permissions::POST_ANNOUNCEMENTS
+            }
+            Role::Member => {
+                Permissions::SEND_MESSAGES | Permissions::INVITE_MEMBERS | Permissions::CREATE_ROOMS
+            }
+            Role::Guest => Permissions::SEND_MESSAGES,
+        }
+    }
+
+    /// Higher rank outranks lower rank; used to stop users acting on their peers or superiors.
+    pub fn rank(&self) -> u8 {
+        match self {
+            Role::Owner => 4,
+            Role::Admin => 3,
+            Role::Moderator => 2,
+            Role::Member => 1,
+            Role::Guest => 0,
+        }
+    }
+
+    pub fn outranks(&self, other: Role) -> bool {
+        self.rank() > other.rank()
+    }
+}
+
+pub struct PermissionBmc;
+
+impl PermissionBmc {
+    /// Returns the ctx user's effective role in the room, `None` when they cannot see it.
+    pub async fn role_in_room(ctx: &Ctx, mm: &ModelManager, room_id: i64) -> Result<Option<Role>> {
+        Self::user_role_in_room(ctx, mm, room_id, ctx.user_id()).await
+    }
+
+    /// Returns the effective role of `user_id` in the room. Workspace owners and admins carry
+    /// their role into every room of the workspace, and other workspace members act as guests
+    /// in public rooms they have not joined.
+    pub async fn user_role_in_room(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        user_id: i64,
+    ) -> Result<Option<Role>> {
+        let room = RoomBmc::get(ctx, mm, room_id).await?;
+        let Some(workspace_role) =
+            WorkspaceMemberBmc::role_of(ctx, mm, room.workspace_id, user_id).await?
+        else {
+            return Ok(None);
+        };
+        let room_role = Self::member_role(ctx, mm, room_id, user_id).await?;
+
+        let candidates = [
+            room_role,
+            workspace_role
+                .outranks(Role::Moderator)
+                .then_some(workspace_role),
+            room.is_public().then_some(Role::Guest),
+        ];
+
+        Ok(candidates.into_iter().flatten().max_by_key(Role::rank))
+    }
+
+    /// Returns the stored role of `user_id` in the room, `None` when not a member.
+    pub async fn member_role(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        user_id: i64,
+    ) -> Result<Option<Role>> {
+        let role: Option<(String,)> =
+            sqlx::query_as("SELECT role FROM room_members WHERE room_id = $1 AND user_id = $2")
+                .bind(room_id)
+                .bind(user_id)
+                .fetch_optional(mm.db())
+                .await?;
+
+        role.map(|(role,)| Role::from_str(&role).map_err(|_| Error::RoleInvalid { role }))
+            .transpose()
+    }
+
+    pub async fn permissions_in_room(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+    ) -> Result<Permissions> {
+        if ctx.user_id() == 0 {
+            return Ok(Permissions::all());
+        }
+
+        let role = Self::role_in_room(ctx, mm, room_id).await?;
+        Ok(role
+            .map(|r| r.permissions())
+            .unwrap_or(Permissions::empty()))
+    }
+
+    /// The single permission gate: fails with `Error::PermissionDenied` unless the ctx user
+    /// holds every flag of `required` in the room.
+    pub async fn require(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        required: Permissions,
+    ) -> Result<()> {
+        let granted = Self::permissions_in_room(ctx, mm, room_id).await?;
+
+        check(granted, required).map_err(|missing| Error::PermissionDenied { room_id, missing })
+    }
+
+    /// Like `require`, but also checks the ctx user outranks `target_user_id` in the room.
+    pub async fn require_over(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        required: Permissions,
+        target_user_id: i64,
+    ) -> Result<()> {
+        Self::require(ctx, mm, room_id, required).await?;
+
+        if ctx.user_id() == 0 {
+            return Ok(());
+        }
+
+        let own = Self::role_in_room(ctx, mm, room_id).await?;
+        let target = Self::user_role_in_room(ctx, mm, room_id, target_user_id).await?;
+
+        if outranks(own, target) {
+            Ok(())
+        } else {
+            Err(Error::PermissionDenied {
+                room_id,
+                missing: Permissions::empty(),
+            })
+        }
+    }
+
+    pub async fn role_in_workspace(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+    ) -> Result<Option<Role>> {
+        WorkspaceMemberBmc::role_of(ctx, mm, workspace_id, ctx.user_id()).await
+    }
+
+    pub async fn permissions_in_workspace(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+    ) -> Result<Permissions> {
+        if ctx.user_id() == 0 {
+            return Ok(Permissions::all());
+        }
+
+        let role = Self::role_in_workspace(ctx, mm, workspace_id).await?;
+        Ok(role
+            .map(|r| r.permissions())
+            .unwrap_or(Permissions::empty()))
+    }
+
+    /// Workspace-level counterpart of `require`.
+    pub async fn require_in_workspace(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        required: Permissions,
+    ) -> Result<()> {
+        let granted = Self::permissions_in_workspace(ctx, mm, workspace_id).await?;
+
+        check(granted, required).map_err(|missing| Error::WorkspacePermissionDenied {
+            workspace_id,
+            missing,
+        })
+    }
+
+    /// Workspace-level counterpart of `require_over`.
+    pub async fn require_over_in_workspace(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        required: Permissions,
+        target_user_id: i64,
+    ) -> Result<()> {
+        Self::require_in_workspace(ctx, mm, workspace_id, required).await?;
+
+        if ctx.user_id() == 0 {
+            return Ok(());
+        }
+
+        let own = Self::role_in_workspace(ctx, mm, workspace_id).await?;
+        let target = WorkspaceMemberBmc::role_of(ctx, mm, workspace_id, target_user_id).await?;
+
+        if outranks(own, target) {
+            Ok(())
+        } else {
+            Err(Error::WorkspacePermissionDenied {
+                workspace_id,
+                missing: Permissions::empty(),
+            })
+        }
+    }
+}
+
+/// Returns the missing flags when `granted` does not cover `required`.
+fn check(granted: Permissions, required: Permissions) -> core::result::Result<(), Permissions> {
+    if granted.contains(required) {
+        Ok(())
+    } else {
+        Err(required.difference(granted))
+    }
+}
+
+/// A missing role ranks below every role.
+fn outranks(own: Option<Role>, target: Option<Role>) -> bool {
+    match (own, target) {
+        (Some(own), Some(target)) => own.outranks(target),
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_permissions_are_cumulative() {
+        let roles = [
+            Role::Guest,
+            Role::Member,
+            Role::Moderator,
+            Role::Admin,
+            Role::Owner,
+        ];
+
+        for pair in roles.windows(2) {
+            let (lower, higher) = (pair[0], pair[1]);
+            assert!(higher.permissions().contains(lower.permissions()));
+            assert!(higher.outranks(lower));
+        }
+    }
+
+    #[test]
+    fn test_role_from_str_ok() {
+        assert_eq!(Role::from_str("moderator").ok(), Some(Role::Moderator));
+        assert_eq!(Role::Owner.as_ref(), "owner");
+        assert!(Role::from_str("superuser").is_err());
+    }
+
+    #[test]
+    fn test_member_cannot_kick() {
+        let perms = Role::Member.permissions();
+
+        assert!(perms.contains(Permissions::SEND_MESSAGES));
+        assert!(!perms.contains(Permissions::KICK_MEMBERS));
+        assert!(!perms.contains(Permissions::MANAGE_ROOM));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::{ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashSet;
+use uuid::Uuid;
+type UtcDateTime = DateTime<Utc>;
+
+/// An upload flagged by the content scanner. The file is kept under `storage_key` for review,
+/// it is never attached nor served.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct QuarantinedFile {
+    pub id: Uuid,
+    pub user_id: Option<i64>,
+    pub room_id: Option<i64>,
+    pub filename: String,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the file.
+    pub sha256: String,
+    pub storage_key: String,
+    /// What the scanner matched.
+    pub signature: String,
+    pub created_at: UtcDateTime,
+}
+
+pub struct QuarantinedFileForCreate {
+    pub id: Uuid,
+    pub room_id: i64,
+    pub filename: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    pub storage_key: String,
+    pub signature: String,
+}
+
+/// Key the file of quarantine `id` is stored under.
+pub fn quarantine_key(id: Uuid) -> String {
+    format!("quarantine/{id}")
+}
+
+pub struct QuarantineBmc;
+
+impl DbBmc for QuarantineBmc {
+    const TABLE: &'static str = "quarantined_files";
+}
+
+const QUARANTINE_COLUMNS: &str =
+    "id, user_id, room_id, filename, size_bytes, sha256, storage_key, signature, created_at";
+
+impl QuarantineBmc {
+    /// Records a file the ctx user uploaded to `room_id`, already stored under `storage_key`.
+    pub async fn create(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        file_c: QuarantinedFileForCreate,
+    ) -> Result<QuarantinedFile> {
+        let QuarantinedFileForCreate {
+            id,
+            room_id,
+            filename,
+            size_bytes,
+            sha256,
+            storage_key,
+            signature,
+        } = file_c;
+
+        let file = sqlx::query_as::<_, QuarantinedFile>(&format!(
+            r#"
+            INSERT INTO quarantined_files (id, user_id, room_id, filename, size_bytes, sha256,
+                                           storage_key, signature)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING {QUARANTINE_COLUMNS}
+            "#
+        ))
+        .bind(id)
+        .bind(ctx.user_id())
+        .bind(room_id)
+        .bind(filename)
+        .bind(size_bytes)
+        .bind(sha256)
+        .bind(storage_key)
+        .bind(signature)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(file)
+    }
+
+    /// Keys of every quarantined file, so the janitor leaves them alone.
+    pub async fn list_keys(_ctx: &Ctx, mm: &ModelManager) -> Result<HashSet<String>> {
+        let keys = sqlx::query_scalar::<_, String>("SELECT storage_key FROM quarantined_files")
+            .fetch_all(mm.db())
+            .await?;
+
+        Ok(keys.into_iter().collect())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::ctx::Ctx;
+use crate::model::base;
+use crate::model::base::DbBmc;
+use crate::model::{Error, ModelManager, Result};
+use serde::{Deserialize, Serialize};
+use sqlb::{Fields, HasFields};
+use sqlx::FromRow;
+
+use super::{RoomBmc, is_permutation, write_positions};
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct RoomCategory {
+    pub id: i64,
+    pub workspace_id: i64,
+    pub name: String,
+    pub position: i32,
+}
+
+#[derive(Fields, Deserialize)]
+pub struct RoomCategoryCreate {
+    pub workspace_id: i64,
+    pub name: String,
+}
+
+#[derive(Fields, Deserialize)]
+pub struct RoomCategoryUpdate {
+    pub name: Option<String>,
+}
+
+pub struct RoomCategoryBmc;
+
+impl DbBmc for RoomCategoryBmc {
+    const TABLE: &'static str = "room_categories";
+}
+
+impl RoomCategoryBmc {
+    /// Creates the category below the existing ones.
+    pub async fn create(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        category_c: RoomCategoryCreate,
+    ) -> Result<i64> {
+        let (position,): (i32,) = sqlx::query_as(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM room_categories WHERE workspace_id = $1",
+        )
+        .bind(category_c.workspace_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        let mut fields = category_c.not_none_fields();
+        fields.push(("position", position).into());
+
+        let (id,) = sqlb::insert()
+            .table(Self::TABLE)
+            .data(fields)
+            .returning(&["id"])
+            .fetch_one::<_, (i64,)>(mm.db())
+            .await?;
+
+        Ok(id)
+    }
+
+    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<RoomCategory> {
+        base::get::<Self, _>(ctx, mm, id).await
+    }
+
+    /// Fails unless the category exists and belongs to `workspace_id`.
+    pub async fn require_in_workspace(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        workspace_id: i64,
+    ) -> Result<()> {
+        let category = Self::get(ctx, mm, id).await?;
+
+        if category.workspace_id == workspace_id {
+            Ok(())
+        } else {
+            Err(Error::RoomCategoryInvalid {
+                category_id: id,
+                workspace_id,
+            })
+        }
+    }
+
+    pub async fn list(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+    ) -> Result<Vec<RoomCategory>> {
+        let categories = sqlx::query_as::<_, RoomCategory>(
+            r#"
+            SELECT id, workspace_id, name, position
+            FROM room_categories
+            WHERE workspace_id = $1
+            ORDER BY position, id
+            "#,
+        )
+        .bind(workspace_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(categories)
+    }
+
+    pub async fn update(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        category_u: RoomCategoryUpdate,
+    ) -> Result<()> {
+        base::update::<Self, _>(ctx, mm, id, category_u).await
+    }
+
+    /// Deletes the category; its rooms become uncategorized, after the rooms already there.
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        let category = Self::get(ctx, mm, id).await?;
+        let mut ordered =
+            RoomBmc::list_ids_in_category(ctx, mm, category.workspace_id, None).await?;
+        ordered
+            .extend(RoomBmc::list_ids_in_category(ctx, mm, category.workspace_id, Some(id)).await?);
+
+        let mut tx = mm.db().begin().await?;
+        let count = sqlx::query("DELETE FROM room_categories WHERE id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if count == 0 {
+            return Err(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id,
+            });
+        }
+        write_positions(&mut tx, RoomBmc::TABLE, &ordered).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Sets the order of the categories of a workspace. `category_ids` must list exactly the
+    /// current categories.
+    pub async fn reorder(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        category_ids: &[i64],
+    ) -> Result<()> {
+        let current: Vec<i64> = Self::list(ctx, mm, workspace_id)
+            .await?
+            .into_iter()
+            .map(|category| category.id)
+            .collect();
+        if !is_permutation(&current, category_ids) {
+            return Err(Error::RoomOrderInvalid { expected: current });
+        }
+
+        write_positions(mm.db(), Self::TABLE, category_ids).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use serial_test::serial;
+
+    #[serial]
+    #[tokio::test]
+    async fn test_delete_appends_rooms_to_uncategorized() -> anyhow::Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let ctx = Ctx::root_ctx();
+        let (fx_workspace_id,): (i64,) =
+            sqlx::query_as("SELECT id FROM workspaces WHERE name = 'Default'")
+                .fetch_one(mm.db())
+                .await?;
+        let fx_category_id = RoomCategoryBmc::create(
+            &ctx,
+            &mm,
+            RoomCategoryCreate {
+                workspace_id: fx_workspace_id,
+                name: "to_delete".to_string(),
+            },
+        )
+        .await?;
+        _dev_utils::seed_room(&mm, "category_stays", "text").await?;
+        let mut fx_moved_ids = Vec::new();
+        for title in ["category_first", "category_second"] {
+            let room_id = _dev_utils::seed_room(&mm, title, "text").await?;
+            RoomBmc::move_to(&ctx, &mm, room_id, Some(fx_category_id), None).await?;
+            fx_moved_ids.push(room_id);
+        }
+        let uncategorized = RoomBmc::list_ids_in_category(&ctx, &mm, fx_workspace_id, None).await?;
+
+        // Execute
+        RoomCategoryBmc::delete(&ctx, &mm, fx_category_id).await?;
+
+        // Check
+        let expected: Vec<i64> = uncategorized.into_iter().chain(fx_moved_ids).collect();
+        assert_eq!(
+            RoomBmc::list_ids_in_category(&ctx, &mm, fx_workspace_id, None).await?,
+            expected
+        );
+        let (positions,): (Vec<i32>,) = sqlx::query_as(
+            r#"
+            SELECT ARRAY_AGG(position ORDER BY position) FROM rooms
+            WHERE workspace_id = $1 AND category_id IS NULL
+            "#,
+        )
+        .bind(fx_workspace_id)
+        .fetch_one(mm.db())
+        .await?;
+        assert_eq!(positions, (0..expected.len() as i32).collect::<Vec<_>>());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::permission::Role;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+type UtcDateTime = DateTime<Utc>;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct RoomMember {
+    pub user_id: i64,
+    pub username: String,
+    pub role: String,
+    pub joined_at: UtcDateTime,
+}
+
+pub struct RoomMemberBmc;
+
+impl DbBmc for RoomMemberBmc {
+    const TABLE: &'static str = "room_members";
+}
+
+impl RoomMemberBmc {
+    /// Adds `user_id` to the room with `role`; existing members keep their current role.
+    pub async fn add(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO room_members (room_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .bind(role.as_ref())
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_role(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> Result<()> {
+        let count = sqlb::update()
+            .table(Self::TABLE)
+            .and_where("room_id", "=", room_id)
+            .and_where("user_id", "=", user_id)
+            .data(vec![("role", role.as_ref().to_string()).into()])
+            .exec(mm.db())
+            .await?;
+
+        if count == 0 {
+            Err(Error::RoomMemberNotFound { room_id, user_id })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn remove(_ctx: &Ctx, mm: &ModelManager, room_id: i64, user_id: i64) -> Result<()> {
+        let count = sqlb::delete()
+            .table(Self::TABLE)
+            .and_where("room_id", "=", room_id)
+            .and_where("user_id", "=", user_id)
+            .exec(mm.db())
+            .await?;
+
+        if count == 0 {
+            Err(Error::RoomMemberNotFound { room_id, user_id })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn list_by_room(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+    ) -> Result<Vec<RoomMember>> {
+        let members = sqlx::query_as::<_, RoomMember>(
+            r#"
+            SELECT m.user_id, u.username, m.role, m.joined_at
+            FROM room_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.room_id = $1
+            ORDER BY m.joined_at
+            "#,
+        )
+        .bind(room_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(members)
+    }
+
+    pub async fn list_user_ids(_ctx: &Ctx, mm: &ModelManager, room_id: i64) -> Result<Vec<i64>> {
+        let ids: Vec<(i64,)> =
+            sqlx::query_as("SELECT user_id FROM room_members WHERE room_id = $1")
+                .bind(room_id)
+                .fetch_all(mm.db())
+                .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+}
@@ -1,27 +1,142 @@
 use crate::Ctx;
+use crate::model::attachment::AttachmentBmc;
 use crate::model::base;
 use crate::model::base::DbBmc;
-use crate::model::{ModelManager, Result};
+use crate::model::permission::{PermissionBmc, Permissions, Role};
+use crate::model::workspace::member::WorkspaceMemberBmc;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlb::Fields;
-use sqlx::FromRow;
+use sqlb::{Fields, HasFields};
+use sqlx::{FromRow, PgExecutor};
+type UtcDateTime = DateTime<Utc>;
 
-#[derive(Debug, Clone, Fields, FromRow, Serialize, Deserialize)]
+pub mod category;
+pub mod member;
+
+use self::category::RoomCategoryBmc;
+use self::member::RoomMemberBmc;
+
+pub const VISIBILITY_PUBLIC: &str = "public";
+pub const VISIBILITY_PRIVATE: &str = "private";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "room_kind", rename_all = "snake_case")]
+pub enum RoomKind {
+    Text,
+    Voice,
+    /// Read-mostly room: only members with `POST_ANNOUNCEMENTS` can post.
+    Announcement,
+    /// Text room meant for longer, topic-driven posts.
+    Forum,
+    /// Voice room with a stage of speakers and an audience.
+    Stage,
+}
+
+sqlb::bindable!(RoomKind);
+
+impl RoomKind {
+    pub fn has_voice(&self) -> bool {
+        matches!(self, RoomKind::Voice | RoomKind::Stage)
+    }
+
+    pub fn has_messages(&self) -> bool {
+        !self.has_voice()
+    }
+
+    /// The permissions needed to post a message in a room of this kind.
+    pub fn post_permissions(&self) -> Permissions {
+        match self {
+            RoomKind::Announcement => Permissions::SEND_MESSAGES | Permissions::POST_ANNOUNCEMENTS,
+            _ => Permissions::SEND_MESSAGES,
+        }
+    }
+}
+
+/// Six hours, the longest slow mode a room can be set to.
+pub const MAX_SLOW_MODE_SEC: i32 = 6 * 60 * 60;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
 pub struct Room {
     pub id: i64,
-    pub room_type: String,
+    pub workspace_id: i64,
+    pub category_id: Option<i64>,
+    pub room_type: RoomKind,
     pub title: String,
+    pub topic: Option<String>,
+    pub visibility: String,
+    pub position: i32,
+    pub archived: bool,
+    pub slow_mode_sec: i32,
+    pub voice_capacity: Option<i32>,
+    pub push_to_talk: bool,
+    pub created_by: Option<i64>,
+    pub created_at: UtcDateTime,
+}
+
+impl Room {
+    pub fn is_public(&self) -> bool {
+        self.visibility == VISIBILITY_PUBLIC
+    }
+
+    /// Stage rooms only let approved speakers unmute.
+    pub fn is_stage(&self) -> bool {
+        self.room_type == RoomKind::Stage
+    }
+
+    /// Fails with `Error::RoomKindUnsupported` unless the room carries messages.
+    pub fn require_messages(&self) -> Result<()> {
+        self.require_kind(self.room_type.has_messages())
+    }
+
+    /// Fails with `Error::RoomKindUnsupported` unless the room carries voice.
+    pub fn require_voice(&self) -> Result<()> {
+        self.require_kind(self.room_type.has_voice())
+    }
+
+    fn require_kind(&self, supported: bool) -> Result<()> {
+        if supported {
+            Ok(())
+        } else {
+            Err(Error::RoomKindUnsupported {
+                room_id: self.id,
+                kind: self.room_type,
+            })
+        }
+    }
+
+    /// Archived rooms are read-only: history stays readable but nothing new can be posted.
+    pub fn require_not_archived(&self) -> Result<()> {
+        if self.archived {
+            Err(Error::RoomArchived { room_id: self.id })
+        } else {
+            Ok(())
+        }
+    }
 }
 
 #[derive(Fields, Deserialize)]
 pub struct RoomCreate {
-    pub room_type: String,
+    pub workspace_id: i64,
+    pub category_id: Option<i64>,
+    pub room_type: RoomKind,
     pub title: String,
+    pub topic: Option<String>,
+    pub visibility: Option<String>,
+    pub slow_mode_sec: Option<i32>,
+    pub voice_capacity: Option<i32>,
+    pub push_to_talk: Option<bool>,
 }
 
 #[derive(Fields, Deserialize)]
 pub struct RoomUpdate {
     pub title: Option<String>,
+    pub topic: Option<String>,
+    pub visibility: Option<String>,
+    pub slow_mode_sec: Option<i32>,
+    pub voice_capacity: Option<i32>,
+    pub push_to_talk: Option<bool>,
 }
 
 pub struct RoomBmc;
@@ -30,17 +145,109 @@ impl DbBmc for RoomBmc {
     const TABLE: &'static str = "rooms";
 }
 
+const ROOM_COLUMNS: &str = "id, workspace_id, category_id, room_type, title, topic, visibility, \
+                            position, archived, slow_mode_sec, voice_capacity, push_to_talk, created_by, \
+                            created_at";
+
 impl RoomBmc {
-    pub async fn create(ctx: &Ctx, mm: &ModelManager, title: RoomCreate) -> Result<i64> {
-        base::create::<Self, _>(ctx, mm, title).await
+    /// Creates the room at the end of its category.
+    pub async fn create(ctx: &Ctx, mm: &ModelManager, room_c: RoomCreate) -> Result<i64> {
+        validate_visibility(room_c.visibility.as_deref())?;
+        validate_slow_mode(room_c.slow_mode_sec)?;
+        validate_voice_capacity(room_c.voice_capacity)?;
+        // Slow mode only applies to rooms that carry messages
+        if let Some(sec) = room_c.slow_mode_sec
+            && sec > 0
+            && !room_c.room_type.has_messages()
+        {
+            return Err(Error::RoomInvalidSlowMode { slow_mode_sec: sec });
+        }
+        PermissionBmc::require_in_workspace(
+            ctx,
+            mm,
+            room_c.workspace_id,
+            Permissions::CREATE_ROOMS,
+        )
+        .await?;
+        if let Some(category_id) = room_c.category_id {
+            RoomCategoryBmc::require_in_workspace(ctx, mm, category_id, room_c.workspace_id)
+                .await?;
+        }
+
+        let position =
+            Self::next_position(ctx, mm, room_c.workspace_id, room_c.category_id).await?;
+        let mut fields = room_c.not_none_fields();
+        fields.push(("position", position).into());
+        fields.push(("created_by", ctx.user_id()).into());
+
+        let (id,) = sqlb::insert()
+            .table(Self::TABLE)
+            .data(fields)
+            .returning(&["id"])
+            .fetch_one::<_, (i64,)>(mm.db())
+            .await?;
+        RoomMemberBmc::add(ctx, mm, id, ctx.user_id(), Role::Owner).await?;
+
+        Ok(id)
     }
 
-    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Room> {
-        base::get::<Self, _>(ctx, mm, id).await
+    pub async fn get(_ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Room> {
+        sqlx::query_as::<_, Room>(&format!("SELECT {ROOM_COLUMNS} FROM rooms WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(mm.db())
+            .await?
+            .ok_or(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id,
+            })
     }
 
-    pub async fn list(ctx: &Ctx, mm: &ModelManager) -> Result<Vec<Room>> {
-        base::list::<Self, _>(ctx, mm).await
+    /// Returns the room if the ctx user can see it (see `PermissionBmc::role_in_room`).
+    pub async fn get_visible(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Room> {
+        let room = Self::get(ctx, mm, id).await?;
+
+        if ctx.user_id() == 0 || PermissionBmc::role_in_room(ctx, mm, id).await?.is_some() {
+            Ok(room)
+        } else {
+            Err(Error::RoomAccessDenied { room_id: id })
+        }
+    }
+
+    /// Lists the rooms of a workspace the ctx user can see: public rooms, the private rooms
+    /// they are a member of, and every room for workspace owners and admins.
+    ///
+    /// Rooms come in sidebar order: uncategorized rooms first, then by category position,
+    /// then by room position.
+    pub async fn list(ctx: &Ctx, mm: &ModelManager, workspace_id: i64) -> Result<Vec<Room>> {
+        let Some(workspace_role) = PermissionBmc::role_in_workspace(ctx, mm, workspace_id).await?
+        else {
+            return Err(Error::WorkspaceAccessDenied { workspace_id });
+        };
+        let sees_all = workspace_role.outranks(Role::Moderator);
+
+        let rooms = sqlx::query_as::<_, Room>(
+            r#"
+            SELECT r.id, r.workspace_id, r.category_id, r.room_type, r.title, r.topic,
+                   r.visibility, r.position, r.archived, r.slow_mode_sec, r.voice_capacity,
+                   r.push_to_talk, r.created_by, r.created_at
+            FROM rooms r
+            LEFT JOIN room_categories c ON c.id = r.category_id
+            WHERE r.workspace_id = $1
+              AND ($2
+                   OR r.visibility = $3
+                   OR EXISTS (SELECT 1 FROM room_members m
+                              WHERE m.room_id = r.id AND m.user_id = $4))
+            ORDER BY c.position NULLS FIRST, c.id NULLS FIRST, r.position, r.id
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(sees_all)
+        .bind(VISIBILITY_PUBLIC)
+        .bind(ctx.user_id())
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rooms)
     }
 
     pub async fn update(
@@ -49,10 +256,301 @@ impl RoomBmc {
         id: i64,
         room_update: RoomUpdate,
     ) -> Result<()> {
+        validate_visibility(room_update.visibility.as_deref())?;
+        validate_slow_mode(room_update.slow_mode_sec)?;
+        validate_voice_capacity(room_update.voice_capacity)?;
+
         base::update::<Self, _>(ctx, mm, id, room_update).await
     }
 
     pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        AttachmentBmc::delete_by_room(ctx, mm, id).await?;
         base::delete::<Self>(ctx, mm, id).await
     }
+
+    pub async fn set_archived(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        archived: bool,
+    ) -> Result<()> {
+        let count = sqlb::update()
+            .table(Self::TABLE)
+            .and_where("id", "=", id)
+            .data(vec![("archived", archived).into()])
+            .exec(mm.db())
+            .await?;
+
+        if count == 0 {
+            Err(Error::EntityNotFound {
+                entity: Self::TABLE,
+                id,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Moves the room into `category_id` (`None` for uncategorized) at `position`, or at the
+    /// end when no position is given. The rooms of both the old and the new category are
+    /// renumbered to keep positions contiguous.
+    pub async fn move_to(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        category_id: Option<i64>,
+        position: Option<usize>,
+    ) -> Result<()> {
+        let room = Self::get(ctx, mm, id).await?;
+        if let Some(category_id) = category_id {
+            RoomCategoryBmc::require_in_workspace(ctx, mm, category_id, room.workspace_id).await?;
+        }
+
+        let siblings = Self::list_ids_in_category(ctx, mm, room.workspace_id, category_id).await?;
+        let ordered = place(siblings, id, position);
+        let left = if room.category_id != category_id {
+            let mut left =
+                Self::list_ids_in_category(ctx, mm, room.workspace_id, room.category_id).await?;
+            left.retain(|&sibling| sibling != id);
+            Some(left)
+        } else {
+            None
+        };
+
+        let mut tx = mm.db().begin().await?;
+        sqlx::query("UPDATE rooms SET category_id = $2 WHERE id = $1")
+            .bind(id)
+            .bind(category_id)
+            .execute(&mut tx)
+            .await?;
+        write_positions(&mut tx, Self::TABLE, &ordered).await?;
+        if let Some(left) = left {
+            write_positions(&mut tx, Self::TABLE, &left).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Sets the order of the rooms of one category. `room_ids` must list exactly the rooms
+    /// currently in that category.
+    pub async fn reorder(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        category_id: Option<i64>,
+        room_ids: &[i64],
+    ) -> Result<()> {
+        let current = Self::list_ids_in_category(ctx, mm, workspace_id, category_id).await?;
+        if !is_permutation(&current, room_ids) {
+            return Err(Error::RoomOrderInvalid { expected: current });
+        }
+
+        write_positions(mm.db(), Self::TABLE, room_ids).await
+    }
+
+    /// Returns the ids of the rooms directly in `category_id`, in position order.
+    async fn list_ids_in_category(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        category_id: Option<i64>,
+    ) -> Result<Vec<i64>> {
+        let ids: Vec<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM rooms
+            WHERE workspace_id = $1 AND category_id IS NOT DISTINCT FROM $2
+            ORDER BY position, id
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(category_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    async fn next_position(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        category_id: Option<i64>,
+    ) -> Result<i32> {
+        let (position,): (i32,) = sqlx::query_as(
+            r#"
+            SELECT COALESCE(MAX(position) + 1, 0) FROM rooms
+            WHERE workspace_id = $1 AND category_id IS NOT DISTINCT FROM $2
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(category_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(position)
+    }
+
+    /// Returns the ids of the users who can see `room`: the whole workspace for public rooms,
+    /// the room members for private ones.
+    pub async fn audience_user_ids(ctx: &Ctx, mm: &ModelManager, room: &Room) -> Result<Vec<i64>> {
+        if room.is_public() {
+            WorkspaceMemberBmc::list_user_ids(ctx, mm, room.workspace_id).await
+        } else {
+            RoomMemberBmc::list_user_ids(ctx, mm, room.id).await
+        }
+    }
+
+    /// Returns the ids of the users holding every flag of `required` in `room` through their
+    /// stored role, in the room or carried from the workspace. Public rooms' guests never
+    /// qualify, guests only send messages.
+    pub async fn user_ids_with_permission(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room: &Room,
+        required: Permissions,
+    ) -> Result<Vec<i64>> {
+        let roles = [
+            Role::Owner,
+            Role::Admin,
+            Role::Moderator,
+            Role::Member,
+            Role::Guest,
+        ];
+        let granting = |role: &&Role| role.permissions().contains(required);
+        let room_roles: Vec<String> = roles
+            .iter()
+            .filter(granting)
+            .map(|role| role.as_ref().to_string())
+            .collect();
+        let workspace_roles: Vec<String> = roles
+            .iter()
+            .filter(granting)
+            .filter(|role| role.outranks(Role::Moderator))
+            .map(|role| role.as_ref().to_string())
+            .collect();
+
+        let ids: Vec<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT user_id FROM room_members WHERE room_id = $1 AND role = ANY($2)
+            UNION
+            SELECT user_id FROM workspace_members WHERE workspace_id = $3 AND role = ANY($4)
+            "#,
+        )
+        .bind(room.id)
+        .bind(room_roles)
+        .bind(room.workspace_id)
+        .bind(workspace_roles)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Sends `msg` to every connected user who can see `room`.
+    pub async fn broadcast(ctx: &Ctx, mm: &ModelManager, room: &Room, msg: &str) -> Result<()> {
+        let user_ids = Self::audience_user_ids(ctx, mm, room).await?;
+        mm.ws_broadcast.broadcast_to_users(&user_ids, msg).await;
+
+        Ok(())
+    }
+}
+
+fn validate_visibility(visibility: Option<&str>) -> Result<()> {
+    match visibility {
+        None | Some(VISIBILITY_PUBLIC) | Some(VISIBILITY_PRIVATE) => Ok(()),
+        Some(other) => Err(Error::RoomInvalidVisibility {
+            visibility: other.to_string(),
+        }),
+    }
+}
+
+fn validate_slow_mode(slow_mode_sec: Option<i32>) -> Result<()> {
+    match slow_mode_sec {
+        Some(sec) if !(0..=MAX_SLOW_MODE_SEC).contains(&sec) => {
+            Err(Error::RoomInvalidSlowMode { slow_mode_sec: sec })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_voice_capacity(voice_capacity: Option<i32>) -> Result<()> {
+    match voice_capacity {
+        Some(capacity) if capacity < 1 => Err(Error::RoomInvalidVoiceCapacity { capacity }),
+        _ => Ok(()),
+    }
+}
+
+/// Sets `position` to the index of each id in `ids` for the rows of `table`.
+async fn write_positions<'e>(db: impl PgExecutor<'e>, table: &str, ids: &[i64]) -> Result<()> {
+    let positions: Vec<i32> = (0..ids.len() as i32).collect();
+
+    sqlx::query(&format!(
+        r#"
+        UPDATE {table} SET position = t.position
+        FROM UNNEST($1::BIGINT[], $2::INTEGER[]) AS t(id, position)
+        WHERE {table}.id = t.id
+        "#
+    ))
+    .bind(ids)
+    .bind(positions)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Moves `id` to `index` in `ids` (appending it when missing), clamping to the end.
+fn place(mut ids: Vec<i64>, id: i64, index: Option<usize>) -> Vec<i64> {
+    ids.retain(|other| *other != id);
+    let index = index.unwrap_or(ids.len()).min(ids.len());
+    ids.insert(index, id);
+
+    ids
+}
+
+fn is_permutation(current: &[i64], proposed: &[i64]) -> bool {
+    let mut current = current.to_vec();
+    let mut proposed = proposed.to_vec();
+    current.sort_unstable();
+    proposed.sort_unstable();
+
+    current == proposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_ok() {
+        assert_eq!(place(vec![1, 2, 3], 3, Some(0)), vec![3, 1, 2]);
+        assert_eq!(place(vec![1, 2, 3], 1, None), vec![2, 3, 1]);
+        assert_eq!(place(vec![1, 2], 9, Some(1)), vec![1, 9, 2]);
+        assert_eq!(place(vec![1, 2], 9, Some(42)), vec![1, 2, 9]);
+    }
+
+    #[test]
+    fn test_room_kind_post_permissions() {
+        assert!(RoomKind::Voice.has_voice() && !RoomKind::Voice.has_messages());
+        assert!(RoomKind::Stage.has_voice());
+        assert!(RoomKind::Forum.has_messages());
+        assert!(
+            !Role::Member
+                .permissions()
+                .contains(RoomKind::Announcement.post_permissions())
+        );
+        assert!(
+            Role::Moderator
+                .permissions()
+                .contains(RoomKind::Announcement.post_permissions())
+        );
+    }
+
+    #[test]
+    fn test_is_permutation() {
+        assert!(is_permutation(&[1, 2, 3], &[3, 1, 2]));
+        assert!(!is_permutation(&[1, 2, 3], &[1, 2]));
+        assert!(!is_permutation(&[1, 2], &[1, 1]));
+    }
 }
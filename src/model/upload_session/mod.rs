@@ -0,0 +1,308 @@
+use crate::config;
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashSet;
+use uuid::Uuid;
+type UtcDateTime = DateTime<Utc>;
+
+/// A resumable upload. The client sends the file in chunks at increasing offsets, then
+/// finalizes the session to turn the staged bytes into an attachment.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub message_id: i64,
+    pub filename: String,
+    /// Announced size of the whole file.
+    pub size_bytes: i64,
+    /// Offset the next chunk must start at.
+    pub received_bytes: i64,
+    pub sha256: Option<String>,
+    pub created_at: UtcDateTime,
+    pub expires_at: UtcDateTime,
+}
+
+impl UploadSession {
+    pub fn is_complete(&self) -> bool {
+        self.received_bytes == self.size_bytes
+    }
+}
+
+pub struct UploadSessionForCreate {
+    pub message_id: i64,
+    pub filename: String,
+    pub size_bytes: i64,
+    pub sha256: Option<String>,
+}
+
+pub struct UploadSessionBmc;
+
+impl DbBmc for UploadSessionBmc {
+    const TABLE: &'static str = "upload_sessions";
+}
+
+const UPLOAD_SESSION_COLUMNS: &str = "id, user_id, message_id, filename, size_bytes, \
+                                      received_bytes, sha256, created_at, expires_at";
+
+impl UploadSessionBmc {
+    /// Opens a session of the ctx user, expiring after `SERVICE_UPLOAD_SESSION_TTL_SEC`.
+    pub async fn create(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        session_c: UploadSessionForCreate,
+    ) -> Result<UploadSession> {
+        let UploadSessionForCreate {
+            message_id,
+            filename,
+            size_bytes,
+            sha256,
+        } = session_c;
+
+        let session = sqlx::query_as::<_, UploadSession>(&format!(
+            r#"
+            INSERT INTO upload_sessions (id, user_id, message_id, filename, size_bytes, sha256,
+                                         expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))
+            RETURNING {UPLOAD_SESSION_COLUMNS}
+            "#
+        ))
+        .bind(Uuid::new_v4())
+        .bind(ctx.user_id())
+        .bind(message_id)
+        .bind(filename)
+        .bind(size_bytes)
+        .bind(sha256)
+        .bind(config().UPLOAD_SESSION_TTL_SEC)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Sessions of other users and expired ones are reported as not found.
+    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: Uuid) -> Result<UploadSession> {
+        sqlx::query_as::<_, UploadSession>(&format!(
+            r#"
+            SELECT {UPLOAD_SESSION_COLUMNS} FROM upload_sessions
+            WHERE id = $1 AND user_id = $2 AND expires_at > now()
+            "#
+        ))
+        .bind(id)
+        .bind(ctx.user_id())
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::UploadSessionNotFound { id })
+    }
+
+    /// Records that the bytes up to `received_bytes` are staged, provided the chunk started at
+    /// `offset`, the offset the session was at. Fails with `UploadOffsetMismatch` when another
+    /// chunk got in first.
+    pub async fn advance(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: Uuid,
+        offset: i64,
+        received_bytes: i64,
+    ) -> Result<UploadSession> {
+        let session = sqlx::query_as::<_, UploadSession>(&format!(
+            r#"
+            UPDATE upload_sessions SET received_bytes = $3
+            WHERE id = $1 AND user_id = $4 AND received_bytes = $2 AND expires_at > now()
+            RETURNING {UPLOAD_SESSION_COLUMNS}
+            "#
+        ))
+        .bind(id)
+        .bind(offset)
+        .bind(received_bytes)
+        .bind(ctx.user_id())
+        .fetch_optional(mm.db())
+        .await?;
+
+        match session {
+            Some(session) => Ok(session),
+            None => {
+                let session = Self::get(ctx, mm, id).await?;
+                Err(Error::UploadOffsetMismatch {
+                    id,
+                    expected: session.received_bytes,
+                })
+            }
+        }
+    }
+
+    /// Deletes the expired sessions of every user. Returns their ids, their staged bytes are
+    /// left to the caller.
+    pub async fn delete_expired(_ctx: &Ctx, mm: &ModelManager) -> Result<Vec<Uuid>> {
+        let ids = sqlx::query_scalar::<_, Uuid>(
+            "DELETE FROM upload_sessions WHERE expires_at <= now() RETURNING id",
+        )
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(ids)
+    }
+
+    /// Ids of the sessions of every user, expired ones included.
+    pub async fn list_ids(_ctx: &Ctx, mm: &ModelManager) -> Result<HashSet<Uuid>> {
+        let ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM upload_sessions")
+            .fetch_all(mm.db())
+            .await?;
+
+        Ok(ids.into_iter().collect())
+    }
+
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM upload_sessions WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(ctx.user_id())
+            .execute(mm.db())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use anyhow::Result;
+    use serial_test::serial;
+
+    /// A session of a new user for 10 bytes, on a message of theirs.
+    async fn fx_session(mm: &ModelManager, username: &str) -> Result<(Ctx, UploadSession)> {
+        let fx_user_id = _dev_utils::seed_user(mm, username).await?;
+        let fx_room_id = _dev_utils::seed_room(mm, username, "text").await?;
+        let (fx_message_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO messages (message_text, message_room_id, message_user_id)
+            VALUES ('file', $1, $2) RETURNING id
+            "#,
+        )
+        .bind(fx_room_id)
+        .bind(fx_user_id)
+        .fetch_one(mm.db())
+        .await?;
+        let ctx = Ctx::new(fx_user_id)?;
+
+        let session = UploadSessionBmc::create(
+            &ctx,
+            mm,
+            UploadSessionForCreate {
+                message_id: fx_message_id,
+                filename: "notes.txt".to_string(),
+                size_bytes: 10,
+                sha256: None,
+            },
+        )
+        .await?;
+
+        Ok((ctx, session))
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_create_get_ok() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+
+        // Execute
+        let (ctx, session) = fx_session(&mm, "upload_session_create").await?;
+
+        // Check
+        let fetched = UploadSessionBmc::get(&ctx, &mm, session.id).await?;
+        assert_eq!(fetched.received_bytes, 0);
+        assert_eq!(fetched.size_bytes, 10);
+        assert!(!fetched.is_complete());
+        // Sessions of other users are hidden
+        let other_id = _dev_utils::seed_user(&mm, "upload_session_other").await?;
+        assert!(matches!(
+            UploadSessionBmc::get(&Ctx::new(other_id)?, &mm, session.id).await,
+            Err(Error::UploadSessionNotFound { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_advance_chunks_ok() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let (ctx, session) = fx_session(&mm, "upload_session_chunks").await?;
+
+        // Execute
+        // A chunk of 6 bytes cut short after 4, the next one resumes at 4
+        let session = UploadSessionBmc::advance(&ctx, &mm, session.id, 0, 4).await?;
+        assert_eq!(session.received_bytes, 4);
+        let session = UploadSessionBmc::advance(&ctx, &mm, session.id, 4, 10).await?;
+
+        // Check
+        assert!(session.is_complete());
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_advance_offset_mismatch_err() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let (ctx, session) = fx_session(&mm, "upload_session_mismatch").await?;
+        UploadSessionBmc::advance(&ctx, &mm, session.id, 0, 4).await?;
+
+        // Execute
+        // A retry of the first chunk that lost the race
+        let res = UploadSessionBmc::advance(&ctx, &mm, session.id, 0, 6).await;
+
+        // Check
+        assert!(matches!(
+            res,
+            Err(Error::UploadOffsetMismatch { expected: 4, .. })
+        ));
+        assert_eq!(
+            UploadSessionBmc::get(&ctx, &mm, session.id)
+                .await?
+                .received_bytes,
+            4
+        );
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_expired_session_not_found() -> Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let (ctx, session) = fx_session(&mm, "upload_session_expired").await?;
+        sqlx::query("UPDATE upload_sessions SET expires_at = now() WHERE id = $1")
+            .bind(session.id)
+            .execute(mm.db())
+            .await?;
+
+        // Execute
+        let get_res = UploadSessionBmc::get(&ctx, &mm, session.id).await;
+        let advance_res = UploadSessionBmc::advance(&ctx, &mm, session.id, 0, 4).await;
+        let expired = UploadSessionBmc::delete_expired(&ctx, &mm).await?;
+
+        // Check
+        assert!(matches!(get_res, Err(Error::UploadSessionNotFound { .. })));
+        assert!(matches!(
+            advance_res,
+            Err(Error::UploadSessionNotFound { .. })
+        ));
+        assert!(expired.contains(&session.id));
+        assert!(
+            !UploadSessionBmc::list_ids(&ctx, &mm)
+                .await?
+                .contains(&session.id)
+        );
+
+        Ok(())
+    }
+}
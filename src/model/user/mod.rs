@@ -4,6 +4,8 @@ use crate::ctx::Ctx;
 use crate::model::ModelManager;
 use crate::model::Result;
 use crate::model::base::{self, DbBmc};
+use crate::model::block::UserBlockBmc;
+use crate::model::workspace::member::WorkspaceMemberBmc;
 use crate::web::rpc::ParamsForCreate;
 use serde::{Deserialize, Serialize};
 use sqlb::{Fields, HasFields};
@@ -125,14 +127,30 @@ impl UserBmc {
         Ok(user)
     }
 
+    pub async fn list_by_usernames(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        usernames: &[String],
+    ) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            "SELECT id, username FROM users WHERE username = ANY($1) ORDER BY id",
+        )
+        .bind(usernames)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(users)
+    }
+
     pub async fn create_user(mm: &ModelManager, username: &str, pwd_clear: &str) -> Result<()> {
         let db = mm.db();
 
         let pwd_salt = Uuid::new_v4();
-        let pwd = pwd::encrypt_pwd(&EncryptContent {
+        let pwd = pwd::hash_pwd(EncryptContent {
             content: pwd_clear.to_string(),
             salt: pwd_salt.to_string(),
-        })?;
+        })
+        .await?;
 
         let user = UserForInsert {
             username: username.to_string(),
@@ -140,11 +158,15 @@ impl UserBmc {
             pwd_salt: pwd_salt,
             token_salt: Uuid::new_v4(),
         };
-        sqlb::insert()
+        let (id,) = sqlb::insert()
             .table(Self::TABLE)
             .data(user.all_fields())
-            .exec(db)
+            .returning(&["id"])
+            .fetch_one::<_, (i64,)>(db)
             .await?;
+
+        WorkspaceMemberBmc::add_to_auto_join(mm, id).await?;
+
         Ok(())
     }
 
@@ -157,6 +179,7 @@ impl UserBmc {
         let db = mm.db();
 
         let user: User = Self::get(&ctx, &mm, ctx.user_id()).await?;
+        UserBlockBmc::require_not_blocked_by(&ctx, &mm, data.id).await?;
 
         let friendship = Friendship {
             user1_id: user.id,
@@ -200,10 +223,11 @@ impl UserBmc {
         let db = mm.db();
         let user: UserForLogin = Self::get(ctx, mm, id).await?;
 
-        let pwd = pwd::encrypt_pwd(&EncryptContent {
+        let pwd = pwd::hash_pwd(EncryptContent {
             content: pwd_clear.to_string(),
             salt: user.pwd_salt.to_string(),
-        })?;
+        })
+        .await?;
 
         sqlb::update()
             .table(Self::TABLE)
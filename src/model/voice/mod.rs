@@ -0,0 +1,455 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::permission::{PermissionBmc, Permissions};
+use crate::model::room::Room;
+use crate::model::{Error, ModelManager, Result, WsEvent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+type UtcDateTime = DateTime<Utc>;
+
+pub mod relay;
+
+use self::relay::PeerState;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct VoiceParticipant {
+    pub room_id: i64,
+    pub user_id: i64,
+    pub muted: bool,
+    pub deafened: bool,
+    pub speaking: bool,
+    pub video: bool,
+    /// Whether the participant may unmute. Always true outside of stage rooms.
+    pub speaker: bool,
+    pub hand_raised: bool,
+    pub joined_at: UtcDateTime,
+}
+
+impl VoiceParticipant {
+    /// The relay state of the participant. In push-to-talk rooms audio only goes out while
+    /// the participant is speaking.
+    pub fn peer_state(&self, push_to_talk: bool) -> PeerState {
+        PeerState {
+            muted: self.muted || (push_to_talk && !self.speaking),
+            deafened: self.deafened,
+        }
+    }
+
+    pub fn state_changed_event(&self) -> WsEvent {
+        WsEvent::VoiceStateChanged {
+            room_id: self.room_id,
+            user_id: self.user_id,
+            muted: self.muted,
+            deafened: self.deafened,
+            speaking: self.speaking,
+            video: self.video,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct VoiceStateUpdate {
+    pub muted: Option<bool>,
+    pub deafened: Option<bool>,
+    pub speaking: Option<bool>,
+    pub video: Option<bool>,
+}
+
+impl VoiceStateUpdate {
+    /// Deafening also mutes, and a muted user cannot be speaking.
+    fn normalized(mut self) -> Self {
+        if self.deafened == Some(true) {
+            self.muted = Some(true);
+        }
+        if self.muted == Some(true) {
+            self.speaking = Some(false);
+        }
+        self
+    }
+
+    /// Whether the update would let the participant be heard.
+    fn opens_mic(&self) -> bool {
+        self.muted == Some(false) || self.speaking == Some(true)
+    }
+}
+
+pub struct VoiceParticipantBmc;
+
+impl DbBmc for VoiceParticipantBmc {
+    const TABLE: &'static str = "room_participants";
+}
+
+const PARTICIPANT_COLUMNS: &str =
+    "room_id, user_id, muted, deafened, speaking, video, speaker, hand_raised, joined_at";
+
+impl VoiceParticipantBmc {
+    /// Adds the ctx user to the voice room; rejoining keeps the current state.
+    ///
+    /// Fails with `Error::VoiceRoomFull` once the room reaches its `voice_capacity`, unless
+    /// the user holds `MANAGE_VOICE`. In stage rooms only `MANAGE_VOICE` holders join as
+    /// speakers, everyone else joins muted in the audience.
+    pub async fn join(ctx: &Ctx, mm: &ModelManager, room: &Room) -> Result<VoiceParticipant> {
+        let moderates = PermissionBmc::permissions_in_room(ctx, mm, room.id)
+            .await?
+            .contains(Permissions::MANAGE_VOICE);
+        let capacity = room.voice_capacity.filter(|_| !moderates);
+        let speaker = !room.is_stage() || moderates;
+
+        let mut tx = mm.db().begin().await?;
+        // Serializes concurrent joins so the capacity check holds
+        sqlx::query("SELECT id FROM rooms WHERE id = $1 FOR UPDATE")
+            .bind(room.id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO room_participants (room_id, user_id, muted, speaker)
+            SELECT $1, $2, $3, $4
+            WHERE $5::INTEGER IS NULL
+               OR (SELECT COUNT(*) FROM room_participants WHERE room_id = $1) < $5
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(room.id)
+        .bind(ctx.user_id())
+        .bind(!speaker)
+        .bind(speaker)
+        .bind(capacity)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        let participant =
+            Self::get(ctx, mm, room.id, ctx.user_id())
+                .await
+                .map_err(|err| match err {
+                    Error::VoiceNotJoined { .. } => Error::VoiceRoomFull {
+                        room_id: room.id,
+                        capacity: capacity.unwrap_or_default(),
+                    },
+                    err => err,
+                })?;
+        mm.audio_relay
+            .join(
+                room.id,
+                participant.user_id,
+                participant.peer_state(room.push_to_talk),
+            )
+            .await;
+
+        Ok(participant)
+    }
+
+    pub async fn get(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        user_id: i64,
+    ) -> Result<VoiceParticipant> {
+        sqlx::query_as::<_, VoiceParticipant>(&format!(
+            "SELECT {PARTICIPANT_COLUMNS} FROM room_participants WHERE room_id = $1 AND user_id = $2"
+        ))
+        .bind(room_id)
+        .bind(user_id)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::VoiceNotJoined { room_id })
+    }
+
+    /// Fails unless both the ctx user and `peer_id` are in the voice room.
+    pub async fn require_peers(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        peer_id: i64,
+    ) -> Result<()> {
+        Self::get(ctx, mm, room_id, ctx.user_id()).await?;
+        Self::get(ctx, mm, room_id, peer_id)
+            .await
+            .map_err(|_| Error::VoicePeerNotJoined {
+                room_id,
+                user_id: peer_id,
+            })?;
+
+        Ok(())
+    }
+
+    pub async fn leave(ctx: &Ctx, mm: &ModelManager, room_id: i64) -> Result<()> {
+        let count =
+            sqlx::query("DELETE FROM room_participants WHERE room_id = $1 AND user_id = $2")
+                .bind(room_id)
+                .bind(ctx.user_id())
+                .execute(mm.db())
+                .await?
+                .rows_affected();
+
+        mm.audio_relay.leave(room_id, ctx.user_id()).await;
+
+        if count == 0 {
+            Err(Error::VoiceNotJoined { room_id })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Removes the ctx user from every voice room, returning the rooms they were in.
+    pub async fn leave_all(ctx: &Ctx, mm: &ModelManager) -> Result<Vec<i64>> {
+        let rooms: Vec<(i64,)> =
+            sqlx::query_as("DELETE FROM room_participants WHERE user_id = $1 RETURNING room_id")
+                .bind(ctx.user_id())
+                .fetch_all(mm.db())
+                .await?;
+        mm.audio_relay.leave_all(ctx.user_id()).await;
+
+        Ok(rooms.into_iter().map(|(room_id,)| room_id).collect())
+    }
+
+    /// Updates the ctx user's own state. Only speakers may unmute or speak.
+    pub async fn update_state(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room: &Room,
+        state: VoiceStateUpdate,
+    ) -> Result<VoiceParticipant> {
+        let state = state.normalized();
+        if state.opens_mic() && !Self::get(ctx, mm, room.id, ctx.user_id()).await?.speaker {
+            return Err(Error::VoiceSpeakerRequired { room_id: room.id });
+        }
+        let VoiceStateUpdate {
+            muted,
+            deafened,
+            speaking,
+            video,
+        } = state;
+
+        let participant = sqlx::query_as::<_, VoiceParticipant>(&format!(
+            r#"
+            UPDATE room_participants SET
+                muted = COALESCE($3, muted),
+                deafened = COALESCE($4, deafened),
+                speaking = COALESCE($5, speaking),
+                video = COALESCE($6, video)
+            WHERE room_id = $1 AND user_id = $2
+            RETURNING {PARTICIPANT_COLUMNS}
+            "#
+        ))
+        .bind(room.id)
+        .bind(ctx.user_id())
+        .bind(muted)
+        .bind(deafened)
+        .bind(speaking)
+        .bind(video)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::VoiceNotJoined { room_id: room.id })?;
+        mm.audio_relay
+            .set_state(
+                room.id,
+                participant.user_id,
+                participant.peer_state(room.push_to_talk),
+            )
+            .await;
+
+        Ok(participant)
+    }
+
+    pub async fn set_hand_raised(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        raised: bool,
+    ) -> Result<VoiceParticipant> {
+        sqlx::query_as::<_, VoiceParticipant>(&format!(
+            r#"
+            UPDATE room_participants SET hand_raised = $3
+            WHERE room_id = $1 AND user_id = $2
+            RETURNING {PARTICIPANT_COLUMNS}
+            "#
+        ))
+        .bind(room_id)
+        .bind(ctx.user_id())
+        .bind(raised)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::VoiceNotJoined { room_id })
+    }
+
+    /// Grants or revokes the right of `user_id` to unmute in a stage room. Granting lowers
+    /// their hand, revoking also mutes them. Fails with `Error::VoiceNotStage` elsewhere, where
+    /// everyone is a speaker.
+    pub async fn set_speaker(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room: &Room,
+        user_id: i64,
+        speaker: bool,
+    ) -> Result<VoiceParticipant> {
+        if !room.is_stage() {
+            return Err(Error::VoiceNotStage { room_id: room.id });
+        }
+
+        let participant = sqlx::query_as::<_, VoiceParticipant>(&format!(
+            r#"
+            UPDATE room_participants SET
+                speaker = $3,
+                hand_raised = false,
+                muted = muted OR NOT $3,
+                speaking = speaking AND $3
+            WHERE room_id = $1 AND user_id = $2
+            RETURNING {PARTICIPANT_COLUMNS}
+            "#
+        ))
+        .bind(room.id)
+        .bind(user_id)
+        .bind(speaker)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(Error::VoicePeerNotJoined {
+            room_id: room.id,
+            user_id,
+        })?;
+        mm.audio_relay
+            .set_state(room.id, user_id, participant.peer_state(room.push_to_talk))
+            .await;
+
+        Ok(participant)
+    }
+
+    /// Serializes `event` and sends it to every connected participant of the voice room, and
+    /// to `also` when given, e.g. the user who just left.
+    pub async fn broadcast_event(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+        event: &WsEvent,
+        also: Option<i64>,
+    ) -> Result<()> {
+        let mut user_ids: Vec<i64> = Self::list_by_room(ctx, mm, room_id)
+            .await?
+            .into_iter()
+            .map(|participant| participant.user_id)
+            .collect();
+        if let Some(user_id) = also
+            && !user_ids.contains(&user_id)
+        {
+            user_ids.push(user_id);
+        }
+
+        match serde_json::to_string(event) {
+            Ok(json) => mm.ws_broadcast.broadcast_to_users(&user_ids, &json).await,
+            Err(e) => tracing::error!("Failed to serialize WsEvent: {e}"),
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_by_room(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        room_id: i64,
+    ) -> Result<Vec<VoiceParticipant>> {
+        let participants = sqlx::query_as::<_, VoiceParticipant>(&format!(
+            "SELECT {PARTICIPANT_COLUMNS} FROM room_participants WHERE room_id = $1 ORDER BY joined_at"
+        ))
+        .bind(room_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(participants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::room::RoomBmc;
+    use serial_test::serial;
+
+    #[test]
+    fn test_voice_state_normalized() {
+        let state = VoiceStateUpdate {
+            deafened: Some(true),
+            speaking: Some(true),
+            ..Default::default()
+        }
+        .normalized();
+
+        assert_eq!(state.muted, Some(true));
+        assert_eq!(state.speaking, Some(false));
+        assert_eq!(state.video, None);
+        assert!(!state.opens_mic());
+    }
+
+    #[test]
+    fn test_peer_state_push_to_talk() {
+        let fx_participant = VoiceParticipant {
+            room_id: 1,
+            user_id: 2,
+            muted: false,
+            deafened: false,
+            speaking: false,
+            video: false,
+            speaker: true,
+            hand_raised: false,
+            joined_at: Utc::now(),
+        };
+
+        assert!(!fx_participant.peer_state(false).muted);
+        assert!(fx_participant.peer_state(true).muted);
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_join_capacity() -> anyhow::Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let fx_first_id = _dev_utils::seed_user(&mm, "voice_first").await?;
+        let fx_second_id = _dev_utils::seed_user(&mm, "voice_second").await?;
+        let fx_room_id = _dev_utils::seed_room(&mm, "Small voice", "voice").await?;
+        sqlx::query("UPDATE rooms SET voice_capacity = 1 WHERE id = $1")
+            .bind(fx_room_id)
+            .execute(mm.db())
+            .await?;
+        let first_ctx = Ctx::new(fx_first_id)?;
+        let second_ctx = Ctx::new(fx_second_id)?;
+        let room = RoomBmc::get(&first_ctx, &mm, fx_room_id).await?;
+
+        // Execute
+        let participant = VoiceParticipantBmc::join(&first_ctx, &mm, &room).await?;
+        // Rejoining does not count against the capacity
+        VoiceParticipantBmc::join(&first_ctx, &mm, &room).await?;
+        let res = VoiceParticipantBmc::join(&second_ctx, &mm, &room).await;
+
+        // Check
+        assert!(participant.speaker);
+        assert!(!participant.muted);
+        assert!(matches!(res, Err(Error::VoiceRoomFull { capacity: 1, .. })));
+        let participants = VoiceParticipantBmc::list_by_room(&first_ctx, &mm, fx_room_id).await?;
+        assert_eq!(participants.len(), 1);
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_set_speaker_not_stage() -> anyhow::Result<()> {
+        // Setup
+        let mm = _dev_utils::init_test().await;
+        let ctx = Ctx::root_ctx();
+        let fx_room_id = _dev_utils::seed_room(&mm, "Not a stage", "voice").await?;
+        let room = RoomBmc::get(&ctx, &mm, fx_room_id).await?;
+
+        // Execute
+        let res = VoiceParticipantBmc::set_speaker(&ctx, &mm, &room, 1, false).await;
+
+        // Check
+        assert!(matches!(
+            res,
+            Err(Error::VoiceNotStage { room_id }) if room_id == fx_room_id
+        ));
+
+        Ok(())
+    }
+}
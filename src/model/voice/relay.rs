@@ -0,0 +1,198 @@
+//! Minimal server-side audio relay (SFU) for voice rooms.
+//!
+//! Clients send Opus frames as binary websocket messages prefixed with the voice room id.
+//! The relay forwards each frame, prefixed with the room id and the sender id, to the other
+//! participants of the room. Muted senders are dropped and deafened participants receive
+//! nothing. Participant state mirrors `room_participants` and is kept in memory so that
+//! forwarding a frame never touches the database.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Largest Opus payload accepted in a single frame (a 120 ms Opus packet is at most 1275
+/// bytes per frame, this leaves room for multi-frame packets).
+pub const MAX_FRAME_PAYLOAD: usize = 4096;
+
+const ROOM_ID_LEN: usize = 8;
+const USER_ID_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerState {
+    pub muted: bool,
+    pub deafened: bool,
+}
+
+/// The relay state: voice room id -> participant id -> state.
+#[derive(Debug, Default)]
+pub struct RelayRooms {
+    rooms: HashMap<i64, HashMap<i64, PeerState>>,
+}
+
+impl RelayRooms {
+    pub fn join(&mut self, room_id: i64, user_id: i64, state: PeerState) {
+        self.rooms
+            .entry(room_id)
+            .or_default()
+            .insert(user_id, state);
+    }
+
+    pub fn set_state(&mut self, room_id: i64, user_id: i64, state: PeerState) {
+        if let Some(peer) = self
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|peers| peers.get_mut(&user_id))
+        {
+            *peer = state;
+        }
+    }
+
+    pub fn leave(&mut self, room_id: i64, user_id: i64) {
+        if let Some(peers) = self.rooms.get_mut(&room_id) {
+            peers.remove(&user_id);
+            if peers.is_empty() {
+                self.rooms.remove(&room_id);
+            }
+        }
+    }
+
+    pub fn leave_all(&mut self, user_id: i64) {
+        self.rooms.retain(|_, peers| {
+            peers.remove(&user_id);
+            !peers.is_empty()
+        });
+    }
+
+    /// Returns who should receive a frame sent by `from` in `room_id`: every other participant
+    /// who is not deafened. Senders who are not in the room or are muted reach nobody.
+    pub fn recipients(&self, room_id: i64, from: i64) -> Vec<i64> {
+        let Some(peers) = self.rooms.get(&room_id) else {
+            return vec![];
+        };
+        match peers.get(&from) {
+            Some(sender) if !sender.muted => {}
+            _ => return vec![],
+        }
+
+        let mut user_ids: Vec<i64> = peers
+            .iter()
+            .filter(|(user_id, peer)| **user_id != from && !peer.deafened)
+            .map(|(user_id, _)| *user_id)
+            .collect();
+        user_ids.sort_unstable();
+        user_ids
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct AudioRelay {
+    rooms: Arc<RwLock<RelayRooms>>,
+}
+
+impl AudioRelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn join(&self, room_id: i64, user_id: i64, state: PeerState) {
+        self.rooms.write().await.join(room_id, user_id, state);
+    }
+
+    pub async fn set_state(&self, room_id: i64, user_id: i64, state: PeerState) {
+        self.rooms.write().await.set_state(room_id, user_id, state);
+    }
+
+    pub async fn leave(&self, room_id: i64, user_id: i64) {
+        self.rooms.write().await.leave(room_id, user_id);
+    }
+
+    pub async fn leave_all(&self, user_id: i64) {
+        self.rooms.write().await.leave_all(user_id);
+    }
+
+    pub async fn recipients(&self, room_id: i64, from: i64) -> Vec<i64> {
+        self.rooms.read().await.recipients(room_id, from)
+    }
+}
+
+/// Splits a client frame into its room id and Opus payload. Returns `None` for frames that are
+/// too short, empty or larger than `MAX_FRAME_PAYLOAD`.
+pub fn decode_client_frame(frame: &[u8]) -> Option<(i64, &[u8])> {
+    let (room_id, payload) = frame.split_first_chunk::<ROOM_ID_LEN>()?;
+    if payload.is_empty() || payload.len() > MAX_FRAME_PAYLOAD {
+        return None;
+    }
+
+    Some((i64::from_be_bytes(*room_id), payload))
+}
+
+/// Builds the frame sent to listeners: room id, sender id, then the Opus payload.
+pub fn encode_relay_frame(room_id: i64, from: i64, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ROOM_ID_LEN + USER_ID_LEN + payload.len());
+    frame.extend_from_slice(&room_id.to_be_bytes());
+    frame.extend_from_slice(&from.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fx_client_frame(room_id: i64, payload: &[u8]) -> Vec<u8> {
+        let mut frame = room_id.to_be_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_frame_roundtrip_ok() {
+        let fx_opus = [0xf8, 0xff, 0xfe];
+        let frame = fx_client_frame(7, &fx_opus);
+
+        let (room_id, payload) = decode_client_frame(&frame).unwrap();
+        assert_eq!(room_id, 7);
+        assert_eq!(payload, fx_opus);
+
+        let relayed = encode_relay_frame(room_id, 42, payload);
+        assert_eq!(&relayed[..8], &7i64.to_be_bytes());
+        assert_eq!(&relayed[8..16], &42i64.to_be_bytes());
+        assert_eq!(&relayed[16..], &fx_opus);
+    }
+
+    #[test]
+    fn test_decode_client_frame_err() {
+        assert!(decode_client_frame(&[0, 1, 2]).is_none());
+        assert!(decode_client_frame(&fx_client_frame(1, &[])).is_none());
+        assert!(decode_client_frame(&fx_client_frame(1, &[0; MAX_FRAME_PAYLOAD + 1])).is_none());
+    }
+
+    #[test]
+    fn test_recipients_honor_mute_and_deafen() {
+        let mut rooms = RelayRooms::default();
+        rooms.join(1, 10, PeerState::default());
+        rooms.join(1, 11, PeerState::default());
+        rooms.join(1, 12, PeerState::default());
+        rooms.join(2, 20, PeerState::default());
+
+        assert_eq!(rooms.recipients(1, 10), vec![11, 12]);
+        assert!(rooms.recipients(1, 20).is_empty(), "not in the room");
+
+        rooms.set_state(
+            1,
+            12,
+            PeerState {
+                muted: true,
+                deafened: true,
+            },
+        );
+        assert_eq!(rooms.recipients(1, 10), vec![11]);
+        assert!(rooms.recipients(1, 12).is_empty(), "muted sender");
+
+        rooms.leave_all(11);
+        assert!(rooms.recipients(1, 10).is_empty());
+        rooms.leave(1, 10);
+        rooms.leave(1, 12);
+        assert!(!rooms.rooms.contains_key(&1));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::ctx::Ctx;
+use crate::model::base::DbBmc;
+use crate::model::permission::Role;
+use crate::model::{Error, ModelManager, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::str::FromStr;
+type UtcDateTime = DateTime<Utc>;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct WorkspaceMember {
+    pub user_id: i64,
+    pub username: String,
+    pub role: String,
+    pub joined_at: UtcDateTime,
+}
+
+pub struct WorkspaceMemberBmc;
+
+impl DbBmc for WorkspaceMemberBmc {
+    const TABLE: &'static str = "workspace_members";
+}
+
+impl WorkspaceMemberBmc {
+    /// Adds `user_id` to the workspace with `role`; existing members keep their current role.
+    pub async fn add(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_members (workspace_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .bind(role.as_ref())
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Adds a newly created user to every `auto_join` workspace.
+    pub async fn add_to_auto_join(mm: &ModelManager, user_id: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_members (workspace_id, user_id)
+            SELECT id, $1 FROM workspaces WHERE auto_join
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes `user_id` from the workspace and from every room inside it.
+    pub async fn remove(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        user_id: i64,
+    ) -> Result<()> {
+        let mut tx = mm.db().begin().await?;
+
+        let count =
+            sqlx::query("DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2")
+                .bind(workspace_id)
+                .bind(user_id)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+        if count == 0 {
+            return Err(Error::WorkspaceMemberNotFound {
+                workspace_id,
+                user_id,
+            });
+        }
+
+        sqlx::query(
+            r#"
+            DELETE FROM room_members
+            WHERE user_id = $2
+              AND room_id IN (SELECT id FROM rooms WHERE workspace_id = $1)
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn set_role(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> Result<()> {
+        let count = sqlb::update()
+            .table(Self::TABLE)
+            .and_where("workspace_id", "=", workspace_id)
+            .and_where("user_id", "=", user_id)
+            .data(vec![("role", role.as_ref().to_string()).into()])
+            .exec(mm.db())
+            .await?;
+
+        if count == 0 {
+            Err(Error::WorkspaceMemberNotFound {
+                workspace_id,
+                user_id,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the stored role of `user_id` in the workspace, `None` when not a member.
+    pub async fn role_of(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+        user_id: i64,
+    ) -> Result<Option<Role>> {
+        let role: Option<(String,)> = sqlx::query_as(
+            "SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .fetch_optional(mm.db())
+        .await?;
+
+        role.map(|(role,)| Role::from_str(&role).map_err(|_| Error::RoleInvalid { role }))
+            .transpose()
+    }
+
+    pub async fn list_by_workspace(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+    ) -> Result<Vec<WorkspaceMember>> {
+        let members = sqlx::query_as::<_, WorkspaceMember>(
+            r#"
+            SELECT m.user_id, u.username, m.role, m.joined_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.workspace_id = $1
+            ORDER BY m.joined_at
+            "#,
+        )
+        .bind(workspace_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(members)
+    }
+
+    pub async fn list_user_ids(
+        _ctx: &Ctx,
+        mm: &ModelManager,
+        workspace_id: i64,
+    ) -> Result<Vec<i64>> {
+        let ids: Vec<(i64,)> =
+            sqlx::query_as("SELECT user_id FROM workspace_members WHERE workspace_id = $1")
+                .bind(workspace_id)
+                .fetch_all(mm.db())
+                .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+}
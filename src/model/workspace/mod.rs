@@ -0,0 +1,82 @@
+use crate::ctx::Ctx;
+use crate::model::attachment::AttachmentBmc;
+use crate::model::base;
+use crate::model::base::DbBmc;
+use crate::model::permission::Role;
+use crate::model::{ModelManager, Result};
+use serde::{Deserialize, Serialize};
+use sqlb::Fields;
+use sqlx::FromRow;
+
+pub mod member;
+
+use self::member::WorkspaceMemberBmc;
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct Workspace {
+    pub id: i64,
+    pub name: String,
+    pub auto_join: bool,
+}
+
+#[derive(Fields, Deserialize)]
+pub struct WorkspaceCreate {
+    pub name: String,
+}
+
+#[derive(Fields, Deserialize)]
+pub struct WorkspaceUpdate {
+    pub name: Option<String>,
+    pub auto_join: Option<bool>,
+}
+
+pub struct WorkspaceBmc;
+
+impl DbBmc for WorkspaceBmc {
+    const TABLE: &'static str = "workspaces";
+}
+
+impl WorkspaceBmc {
+    pub async fn create(ctx: &Ctx, mm: &ModelManager, workspace_c: WorkspaceCreate) -> Result<i64> {
+        let id = base::create::<Self, _>(ctx, mm, workspace_c).await?;
+        WorkspaceMemberBmc::add(ctx, mm, id, ctx.user_id(), Role::Owner).await?;
+
+        Ok(id)
+    }
+
+    pub async fn get(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<Workspace> {
+        base::get::<Self, _>(ctx, mm, id).await
+    }
+
+    /// Lists the workspaces the ctx user belongs to.
+    pub async fn list(ctx: &Ctx, mm: &ModelManager) -> Result<Vec<Workspace>> {
+        let workspaces = sqlx::query_as::<_, Workspace>(
+            r#"
+            SELECT w.id, w.name, w.auto_join
+            FROM workspaces w
+            JOIN workspace_members m ON m.workspace_id = w.id
+            WHERE m.user_id = $1
+            ORDER BY w.id
+            "#,
+        )
+        .bind(ctx.user_id())
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(workspaces)
+    }
+
+    pub async fn update(
+        ctx: &Ctx,
+        mm: &ModelManager,
+        id: i64,
+        workspace_u: WorkspaceUpdate,
+    ) -> Result<()> {
+        base::update::<Self, _>(ctx, mm, id, workspace_u).await
+    }
+
+    pub async fn delete(ctx: &Ctx, mm: &ModelManager, id: i64) -> Result<()> {
+        AttachmentBmc::delete_by_workspace(ctx, mm, id).await?;
+        base::delete::<Self>(ctx, mm, id).await
+    }
+}
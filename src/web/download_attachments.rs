@@ -0,0 +1,294 @@
+use crate::AppState;
+use crate::Ctx;
+use crate::media::thumbnail::RENDITION_CONTENT_TYPE;
+use crate::model::attachment::{Attachment, AttachmentBmc, AttachmentKind};
+use crate::model::messages::MessageBmc;
+use crate::model::room::RoomBmc;
+use crate::model::{self, blob};
+use crate::web::error::{Error, Result};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, IF_NONE_MATCH, IF_RANGE, RANGE, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use uuid::Uuid;
+
+/// Serves an attachment, or the thumbnail or preview of an image, to users who can see the
+/// room of the message it is attached to.
+///
+/// `file_name` is the last segment of one of the attachment keys, e.g. `<sha256>.pdf` or
+/// `<sha256>_256.webp`. Attachments the caller cannot see are reported as not found so ids
+/// cannot be probed.
+pub async fn download_attachment(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    Path((id, file_name)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let mm = &state.mm;
+
+    let attachment = AttachmentBmc::get(&ctx, mm, id).await?;
+    let (room_id, _) = MessageBmc::room_and_author(&ctx, mm, attachment.message_id).await?;
+    RoomBmc::get_visible(&ctx, mm, room_id)
+        .await
+        .map_err(|_| Error::DownloadNotFound)?;
+
+    let (key, content_type) =
+        select_file(&attachment, &file_name).ok_or(Error::DownloadNotFound)?;
+    // Stored files never change, the key identifies the content
+    let etag = format!("\"{file_name}\"");
+
+    let mut builder = Response::builder()
+        .header(ETAG, &etag)
+        .header(CACHE_CONTROL, "private, max-age=31536000, immutable")
+        .header(ACCEPT_RANGES, "bytes")
+        .header(X_CONTENT_TYPE_OPTIONS, "nosniff");
+
+    if etag_matches(headers.get(IF_NONE_MATCH), &etag) {
+        return build(builder.status(StatusCode::NOT_MODIFIED), Body::empty());
+    }
+
+    let store = mm.blob_store();
+    let len = store.size(key).await.map_err(download_error)?;
+
+    // Only images render in the browser, anything else is saved to disk
+    let disposition = match attachment.details.kind() {
+        AttachmentKind::Image => "inline",
+        _ => "attachment",
+    };
+    builder = builder.header(CONTENT_TYPE, content_type).header(
+        CONTENT_DISPOSITION,
+        content_disposition(disposition, &download_name(&attachment, key)),
+    );
+
+    // A stale If-Range means the client's partial copy is outdated, send everything
+    let range = headers
+        .get(RANGE)
+        .filter(|_| {
+            headers
+                .get(IF_RANGE)
+                .is_none_or(|v| v.as_bytes() == etag.as_bytes())
+        })
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| parse_range(v, len));
+
+    // The body streams from the store, only the requested range is read
+    match range {
+        None => {
+            let stream = store.get_stream(key, None).await.map_err(download_error)?;
+            build(
+                builder.status(StatusCode::OK).header(CONTENT_LENGTH, len),
+                Body::from_stream(stream),
+            )
+        }
+        Some(Ok((start, end))) => {
+            let stream = store
+                .get_stream(key, Some((start, end)))
+                .await
+                .map_err(download_error)?;
+            build(
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {start}-{end}/{len}"))
+                    .header(CONTENT_LENGTH, end - start + 1),
+                Body::from_stream(stream),
+            )
+        }
+        Some(Err(RangeNotSatisfiable)) => build(
+            builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{len}")),
+            Body::empty(),
+        ),
+    }
+}
+
+/// A file missing from the store is reported as not found, like a hidden attachment.
+fn download_error(err: blob::Error) -> Error {
+    match err {
+        blob::Error::NotFound { .. } => Error::DownloadNotFound,
+        err => model::Error::from(err).into(),
+    }
+}
+
+fn build(builder: axum::http::response::Builder, body: Body) -> Result<Response> {
+    builder
+        .body(body)
+        .map_err(|err| Error::DownloadFailResponse(err.to_string()))
+}
+
+/// Picks the stored file named `file_name` among the original and its renditions.
+fn select_file<'a>(attachment: &'a Attachment, file_name: &str) -> Option<(&'a str, &'a str)> {
+    attachment
+        .storage_keys()
+        .into_iter()
+        .find(|key| key.rsplit('/').next() == Some(file_name))
+        .map(|key| {
+            if key == attachment.storage_key {
+                (key, attachment.content_type.as_str())
+            } else {
+                (key, RENDITION_CONTENT_TYPE)
+            }
+        })
+}
+
+/// The original file name, with the extension of the served file for renditions.
+fn download_name(attachment: &Attachment, key: &str) -> String {
+    if key == attachment.storage_key {
+        return attachment.filename.clone();
+    }
+    let stem = attachment
+        .filename
+        .rsplit_once('.')
+        .map_or(attachment.filename.as_str(), |(stem, _)| stem);
+    format!("{stem}.webp")
+}
+
+fn etag_matches(if_none_match: Option<&HeaderValue>, etag: &str) -> bool {
+    let Some(value) = if_none_match.and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    value
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// `disposition` (`inline` or `attachment`) with an ASCII fallback name and the exact UTF-8
+/// name (RFC 6266).
+fn content_disposition(disposition: &str, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect();
+
+    format!("{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+#[derive(Debug, PartialEq)]
+struct RangeNotSatisfiable;
+
+/// Parses a single `bytes` range into inclusive offsets. Returns `None` when the header
+/// should be ignored (other units, several ranges, malformed) and the whole file served.
+fn parse_range(
+    header: &str,
+    len: u64,
+) -> Option<core::result::Result<(u64, u64), RangeNotSatisfiable>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = match (start.is_empty(), end.is_empty()) {
+        // Suffix: the last `end` bytes
+        (true, false) => {
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(Err(RangeNotSatisfiable));
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (false, _) => {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = if end.is_empty() {
+                u64::MAX
+            } else {
+                end.parse().ok()?
+            };
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                return Some(Err(RangeNotSatisfiable));
+            }
+            (start, end.min(len - 1))
+        }
+        (true, true) => return None,
+    };
+
+    Some(Ok(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range_ok() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        assert_eq!(parse_range("bytes=990-5000", 1000), Some(Ok((990, 999))));
+    }
+
+    #[test]
+    fn test_parse_range_ignored_or_unsatisfiable() {
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("bytes=9-1", 1000), None);
+        assert_eq!(parse_range("bytes=abc", 1000), None);
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            Some(Err(RangeNotSatisfiable))
+        );
+        assert_eq!(
+            parse_range("bytes=-0", 1000),
+            Some(Err(RangeNotSatisfiable))
+        );
+    }
+
+    #[test]
+    fn test_content_disposition_escapes_name() {
+        assert_eq!(
+            content_disposition("inline", "a \"b\".png"),
+            "inline; filename=\"a _b_.png\"; filename*=UTF-8''a%20%22b%22.png"
+        );
+        assert_eq!(
+            content_disposition("attachment", "été.pdf"),
+            "attachment; filename=\"_t_.pdf\"; filename*=UTF-8''%C3%A9t%C3%A9.pdf"
+        );
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = "\"x.png\"";
+        assert!(etag_matches(
+            Some(&HeaderValue::from_static("\"a\", \"x.png\"")),
+            etag
+        ));
+        assert!(etag_matches(
+            Some(&HeaderValue::from_static("W/\"x.png\"")),
+            etag
+        ));
+        assert!(etag_matches(Some(&HeaderValue::from_static("*")), etag));
+        assert!(!etag_matches(
+            Some(&HeaderValue::from_static("\"y.png\"")),
+            etag
+        ));
+        assert!(!etag_matches(None, etag));
+    }
+}
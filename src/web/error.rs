@@ -1,4 +1,7 @@
-use crate::{crypt, model, web};
+use crate::model::attachment::QuotaScope;
+use crate::model::permission::Permissions;
+use crate::model::room::RoomKind;
+use crate::{crypt, media, model, web};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde::Serialize;
@@ -19,12 +22,25 @@ pub enum Error {
     LoginFailPwdNotMatching { user_id: i64 },
 
     FailedToGetMessageByRoomId,
+
+    // Uploads
+    UploadInvalidMultipart,
+    UploadMissingFields,
+    UploadInvalidParams,
+    UploadChecksumMismatch,
+    UploadChunkInProgress,
+    UploadStaging(String),
+    UploadQuarantined { quarantine_id: uuid::Uuid },
+    DownloadNotFound,
+    DownloadFailResponse(String),
     //CtxExtError
     CtxExt(web::middleware::auth::CtxExtError),
 
     //Model Errors
     Model(model::Error),
     Crypt(crypt::Error),
+    Media(media::Error),
+    Scan(media::scan::Error),
 
     // External Modules
     SerdeJson(String),
@@ -39,12 +55,24 @@ impl From<model::Error> for Error {
     }
 }
 
+impl From<media::scan::Error> for Error {
+    fn from(val: media::scan::Error) -> Self {
+        Self::Scan(val)
+    }
+}
+
 impl From<crypt::Error> for Error {
     fn from(val: crypt::Error) -> Self {
         Self::Crypt(val)
     }
 }
 
+impl From<media::Error> for Error {
+    fn from(val: media::Error) -> Self {
+        Self::Media(val)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(val: serde_json::Error) -> Self {
         Self::SerdeJson(val.to_string())
@@ -64,11 +92,239 @@ impl Error {
             // Auth
             CtxExt(_) => (StatusCode::FORBIDDEN, ClientError::NO_AUTH),
 
+            // Uploads
+            UploadInvalidMultipart | UploadMissingFields | UploadInvalidParams => {
+                (StatusCode::BAD_REQUEST, ClientError::INVALID_PARAMS)
+            }
+            Media(media::Error::TooLarge { max_bytes }) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ClientError::UPLOAD_TOO_LARGE {
+                    max_bytes: *max_bytes,
+                },
+            ),
+            Media(media::Error::DimensionsTooLarge { max_dimension }) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ClientError::UPLOAD_DIMENSIONS_TOO_LARGE {
+                    max_dimension: *max_dimension,
+                },
+            ),
+            Media(media::Error::UnsupportedFormat { .. }) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ClientError::UPLOAD_UNSUPPORTED_TYPE {
+                    allowed: media::allowed_types(),
+                },
+            ),
+            Media(media::Error::Undecodable(_)) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ClientError::UPLOAD_INVALID_IMAGE,
+            ),
+            UploadChecksumMismatch => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ClientError::UPLOAD_CHECKSUM_MISMATCH,
+            ),
+            UploadChunkInProgress => (StatusCode::CONFLICT, ClientError::UPLOAD_CHUNK_IN_PROGRESS),
+            UploadQuarantined { .. } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ClientError::UPLOAD_QUARANTINED,
+            ),
+            // Without a verdict the file is refused, the client can retry later
+            Scan(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                ClientError::UPLOAD_SCAN_UNAVAILABLE,
+            ),
+            Model(model::Error::UploadSessionNotFound { .. }) => {
+                (StatusCode::NOT_FOUND, ClientError::UPLOAD_SESSION_NOT_FOUND)
+            }
+            Model(model::Error::UploadOffsetMismatch { expected, .. }) => (
+                StatusCode::CONFLICT,
+                ClientError::UPLOAD_OFFSET_MISMATCH {
+                    expected: *expected,
+                },
+            ),
+            Model(model::Error::UploadSessionIncomplete {
+                received_bytes,
+                size_bytes,
+                ..
+            }) => (
+                StatusCode::CONFLICT,
+                ClientError::UPLOAD_INCOMPLETE {
+                    received_bytes: *received_bytes,
+                    size_bytes: *size_bytes,
+                },
+            ),
+            Model(model::Error::StorageQuotaExceeded {
+                scope,
+                used_bytes,
+                quota_bytes,
+                ..
+            }) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ClientError::QUOTA_EXCEEDED {
+                    scope: *scope,
+                    used_bytes: *used_bytes,
+                    quota_bytes: *quota_bytes,
+                },
+            ),
+
             // Model
             Model(model::Error::EntityNotFound { entity, id }) => (
                 StatusCode::BAD_REQUEST,
                 ClientError::ENTITY_NOT_FOUND { entity, id: *id },
             ),
+            Model(model::Error::UsernameNotFound { username }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::USERNAME_NOT_FOUND {
+                    username: username.clone(),
+                },
+            ),
+
+            // Rooms
+            Model(model::Error::RoomAccessDenied { room_id }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::ROOM_ACCESS_DENIED { room_id: *room_id },
+            ),
+            Model(model::Error::RoomMemberNotFound { room_id, user_id }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::ROOM_MEMBER_NOT_FOUND {
+                    room_id: *room_id,
+                    user_id: *user_id,
+                },
+            ),
+            Model(
+                model::Error::RoomInvalidVisibility { .. }
+                | model::Error::RoomInvalidSlowMode { .. }
+                | model::Error::RoomInvalidVoiceCapacity { .. }
+                | model::Error::RoomCategoryInvalid { .. },
+            ) => (StatusCode::BAD_REQUEST, ClientError::INVALID_PARAMS),
+            Model(model::Error::RoomArchived { room_id }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::ROOM_ARCHIVED { room_id: *room_id },
+            ),
+            Model(model::Error::RoomKindUnsupported { room_id, kind }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::ROOM_KIND_UNSUPPORTED {
+                    room_id: *room_id,
+                    kind: *kind,
+                },
+            ),
+            Model(model::Error::RoomSlowMode {
+                room_id,
+                retry_after_sec,
+            }) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                ClientError::SLOW_MODE {
+                    room_id: *room_id,
+                    retry_after_sec: *retry_after_sec,
+                },
+            ),
+            Model(model::Error::RoomOrderInvalid { expected }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::ROOM_ORDER_INVALID {
+                    expected: expected.clone(),
+                },
+            ),
+
+            // Permissions
+            Model(model::Error::PermissionDenied { room_id, missing }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::PERMISSION_DENIED {
+                    room_id: *room_id,
+                    missing: *missing,
+                },
+            ),
+
+            Model(model::Error::WorkspacePermissionDenied {
+                workspace_id,
+                missing,
+            }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::WORKSPACE_PERMISSION_DENIED {
+                    workspace_id: *workspace_id,
+                    missing: *missing,
+                },
+            ),
+
+            // Workspaces
+            Model(model::Error::WorkspaceAccessDenied { workspace_id }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::WORKSPACE_ACCESS_DENIED {
+                    workspace_id: *workspace_id,
+                },
+            ),
+            Model(model::Error::WorkspaceMemberNotFound {
+                workspace_id,
+                user_id,
+            }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::WORKSPACE_MEMBER_NOT_FOUND {
+                    workspace_id: *workspace_id,
+                    user_id: *user_id,
+                },
+            ),
+
+            // Blocks
+            Model(model::Error::UserBlocked { .. }) => {
+                (StatusCode::FORBIDDEN, ClientError::USER_BLOCKED)
+            }
+            Model(model::Error::CannotBlockSelf) => {
+                (StatusCode::BAD_REQUEST, ClientError::INVALID_PARAMS)
+            }
+
+            // Voice
+            Model(model::Error::VoiceNotJoined { room_id }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::VOICE_NOT_JOINED { room_id: *room_id },
+            ),
+            Model(model::Error::VoicePeerNotJoined { room_id, user_id }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::VOICE_PEER_NOT_JOINED {
+                    room_id: *room_id,
+                    user_id: *user_id,
+                },
+            ),
+
+            Model(model::Error::VoiceRoomFull { room_id, capacity }) => (
+                StatusCode::CONFLICT,
+                ClientError::VOICE_ROOM_FULL {
+                    room_id: *room_id,
+                    capacity: *capacity,
+                },
+            ),
+            Model(model::Error::VoiceSpeakerRequired { room_id }) => (
+                StatusCode::FORBIDDEN,
+                ClientError::VOICE_SPEAKER_REQUIRED { room_id: *room_id },
+            ),
+            Model(model::Error::VoiceNotStage { room_id }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::VOICE_NOT_STAGE { room_id: *room_id },
+            ),
+
+            // Invites
+            Model(model::Error::InviteNotFound { code }) => (
+                StatusCode::BAD_REQUEST,
+                ClientError::INVITE_NOT_FOUND { code: code.clone() },
+            ),
+            Model(
+                model::Error::InviteExpired { code }
+                | model::Error::InviteRevoked { code }
+                | model::Error::InviteUsedUp { code },
+            ) => (
+                StatusCode::GONE,
+                ClientError::INVITE_INVALID { code: code.clone() },
+            ),
+            Model(
+                model::Error::InviteInvalidMaxUses { .. }
+                | model::Error::InviteInvalidExpiry { .. },
+            ) => (StatusCode::BAD_REQUEST, ClientError::INVALID_PARAMS),
+            Model(model::Error::InviteAlreadyUsed { code }) => (
+                StatusCode::CONFLICT,
+                ClientError::INVITE_ALREADY_USED { code: code.clone() },
+            ),
+
+            // Attachments
+            Model(model::Error::AttachmentNotFound { .. }) | DownloadNotFound => {
+                (StatusCode::NOT_FOUND, ClientError::ATTACHMENT_NOT_FOUND)
+            }
 
             // Fallback
             _ => (
@@ -85,7 +341,105 @@ impl Error {
 pub enum ClientError {
     LOGIN_FAIL,
     NO_AUTH,
-    ENTITY_NOT_FOUND { entity: &'static str, id: i64 },
+    ENTITY_NOT_FOUND {
+        entity: &'static str,
+        id: i64,
+    },
+    USERNAME_NOT_FOUND {
+        username: String,
+    },
+    ROOM_ACCESS_DENIED {
+        room_id: i64,
+    },
+    ROOM_MEMBER_NOT_FOUND {
+        room_id: i64,
+        user_id: i64,
+    },
+    ROOM_ARCHIVED {
+        room_id: i64,
+    },
+    ROOM_KIND_UNSUPPORTED {
+        room_id: i64,
+        kind: RoomKind,
+    },
+    SLOW_MODE {
+        room_id: i64,
+        retry_after_sec: i64,
+    },
+    ROOM_ORDER_INVALID {
+        expected: Vec<i64>,
+    },
+    PERMISSION_DENIED {
+        room_id: i64,
+        missing: Permissions,
+    },
+    WORKSPACE_PERMISSION_DENIED {
+        workspace_id: i64,
+        missing: Permissions,
+    },
+    WORKSPACE_ACCESS_DENIED {
+        workspace_id: i64,
+    },
+    WORKSPACE_MEMBER_NOT_FOUND {
+        workspace_id: i64,
+        user_id: i64,
+    },
+    USER_BLOCKED,
+    VOICE_NOT_JOINED {
+        room_id: i64,
+    },
+    VOICE_PEER_NOT_JOINED {
+        room_id: i64,
+        user_id: i64,
+    },
+    VOICE_ROOM_FULL {
+        room_id: i64,
+        capacity: i32,
+    },
+    VOICE_SPEAKER_REQUIRED {
+        room_id: i64,
+    },
+    VOICE_NOT_STAGE {
+        room_id: i64,
+    },
+    INVITE_NOT_FOUND {
+        code: String,
+    },
+    INVITE_INVALID {
+        code: String,
+    },
+    INVITE_ALREADY_USED {
+        code: String,
+    },
+    UPLOAD_TOO_LARGE {
+        max_bytes: usize,
+    },
+    UPLOAD_DIMENSIONS_TOO_LARGE {
+        max_dimension: u32,
+    },
+    UPLOAD_UNSUPPORTED_TYPE {
+        allowed: Vec<&'static str>,
+    },
+    UPLOAD_INVALID_IMAGE,
+    UPLOAD_CHECKSUM_MISMATCH,
+    UPLOAD_CHUNK_IN_PROGRESS,
+    UPLOAD_QUARANTINED,
+    UPLOAD_SCAN_UNAVAILABLE,
+    UPLOAD_SESSION_NOT_FOUND,
+    UPLOAD_OFFSET_MISMATCH {
+        expected: i64,
+    },
+    UPLOAD_INCOMPLETE {
+        received_bytes: i64,
+        size_bytes: i64,
+    },
+    QUOTA_EXCEEDED {
+        scope: QuotaScope,
+        used_bytes: i64,
+        quota_bytes: i64,
+    },
+    ATTACHMENT_NOT_FOUND,
+    INVALID_PARAMS,
     SERVICE_ERROR,
 }
 
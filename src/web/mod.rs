@@ -1,6 +1,9 @@
+pub mod download_attachments;
 pub mod error;
 pub mod middleware;
 pub mod routes;
 pub mod rpc;
-pub mod upload_images;
+pub mod staging;
+pub mod upload_attachments;
+pub mod upload_sessions;
 pub mod websockets;
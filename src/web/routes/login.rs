@@ -1,5 +1,5 @@
 use crate::crypt::EncryptContent;
-use crate::crypt::pwd;
+use crate::crypt::pwd::{self, SchemeStatus};
 use crate::crypt::token::generate_web_token;
 use crate::ctx::Ctx;
 use crate::model::ModelManager;
@@ -15,7 +15,7 @@ use axum::{Json, Router};
 use serde::Deserialize;
 use serde_json::{Value, json};
 use tower_cookies::Cookies;
-use tracing::debug;
+use tracing::{debug, warn};
 
 #[derive(Debug, Deserialize)]
 struct LoginPayload {
@@ -108,15 +108,27 @@ async fn api_login_handler(
         return Err(Error::LoginFailUserHasNoPwd { user_id });
     };
 
-    pwd::validate_pwd(
-        &EncryptContent {
+    let scheme_status = pwd::validate_pwd(
+        EncryptContent {
             salt: user.pwd_salt.to_string(),
             content: pwd_clear.clone(),
         },
-        &pwd,
+        pwd,
     )
+    .await
     .map_err(|_| Error::LoginFailPwdNotMatching { user_id })?;
 
+    // -- Upgrade a password hashed with an older scheme, retried on the next login if it fails
+    if let SchemeStatus::Outdated = scheme_status {
+        debug!("{:<12} - pwd encrypt scheme outdated, upgrading", "HANDLER");
+        if let Err(err) = UserBmc::update_pwd(&root_ctx, &mm, user_id, &pwd_clear).await {
+            warn!(
+                "{:<12} - pwd upgrade failed for user {user_id}: {err:?}",
+                "HANDLER"
+            );
+        }
+    }
+
     let token = generate_web_token(&user.username, &user.token_salt.to_string())?;
     let token_str = token.to_string();
 
@@ -0,0 +1,23 @@
+use crate::ctx::Ctx;
+use crate::model::ModelManager;
+use crate::model::block::{BlockedUser, UserBlockBmc};
+use crate::web::error::Result;
+
+use super::ParamsIded;
+
+pub async fn block_user(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<i64> {
+    let ParamsIded { id } = params;
+    UserBlockBmc::block(&ctx, &mm, id).await?;
+    Ok(id)
+}
+
+pub async fn unblock_user(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<i64> {
+    let ParamsIded { id } = params;
+    UserBlockBmc::unblock(&ctx, &mm, id).await?;
+    Ok(id)
+}
+
+pub async fn list_blocked_users(ctx: Ctx, mm: ModelManager) -> Result<Vec<BlockedUser>> {
+    let blocked = UserBlockBmc::list_blocked(&ctx, &mm).await?;
+    Ok(blocked)
+}
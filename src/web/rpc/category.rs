@@ -0,0 +1,94 @@
+use crate::ctx::Ctx;
+use crate::model;
+use crate::model::ModelManager;
+use crate::model::permission::{PermissionBmc, Permissions};
+use crate::model::room::category::{
+    RoomCategory, RoomCategoryBmc, RoomCategoryCreate, RoomCategoryUpdate,
+};
+use crate::web::error::Result;
+use serde::Deserialize;
+
+use super::room::ParamsWorkspace;
+use super::{ParamsForCreate, ParamsForUpdate, ParamsIded};
+
+#[derive(Deserialize)]
+pub struct ParamsReorderCategories {
+    pub workspace_id: i64,
+    pub category_ids: Vec<i64>,
+}
+
+pub async fn create_category(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreate<RoomCategoryCreate>,
+) -> Result<RoomCategory> {
+    let ParamsForCreate { data } = params;
+    PermissionBmc::require_in_workspace(&ctx, &mm, data.workspace_id, Permissions::MANAGE_ROOM)
+        .await?;
+
+    let id = RoomCategoryBmc::create(&ctx, &mm, data).await?;
+    let category = RoomCategoryBmc::get(&ctx, &mm, id).await?;
+    Ok(category)
+}
+
+pub async fn list_categories(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsWorkspace,
+) -> Result<Vec<RoomCategory>> {
+    let ParamsWorkspace { workspace_id } = params;
+    if PermissionBmc::role_in_workspace(&ctx, &mm, workspace_id)
+        .await?
+        .is_none()
+    {
+        return Err(model::Error::WorkspaceAccessDenied { workspace_id }.into());
+    }
+
+    let categories = RoomCategoryBmc::list(&ctx, &mm, workspace_id).await?;
+    Ok(categories)
+}
+
+pub async fn update_category(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForUpdate<RoomCategoryUpdate>,
+) -> Result<RoomCategory> {
+    let ParamsForUpdate { id, data } = params;
+    let category = RoomCategoryBmc::get(&ctx, &mm, id).await?;
+    PermissionBmc::require_in_workspace(&ctx, &mm, category.workspace_id, Permissions::MANAGE_ROOM)
+        .await?;
+
+    RoomCategoryBmc::update(&ctx, &mm, id, data).await?;
+    let category = RoomCategoryBmc::get(&ctx, &mm, id).await?;
+    Ok(category)
+}
+
+pub async fn delete_category(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<RoomCategory> {
+    let ParamsIded { id } = params;
+    let category = RoomCategoryBmc::get(&ctx, &mm, id).await?;
+    PermissionBmc::require_in_workspace(&ctx, &mm, category.workspace_id, Permissions::MANAGE_ROOM)
+        .await?;
+
+    RoomCategoryBmc::delete(&ctx, &mm, id).await?;
+    Ok(category)
+}
+
+pub async fn reorder_categories(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsReorderCategories,
+) -> Result<Vec<RoomCategory>> {
+    let ParamsReorderCategories {
+        workspace_id,
+        category_ids,
+    } = params;
+    PermissionBmc::require_in_workspace(&ctx, &mm, workspace_id, Permissions::MANAGE_ROOM).await?;
+
+    RoomCategoryBmc::reorder(&ctx, &mm, workspace_id, &category_ids).await?;
+    let categories = RoomCategoryBmc::list(&ctx, &mm, workspace_id).await?;
+    Ok(categories)
+}
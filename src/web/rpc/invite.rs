@@ -0,0 +1,154 @@
+use crate::ctx::Ctx;
+use crate::model;
+use crate::model::ModelManager;
+use crate::model::invite::{AcceptedInvite, Invite, InviteBmc, InviteForCreate, InviteUse};
+use crate::model::permission::{PermissionBmc, Permissions, Role};
+use crate::model::room::RoomBmc;
+use crate::web::error::Result;
+use serde::Deserialize;
+
+use super::{ParamsForCreate, ParamsIded};
+
+#[derive(Deserialize)]
+pub struct ParamsInviteScope {
+    pub workspace_id: i64,
+    pub room_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsInviteCode {
+    pub code: String,
+}
+
+/// Creates an invite code. The caller needs `INVITE_MEMBERS` in the invite's scope and can
+/// only hand out roles up to member, or below their own role.
+pub async fn create_invite(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreate<InviteForCreate>,
+) -> Result<Invite> {
+    let ParamsForCreate { data } = params;
+    require_in_scope(
+        &ctx,
+        &mm,
+        data.workspace_id,
+        data.room_id,
+        Permissions::INVITE_MEMBERS,
+    )
+    .await?;
+
+    let role = data.role.unwrap_or(Role::Member);
+    let own = match data.room_id {
+        Some(room_id) => PermissionBmc::role_in_room(&ctx, &mm, room_id).await?,
+        None => PermissionBmc::role_in_workspace(&ctx, &mm, data.workspace_id).await?,
+    };
+    let grantable = ctx.user_id() == 0
+        || !Role::Member.outranks(role)
+        || own.is_some_and(|own| own.outranks(role));
+    if role == Role::Owner || !grantable {
+        return Err(denied(data.workspace_id, data.room_id, Permissions::MANAGE_ROLES).into());
+    }
+
+    let invite = InviteBmc::create(&ctx, &mm, data).await?;
+    Ok(invite)
+}
+
+pub async fn list_invites(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsInviteScope,
+) -> Result<Vec<Invite>> {
+    let ParamsInviteScope {
+        workspace_id,
+        room_id,
+    } = params;
+    require_in_scope(
+        &ctx,
+        &mm,
+        workspace_id,
+        room_id,
+        Permissions::INVITE_MEMBERS,
+    )
+    .await?;
+
+    let invites = InviteBmc::list(&ctx, &mm, workspace_id, room_id).await?;
+    Ok(invites)
+}
+
+pub async fn revoke_invite(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Invite> {
+    let ParamsIded { id } = params;
+    let invite = InviteBmc::get(&ctx, &mm, id).await?;
+    require_invite_manager(&ctx, &mm, &invite).await?;
+
+    InviteBmc::revoke(&ctx, &mm, id).await?;
+    let invite = InviteBmc::get(&ctx, &mm, id).await?;
+    Ok(invite)
+}
+
+pub async fn accept_invite(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsInviteCode,
+) -> Result<AcceptedInvite> {
+    let ParamsInviteCode { code } = params;
+    let accepted = InviteBmc::accept(&ctx, &mm, &code).await?;
+    Ok(accepted)
+}
+
+pub async fn list_invite_uses(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<Vec<InviteUse>> {
+    let ParamsIded { id } = params;
+    let invite = InviteBmc::get(&ctx, &mm, id).await?;
+    require_invite_manager(&ctx, &mm, &invite).await?;
+
+    let uses = InviteBmc::list_uses(&ctx, &mm, id).await?;
+    Ok(uses)
+}
+
+/// Room scoped checks also make sure the room belongs to `workspace_id`.
+async fn require_in_scope(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    workspace_id: i64,
+    room_id: Option<i64>,
+    required: Permissions,
+) -> model::Result<()> {
+    match room_id {
+        Some(room_id) => {
+            let room = RoomBmc::get_visible(ctx, mm, room_id).await?;
+            if room.workspace_id != workspace_id {
+                return Err(model::Error::RoomAccessDenied { room_id });
+            }
+            PermissionBmc::require(ctx, mm, room_id, required).await
+        }
+        None => PermissionBmc::require_in_workspace(ctx, mm, workspace_id, required).await,
+    }
+}
+
+/// The creator of an invite can always manage it, others need to manage its scope.
+async fn require_invite_manager(ctx: &Ctx, mm: &ModelManager, invite: &Invite) -> Result<()> {
+    if invite.created_by == ctx.user_id() {
+        return Ok(());
+    }
+
+    let required = match invite.room_id {
+        Some(_) => Permissions::MANAGE_ROOM,
+        None => Permissions::MANAGE_WORKSPACE,
+    };
+    require_in_scope(ctx, mm, invite.workspace_id, invite.room_id, required).await?;
+
+    Ok(())
+}
+
+fn denied(workspace_id: i64, room_id: Option<i64>, missing: Permissions) -> model::Error {
+    match room_id {
+        Some(room_id) => model::Error::PermissionDenied { room_id, missing },
+        None => model::Error::WorkspacePermissionDenied {
+            workspace_id,
+            missing,
+        },
+    }
+}
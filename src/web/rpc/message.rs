@@ -1,9 +1,12 @@
-use super::ParamsForCreate;
+use super::{ParamsForCreate, ParamsIded};
 use crate::model::ModelManager;
-use crate::model::Result;
 use crate::model::WsEvent;
-use crate::model::messages::{FriendMessage, Message, MessageToFriend, MessageWithImages};
+use crate::model::block::UserBlockBmc;
+use crate::model::messages::{FriendMessage, Message, MessageToFriend, MessageWithAttachments};
+use crate::model::permission::PermissionBmc;
+use crate::model::room::RoomBmc;
 use crate::model::user::{User, UserBmc};
+use crate::web::error::Result;
 use crate::{ctx::Ctx, model::messages::MessageBmc};
 
 #[derive(serde::Serialize)]
@@ -17,17 +20,26 @@ pub async fn send_message(
     params: ParamsForCreate<Message>,
 ) -> Result<MessageResponse> {
     let ParamsForCreate { data } = params;
-    let message = MessageBmc::send_message(&ctx, &mm, data.clone()).await?;
-    let user: User = UserBmc::get(&ctx, &mm, ctx.user_id()).await?;
-    let username = user.username.clone();
+    let id = post_room_message(&ctx, &mm, data).await?;
+    Ok(MessageResponse { id })
+}
 
-    let msg = WsEvent::NewRoomMessage {
-        room_id: data.message_room_id,
-        from: username.clone(),
-        content: data.message_text.clone(),
-    };
+/// Posts a message to a room on behalf of the ctx user and notifies the room. Shared by the
+/// `send_message` RPC and the websocket `send_message` command.
+pub(crate) async fn post_room_message(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    mut data: Message,
+) -> Result<i64> {
+    let room = RoomBmc::get_visible(ctx, mm, data.message_room_id).await?;
+    room.require_not_archived()?;
+    room.require_messages()?;
+    PermissionBmc::require(ctx, mm, room.id, room.room_type.post_permissions()).await?;
+    data.message_user_id = ctx.user_id();
 
-    let text = serde_json::to_string(&msg);
+    let message = MessageBmc::send_message(ctx, mm, &room, data.clone()).await?;
+    let user: User = UserBmc::get(ctx, mm, ctx.user_id()).await?;
+    let username = user.username.clone();
 
     tracing::debug!(
         "Sending websocket message: username = {}, message = {}",
@@ -35,11 +47,51 @@ pub async fn send_message(
         &data.message_text
     );
 
-    mm.ws_broadcast
-        .broadcast_to_room(&username, &data.message_text)
-        .await;
+    // -- Users who blocked the sender get the message flagged, as when listing the room
+    let audience = RoomBmc::audience_user_ids(ctx, mm, &room).await?;
+    let blockers = UserBlockBmc::list_blockers_among(ctx, mm, &audience).await?;
+    let (blocking, others): (Vec<i64>, Vec<i64>) =
+        audience.iter().partition(|id| blockers.contains(id));
+    for (recipients, author_blocked) in [(others, false), (blocking, true)] {
+        if recipients.is_empty() {
+            continue;
+        }
+        let text = serde_json::to_string(&WsEvent::NewRoomMessage {
+            room_id: data.message_room_id,
+            from: username.clone(),
+            content: data.message_text.clone(),
+            author_blocked,
+        })?;
+        mm.ws_broadcast.broadcast_to_users(&recipients, &text).await;
+    }
+
+    // -- Notify mentioned users (blocked senders are filtered out by the model)
+    let mut mentioned = MessageBmc::mention_recipients(ctx, mm, &data.message_text).await?;
+    mentioned.retain(|u| audience.contains(&u.id));
+    let mention = serde_json::to_string(&WsEvent::Mention {
+        room_id: data.message_room_id,
+        message_id: message,
+        from: username.clone(),
+    })?;
+    for user in mentioned {
+        mm.ws_broadcast.broadcast_to_user(user.id, &mention).await;
+    }
+
+    Ok(message)
+}
+
+pub async fn delete_message(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<i64> {
+    let ParamsIded { id } = params;
+    let room_id = MessageBmc::delete(&ctx, &mm, id).await?;
+
+    let room = RoomBmc::get(&ctx, &mm, room_id).await?;
+    let event = serde_json::to_string(&WsEvent::MessageDeleted {
+        room_id,
+        message_id: id,
+    })?;
+    RoomBmc::broadcast(&ctx, &mm, &room, &event).await?;
 
-    Ok(MessageResponse { id: message })
+    Ok(id)
 }
 
 pub async fn send_private_message(
@@ -57,8 +109,9 @@ pub async fn get_messages_by_room_id(
     ctx: Ctx,
     mm: ModelManager,
     params: i64,
-) -> Result<Vec<MessageWithImages>> {
-    let messages = MessageBmc::list_with_images_by_room_id(&ctx, &mm, params).await?;
+) -> Result<Vec<MessageWithAttachments>> {
+    RoomBmc::get_visible(&ctx, &mm, params).await?;
+    let messages = MessageBmc::list_with_attachments_by_room_id(&ctx, &mm, params).await?;
 
     Ok(messages)
 }
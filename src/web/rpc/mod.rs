@@ -5,11 +5,32 @@ use crate::{
     model::user::*,
     web::{
         error::{Error, Result},
+        rpc::block::{block_user, list_blocked_users, unblock_user},
+        rpc::category::{
+            create_category, delete_category, list_categories, reorder_categories, update_category,
+        },
+        rpc::invite::{
+            accept_invite, create_invite, list_invite_uses, list_invites, revoke_invite,
+        },
         rpc::message::{
-            get_messages_by_room_id, get_private_messages, send_message, send_private_message,
+            delete_message, get_messages_by_room_id, get_private_messages, send_message,
+            send_private_message,
+        },
+        rpc::room::{
+            archive_room, create_room, delete_room, get_room_permissions, invite_to_room,
+            join_room, kick_from_room, leave_room, list_room_members, list_rooms, move_room,
+            reorder_rooms, set_member_role, unarchive_room, update_room,
+        },
+        rpc::storage::get_storage_usage,
+        rpc::voice::{
+            approve_speaker, join_voice, leave_voice, list_voice_participants, lower_hand,
+            raise_hand, revoke_speaker, update_voice_state,
+        },
+        rpc::workspace::{
+            add_workspace_member, create_workspace, delete_workspace, kick_from_workspace,
+            leave_workspace, list_workspace_members, list_workspaces, set_workspace_member_role,
+            update_workspace,
         },
-        rpc::room::{create_room, delete_room, list_rooms, update_room},
-        rpc::voice::join_voice,
     },
 };
 
@@ -24,9 +45,17 @@ use log::debug;
 use serde::Deserialize;
 use serde_json::{Value, from_value, json, to_value};
 
+mod block;
+mod category;
+mod invite;
 mod message;
 mod room;
+mod storage;
 mod voice;
+mod workspace;
+
+pub(crate) use self::message::post_room_message;
+pub(crate) use self::voice::{leave_all_voice, relay_rtc_signal};
 
 #[derive(Deserialize)]
 struct RpcRequest {
@@ -107,16 +136,62 @@ async fn _rpc_handler(ctx: Ctx, mm: ModelManager, rpc_req: RpcRequest) -> Result
         // Voice RPC methods
         //"get_audio_room" => exec_rpc_fn!(get_audio_room_info, ctx, mm, rpc_params),
         "join_voice" => exec_rpc_fn!(join_voice, ctx, mm, rpc_params),
+        "leave_voice" => exec_rpc_fn!(leave_voice, ctx, mm, rpc_params),
+        "update_voice_state" => exec_rpc_fn!(update_voice_state, ctx, mm, rpc_params),
+        "list_voice_participants" => exec_rpc_fn!(list_voice_participants, ctx, mm, rpc_params),
+        "raise_hand" => exec_rpc_fn!(raise_hand, ctx, mm, rpc_params),
+        "lower_hand" => exec_rpc_fn!(lower_hand, ctx, mm, rpc_params),
+        "approve_speaker" => exec_rpc_fn!(approve_speaker, ctx, mm, rpc_params),
+        "revoke_speaker" => exec_rpc_fn!(revoke_speaker, ctx, mm, rpc_params),
+
+        // Workspace RPC methods
+        "create_workspace" => exec_rpc_fn!(create_workspace, ctx, mm, rpc_params),
+        "list_workspaces" => exec_rpc_fn!(list_workspaces, ctx, mm),
+        "update_workspace" => exec_rpc_fn!(update_workspace, ctx, mm, rpc_params),
+        "delete_workspace" => exec_rpc_fn!(delete_workspace, ctx, mm, rpc_params),
+        "leave_workspace" => exec_rpc_fn!(leave_workspace, ctx, mm, rpc_params),
+        "add_workspace_member" => exec_rpc_fn!(add_workspace_member, ctx, mm, rpc_params),
+        "kick_from_workspace" => exec_rpc_fn!(kick_from_workspace, ctx, mm, rpc_params),
+        "list_workspace_members" => exec_rpc_fn!(list_workspace_members, ctx, mm, rpc_params),
+        "set_workspace_member_role" => {
+            exec_rpc_fn!(set_workspace_member_role, ctx, mm, rpc_params)
+        }
 
         // Room RPC methods
         "create_room" => exec_rpc_fn!(create_room, ctx, mm, rpc_params),
-        "list_rooms" => exec_rpc_fn!(list_rooms, ctx, mm),
+        "list_rooms" => exec_rpc_fn!(list_rooms, ctx, mm, rpc_params),
         "update_room" => exec_rpc_fn!(update_room, ctx, mm, rpc_params),
         "delete_room" => exec_rpc_fn!(delete_room, ctx, mm, rpc_params),
+        "join_room" => exec_rpc_fn!(join_room, ctx, mm, rpc_params),
+        "leave_room" => exec_rpc_fn!(leave_room, ctx, mm, rpc_params),
+        "invite_to_room" => exec_rpc_fn!(invite_to_room, ctx, mm, rpc_params),
+        "kick_from_room" => exec_rpc_fn!(kick_from_room, ctx, mm, rpc_params),
+        "list_room_members" => exec_rpc_fn!(list_room_members, ctx, mm, rpc_params),
+        "set_member_role" => exec_rpc_fn!(set_member_role, ctx, mm, rpc_params),
+        "get_room_permissions" => exec_rpc_fn!(get_room_permissions, ctx, mm, rpc_params),
+        "archive_room" => exec_rpc_fn!(archive_room, ctx, mm, rpc_params),
+        "unarchive_room" => exec_rpc_fn!(unarchive_room, ctx, mm, rpc_params),
+        "move_room" => exec_rpc_fn!(move_room, ctx, mm, rpc_params),
+        "reorder_rooms" => exec_rpc_fn!(reorder_rooms, ctx, mm, rpc_params),
+
+        // Category RPC methods
+        "create_category" => exec_rpc_fn!(create_category, ctx, mm, rpc_params),
+        "list_categories" => exec_rpc_fn!(list_categories, ctx, mm, rpc_params),
+        "update_category" => exec_rpc_fn!(update_category, ctx, mm, rpc_params),
+        "delete_category" => exec_rpc_fn!(delete_category, ctx, mm, rpc_params),
+        "reorder_categories" => exec_rpc_fn!(reorder_categories, ctx, mm, rpc_params),
+
+        // Invite RPC methods
+        "create_invite" => exec_rpc_fn!(create_invite, ctx, mm, rpc_params),
+        "list_invites" => exec_rpc_fn!(list_invites, ctx, mm, rpc_params),
+        "revoke_invite" => exec_rpc_fn!(revoke_invite, ctx, mm, rpc_params),
+        "accept_invite" => exec_rpc_fn!(accept_invite, ctx, mm, rpc_params),
+        "list_invite_uses" => exec_rpc_fn!(list_invite_uses, ctx, mm, rpc_params),
 
         // Message RPC methods
         "get_messages_by_room_id" => exec_rpc_fn!(get_messages_by_room_id, ctx, mm, rpc_params),
         "send_message" => exec_rpc_fn!(send_message, ctx, mm, rpc_params),
+        "delete_message" => exec_rpc_fn!(delete_message, ctx, mm, rpc_params),
         "send_private_message" => exec_rpc_fn!(send_private_message, ctx, mm, rpc_params),
         "get_private_messages" => exec_rpc_fn!(get_private_messages, ctx, mm, rpc_params),
 
@@ -125,6 +200,14 @@ async fn _rpc_handler(ctx: Ctx, mm: ModelManager, rpc_req: RpcRequest) -> Result
         "get_friends" => exec_rpc_fn!(UserBmc::get_friends, ctx, mm),
         "find_by_id" => exec_rpc_fn!(UserBmc::find_username_by_id, ctx, mm, rpc_params),
 
+        // Storage RPC methods
+        "get_storage_usage" => exec_rpc_fn!(get_storage_usage, ctx, mm, rpc_params),
+
+        // Block RPC methods
+        "block_user" => exec_rpc_fn!(block_user, ctx, mm, rpc_params),
+        "unblock_user" => exec_rpc_fn!(unblock_user, ctx, mm, rpc_params),
+        "list_blocked_users" => exec_rpc_fn!(list_blocked_users, ctx, mm),
+
         // Fallback as Err
         _ => return Err(Error::RpcMethodUnknown(rpc_method)),
     };
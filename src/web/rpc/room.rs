@@ -1,10 +1,55 @@
+use crate::model;
 use crate::model::ModelManager;
+use crate::model::block::UserBlockBmc;
+use crate::model::permission::{PermissionBmc, Permissions, Role};
+use crate::model::room::member::{RoomMember, RoomMemberBmc};
 use crate::model::room::{Room, RoomCreate, RoomUpdate};
+use crate::model::workspace::member::WorkspaceMemberBmc;
 use crate::web::error::Result;
 use crate::{ctx::Ctx, model::room::RoomBmc};
+use serde::{Deserialize, Serialize};
 
 use super::{ParamsForCreate, ParamsForUpdate, ParamsIded};
 
+#[derive(Deserialize)]
+pub struct ParamsRoomUser {
+    pub room_id: i64,
+    pub user_id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsWorkspace {
+    pub workspace_id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsRoomRole {
+    pub room_id: i64,
+    pub user_id: i64,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsMoveRoom {
+    pub room_id: i64,
+    pub category_id: Option<i64>,
+    pub position: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsReorderRooms {
+    pub workspace_id: i64,
+    pub category_id: Option<i64>,
+    pub room_ids: Vec<i64>,
+}
+
+#[derive(Serialize)]
+pub struct RoomPermissions {
+    pub room_id: i64,
+    pub role: Option<Role>,
+    pub permissions: Permissions,
+}
+
 pub async fn create_room(
     ctx: Ctx,
     mm: ModelManager,
@@ -16,8 +61,9 @@ pub async fn create_room(
     Ok(id)
 }
 
-pub async fn list_rooms(ctx: Ctx, mm: ModelManager) -> Result<Vec<Room>> {
-    let rooms = RoomBmc::list(&ctx, &mm).await?;
+pub async fn list_rooms(ctx: Ctx, mm: ModelManager, params: ParamsWorkspace) -> Result<Vec<Room>> {
+    let ParamsWorkspace { workspace_id } = params;
+    let rooms = RoomBmc::list(&ctx, &mm, workspace_id).await?;
     Ok(rooms)
 }
 
@@ -27,6 +73,11 @@ pub async fn update_room(
     params: ParamsForUpdate<RoomUpdate>,
 ) -> Result<Room> {
     let ParamsForUpdate { id, data } = params;
+    let room = RoomBmc::get_visible(&ctx, &mm, id).await?;
+    PermissionBmc::require(&ctx, &mm, id, Permissions::MANAGE_ROOM).await?;
+    if data.slow_mode_sec.is_some_and(|sec| sec > 0) {
+        room.require_messages()?;
+    }
     RoomBmc::update(&ctx, &mm, id, data).await?;
     let room = RoomBmc::get(&ctx, &mm, id).await?;
     Ok(room)
@@ -34,7 +85,176 @@ pub async fn update_room(
 
 pub async fn delete_room(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Room> {
     let ParamsIded { id } = params;
-    let room = RoomBmc::get(&ctx, &mm, id).await?;
+    let room = RoomBmc::get_visible(&ctx, &mm, id).await?;
+    PermissionBmc::require(&ctx, &mm, id, Permissions::MANAGE_ROOM).await?;
     RoomBmc::delete(&ctx, &mm, id).await?;
     Ok(room)
 }
+
+/// Moves a room to another category or position. The sidebar is shared by the whole
+/// workspace, so this needs `MANAGE_ROOM` at the workspace level.
+pub async fn move_room(ctx: Ctx, mm: ModelManager, params: ParamsMoveRoom) -> Result<Room> {
+    let ParamsMoveRoom {
+        room_id,
+        category_id,
+        position,
+    } = params;
+    let room = RoomBmc::get_visible(&ctx, &mm, room_id).await?;
+    PermissionBmc::require_in_workspace(&ctx, &mm, room.workspace_id, Permissions::MANAGE_ROOM)
+        .await?;
+
+    RoomBmc::move_to(&ctx, &mm, room_id, category_id, position).await?;
+    let room = RoomBmc::get(&ctx, &mm, room_id).await?;
+    Ok(room)
+}
+
+pub async fn reorder_rooms(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsReorderRooms,
+) -> Result<Vec<Room>> {
+    let ParamsReorderRooms {
+        workspace_id,
+        category_id,
+        room_ids,
+    } = params;
+    PermissionBmc::require_in_workspace(&ctx, &mm, workspace_id, Permissions::MANAGE_ROOM).await?;
+
+    RoomBmc::reorder(&ctx, &mm, workspace_id, category_id, &room_ids).await?;
+    let rooms = RoomBmc::list(&ctx, &mm, workspace_id).await?;
+    Ok(rooms)
+}
+
+pub async fn archive_room(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Room> {
+    set_archived(ctx, mm, params, true).await
+}
+
+pub async fn unarchive_room(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Room> {
+    set_archived(ctx, mm, params, false).await
+}
+
+async fn set_archived(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+    archived: bool,
+) -> Result<Room> {
+    let ParamsIded { id } = params;
+    RoomBmc::get_visible(&ctx, &mm, id).await?;
+    PermissionBmc::require(&ctx, &mm, id, Permissions::MANAGE_ROOM).await?;
+
+    RoomBmc::set_archived(&ctx, &mm, id, archived).await?;
+    let room = RoomBmc::get(&ctx, &mm, id).await?;
+    Ok(room)
+}
+
+pub async fn join_room(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Room> {
+    let ParamsIded { id } = params;
+    let room = RoomBmc::get_visible(&ctx, &mm, id).await?;
+    RoomMemberBmc::add(&ctx, &mm, id, ctx.user_id(), Role::Member).await?;
+    Ok(room)
+}
+
+pub async fn leave_room(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Room> {
+    let ParamsIded { id } = params;
+    let room = RoomBmc::get(&ctx, &mm, id).await?;
+    RoomMemberBmc::remove(&ctx, &mm, id, ctx.user_id()).await?;
+    Ok(room)
+}
+
+pub async fn invite_to_room(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsRoomUser,
+) -> Result<Vec<RoomMember>> {
+    let ParamsRoomUser { room_id, user_id } = params;
+    PermissionBmc::require(&ctx, &mm, room_id, Permissions::INVITE_MEMBERS).await?;
+    UserBlockBmc::require_not_blocked_by(&ctx, &mm, user_id).await?;
+
+    // Rooms can only be shared with people already in the workspace
+    let room = RoomBmc::get(&ctx, &mm, room_id).await?;
+    if WorkspaceMemberBmc::role_of(&ctx, &mm, room.workspace_id, user_id)
+        .await?
+        .is_none()
+    {
+        return Err(model::Error::WorkspaceMemberNotFound {
+            workspace_id: room.workspace_id,
+            user_id,
+        }
+        .into());
+    }
+
+    RoomMemberBmc::add(&ctx, &mm, room_id, user_id, Role::Member).await?;
+    let members = RoomMemberBmc::list_by_room(&ctx, &mm, room_id).await?;
+    Ok(members)
+}
+
+pub async fn kick_from_room(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsRoomUser,
+) -> Result<Vec<RoomMember>> {
+    let ParamsRoomUser { room_id, user_id } = params;
+    PermissionBmc::require_over(&ctx, &mm, room_id, Permissions::KICK_MEMBERS, user_id).await?;
+
+    RoomMemberBmc::remove(&ctx, &mm, room_id, user_id).await?;
+    let members = RoomMemberBmc::list_by_room(&ctx, &mm, room_id).await?;
+    Ok(members)
+}
+
+pub async fn list_room_members(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<Vec<RoomMember>> {
+    let ParamsIded { id } = params;
+    RoomBmc::get_visible(&ctx, &mm, id).await?;
+    let members = RoomMemberBmc::list_by_room(&ctx, &mm, id).await?;
+    Ok(members)
+}
+
+/// Assigns a room role. The caller needs `MANAGE_ROLES`, must outrank the target,
+/// and can only grant roles below their own.
+pub async fn set_member_role(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsRoomRole,
+) -> Result<Vec<RoomMember>> {
+    let ParamsRoomRole {
+        room_id,
+        user_id,
+        role,
+    } = params;
+    PermissionBmc::require_over(&ctx, &mm, room_id, Permissions::MANAGE_ROLES, user_id).await?;
+
+    let own = PermissionBmc::role_in_room(&ctx, &mm, room_id).await?;
+    if !own.is_some_and(|own| own.outranks(role)) {
+        return Err(model::Error::PermissionDenied {
+            room_id,
+            missing: Permissions::MANAGE_ROLES,
+        }
+        .into());
+    }
+
+    RoomMemberBmc::set_role(&ctx, &mm, room_id, user_id, role).await?;
+    let members = RoomMemberBmc::list_by_room(&ctx, &mm, room_id).await?;
+    Ok(members)
+}
+
+pub async fn get_room_permissions(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<RoomPermissions> {
+    let ParamsIded { id } = params;
+    RoomBmc::get_visible(&ctx, &mm, id).await?;
+
+    let role = PermissionBmc::role_in_room(&ctx, &mm, id).await?;
+    let permissions = PermissionBmc::permissions_in_room(&ctx, &mm, id).await?;
+
+    Ok(RoomPermissions {
+        room_id: id,
+        role,
+        permissions,
+    })
+}
@@ -0,0 +1,41 @@
+use crate::ctx::Ctx;
+use crate::model::ModelManager;
+use crate::model::attachment::{AttachmentBmc, RoomUploads, StorageUsage};
+use crate::model::room::RoomBmc;
+use crate::web::error::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct ParamsStorageUsage {
+    /// Also report the usage of this room, against its quota.
+    pub room_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct StorageUsageResponse {
+    /// Everything the ctx user uploaded, against their quota.
+    user: StorageUsage,
+    /// Where those uploads are, deleting their messages frees the space.
+    rooms: Vec<RoomUploads>,
+    room: Option<StorageUsage>,
+}
+
+pub async fn get_storage_usage(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsStorageUsage,
+) -> Result<StorageUsageResponse> {
+    let ParamsStorageUsage { room_id } = params;
+
+    let user = AttachmentBmc::user_usage(&ctx, &mm, ctx.user_id()).await?;
+    let rooms = AttachmentBmc::list_uploads_by_room(&ctx, &mm).await?;
+    let room = match room_id {
+        Some(room_id) => {
+            RoomBmc::get_visible(&ctx, &mm, room_id).await?;
+            Some(AttachmentBmc::room_usage(&ctx, &mm, room_id).await?)
+        }
+        None => None,
+    };
+
+    Ok(StorageUsageResponse { user, rooms, room })
+}
@@ -1,95 +1,223 @@
-use crate::model::base;
-use crate::model::base::DbBmc;
-use crate::model::messages::{FriendMessage, Message, MessageToFriend, MessageWithImages};
+use crate::model::permission::{PermissionBmc, Permissions};
 use crate::model::room::{Room, RoomBmc};
-use crate::model::{ModelManager, Result};
+use crate::model::voice::{VoiceParticipant, VoiceParticipantBmc, VoiceStateUpdate};
+use crate::model::{ModelManager, Result, WsEvent};
 use crate::{
     ctx::Ctx,
     model::user::{User, UserBmc},
 };
 use axum::http::StatusCode;
-use sqlb::Fields;
-use sqlx::FromRow;
 
-#[derive(Debug, FromRow, Fields)]
-pub struct RoomParticipant {
+#[derive(serde::Deserialize)]
+pub struct ParamsVoiceRoom {
     pub room_id: i64,
-    pub user_id: i64,
 }
 
-pub struct ChatUsersBmc;
-impl DbBmc for ChatUsersBmc {
-    const TABLE: &'static str = "room_participants";
+#[derive(serde::Deserialize)]
+pub struct ParamsVoiceState {
+    pub room_id: i64,
+    #[serde(flatten)]
+    pub state: VoiceStateUpdate,
 }
 
 #[derive(serde::Deserialize)]
-pub struct ParamsJoinVoice {
+pub struct ParamsVoiceUser {
     pub room_id: i64,
+    pub user_id: i64,
 }
 
 #[derive(serde::Serialize)]
 pub struct JoinVoiceResult {
     pub room: Room,
     pub users: Vec<i64>,
-}
-
-impl ChatUsersBmc {
-    pub async fn insert(ctx: &Ctx, mm: &ModelManager, row: RoomParticipant) -> Result<()> {
-        base::create::<Self, _>(ctx, mm, row).await.map(|_| ())
-    }
-
-    pub async fn list_by_room(
-        ctx: &Ctx,
-        mm: &ModelManager,
-        room_id: i64,
-    ) -> Result<Vec<RoomParticipant>> {
-        let db = mm.db();
-        let items = sqlb::select()
-            .table(Self::TABLE)
-            .columns(&["user_id"])
-            .and_where("room_id", "=", room_id)
-            .order_by("joined_at")
-            .fetch_all::<_, RoomParticipant>(db)
-            .await?;
-        Ok(items)
-    }
+    pub participants: Vec<VoiceParticipant>,
 }
 
 pub async fn join_voice(
     ctx: Ctx,
     mm: ModelManager,
-    params: ParamsJoinVoice,
+    params: ParamsVoiceRoom,
 ) -> Result<JoinVoiceResult> {
-    let ParamsJoinVoice { room_id } = params;
+    let ParamsVoiceRoom { room_id } = params;
 
-    let room = RoomBmc::get(&ctx, &mm, room_id)
+    let room = RoomBmc::get_visible(&ctx, &mm, room_id)
         .await
         .map_err(|_| (StatusCode::NOT_FOUND, "Room not found"))?;
+    room.require_not_archived()?;
+    room.require_voice()?;
+
+    VoiceParticipantBmc::join(&ctx, &mm, &room).await?;
+
+    let user: User = UserBmc::get(&ctx, &mm, ctx.user_id()).await?;
+    let event = WsEvent::VoiceJoin {
+        room_id,
+        user_id: user.id,
+        username: user.username,
+    };
+    VoiceParticipantBmc::broadcast_event(&ctx, &mm, room_id, &event, None).await?;
+
+    let participants = VoiceParticipantBmc::list_by_room(&ctx, &mm, room_id).await?;
+    let users = participants.iter().map(|p| p.user_id).collect::<Vec<_>>();
+
+    Ok(JoinVoiceResult {
+        room,
+        users,
+        participants,
+    })
+}
+
+pub async fn leave_voice(ctx: Ctx, mm: ModelManager, params: ParamsVoiceRoom) -> Result<Room> {
+    let ParamsVoiceRoom { room_id } = params;
+    let room = RoomBmc::get(&ctx, &mm, room_id).await?;
 
-    if room.room_type != "voice" {
-        return Err((StatusCode::BAD_REQUEST, "Not a voice room").into());
+    VoiceParticipantBmc::leave(&ctx, &mm, room_id).await?;
+    notify_left(&ctx, &mm, &room).await?;
+
+    Ok(room)
+}
+
+/// Removes the ctx user from every voice room they are in, e.g. when their socket closes.
+pub async fn leave_all_voice(ctx: &Ctx, mm: &ModelManager) -> Result<()> {
+    for room_id in VoiceParticipantBmc::leave_all(ctx, mm).await? {
+        let room = RoomBmc::get(ctx, mm, room_id).await?;
+        notify_left(ctx, mm, &room).await?;
     }
 
-    let _ = ChatUsersBmc::insert(
-        &ctx,
-        &mm,
-        RoomParticipant {
-            room_id,
-            user_id: ctx.user_id(),
-        },
-    )
-    .await;
-
-    let users = ChatUsersBmc::list_by_room(&ctx, &mm, room_id)
-        .await?
-        .into_iter()
-        .map(|u| u.user_id)
-        .collect::<Vec<_>>();
+    Ok(())
+}
+
+pub async fn update_voice_state(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceState,
+) -> Result<VoiceParticipant> {
+    let ParamsVoiceState { room_id, state } = params;
+    let room = RoomBmc::get(&ctx, &mm, room_id).await?;
 
-    let user: User = UserBmc::get(&ctx, &mm, ctx.user_id()).await?;
-    mm.ws_broadcast
-        .broadcast_voice(room_id, user.id, &user.username)
-        .await;
+    let participant = VoiceParticipantBmc::update_state(&ctx, &mm, &room, state).await?;
+    let event = participant.state_changed_event();
+    VoiceParticipantBmc::broadcast_event(&ctx, &mm, room_id, &event, None).await?;
+
+    Ok(participant)
+}
+
+pub async fn list_voice_participants(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceRoom,
+) -> Result<Vec<VoiceParticipant>> {
+    let ParamsVoiceRoom { room_id } = params;
+    RoomBmc::get_visible(&ctx, &mm, room_id).await?;
+
+    VoiceParticipantBmc::list_by_room(&ctx, &mm, room_id).await
+}
+
+pub async fn raise_hand(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceRoom,
+) -> Result<VoiceParticipant> {
+    set_hand_raised(ctx, mm, params, true).await
+}
+
+pub async fn lower_hand(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceRoom,
+) -> Result<VoiceParticipant> {
+    set_hand_raised(ctx, mm, params, false).await
+}
+
+/// Lets a participant of a stage room unmute.
+pub async fn approve_speaker(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceUser,
+) -> Result<VoiceParticipant> {
+    set_speaker(ctx, mm, params, true).await
+}
+
+/// Moves a participant of a stage room back to the audience, muting them.
+pub async fn revoke_speaker(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceUser,
+) -> Result<VoiceParticipant> {
+    set_speaker(ctx, mm, params, false).await
+}
+
+/// Forwards a WebRTC signaling payload to `to_user`, who must share the voice room with the
+/// ctx user. The server never inspects the payload.
+pub async fn relay_rtc_signal(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    room_id: i64,
+    to_user: i64,
+    payload: serde_json::Value,
+) -> Result<()> {
+    VoiceParticipantBmc::require_peers(ctx, mm, room_id, to_user).await?;
+
+    let event = WsEvent::RtcSignal {
+        room_id,
+        from_user: ctx.user_id(),
+        payload,
+    };
+    match serde_json::to_string(&event) {
+        Ok(json) => mm.ws_broadcast.broadcast_to_user(to_user, &json).await,
+        Err(e) => tracing::error!("Failed to serialize WsEvent::RtcSignal: {e}"),
+    }
+
+    Ok(())
+}
+
+async fn set_hand_raised(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceRoom,
+    raised: bool,
+) -> Result<VoiceParticipant> {
+    let ParamsVoiceRoom { room_id } = params;
+    RoomBmc::get(&ctx, &mm, room_id).await?;
+
+    let participant = VoiceParticipantBmc::set_hand_raised(&ctx, &mm, room_id, raised).await?;
+    let event = WsEvent::VoiceHandRaised {
+        room_id,
+        user_id: participant.user_id,
+        raised,
+    };
+    VoiceParticipantBmc::broadcast_event(&ctx, &mm, room_id, &event, None).await?;
+
+    Ok(participant)
+}
+
+async fn set_speaker(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsVoiceUser,
+    speaker: bool,
+) -> Result<VoiceParticipant> {
+    let ParamsVoiceUser { room_id, user_id } = params;
+    PermissionBmc::require(&ctx, &mm, room_id, Permissions::MANAGE_VOICE).await?;
+    let room = RoomBmc::get(&ctx, &mm, room_id).await?;
+
+    let participant = VoiceParticipantBmc::set_speaker(&ctx, &mm, &room, user_id, speaker).await?;
+    let event = WsEvent::VoiceSpeakerChanged {
+        room_id,
+        user_id,
+        speaker,
+    };
+    VoiceParticipantBmc::broadcast_event(&ctx, &mm, room_id, &event, None).await?;
+    let event = participant.state_changed_event();
+    VoiceParticipantBmc::broadcast_event(&ctx, &mm, room_id, &event, None).await?;
+
+    Ok(participant)
+}
+
+async fn notify_left(ctx: &Ctx, mm: &ModelManager, room: &Room) -> Result<()> {
+    let event = WsEvent::VoiceLeave {
+        room_id: room.id,
+        user_id: ctx.user_id(),
+    };
 
-    Ok(JoinVoiceResult { room: room, users })
+    VoiceParticipantBmc::broadcast_event(ctx, mm, room.id, &event, Some(ctx.user_id())).await
 }
@@ -0,0 +1,168 @@
+use crate::ctx::Ctx;
+use crate::model;
+use crate::model::ModelManager;
+use crate::model::block::UserBlockBmc;
+use crate::model::permission::{PermissionBmc, Permissions, Role};
+use crate::model::workspace::member::{WorkspaceMember, WorkspaceMemberBmc};
+use crate::model::workspace::{Workspace, WorkspaceBmc, WorkspaceCreate, WorkspaceUpdate};
+use crate::web::error::Result;
+use serde::Deserialize;
+
+use super::{ParamsForCreate, ParamsForUpdate, ParamsIded};
+
+#[derive(Deserialize)]
+pub struct ParamsWorkspaceUser {
+    pub workspace_id: i64,
+    pub user_id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ParamsWorkspaceRole {
+    pub workspace_id: i64,
+    pub user_id: i64,
+    pub role: Role,
+}
+
+pub async fn create_workspace(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForCreate<WorkspaceCreate>,
+) -> Result<Workspace> {
+    let ParamsForCreate { data } = params;
+    let id = WorkspaceBmc::create(&ctx, &mm, data).await?;
+    let workspace = WorkspaceBmc::get(&ctx, &mm, id).await?;
+    Ok(workspace)
+}
+
+pub async fn list_workspaces(ctx: Ctx, mm: ModelManager) -> Result<Vec<Workspace>> {
+    let workspaces = WorkspaceBmc::list(&ctx, &mm).await?;
+    Ok(workspaces)
+}
+
+pub async fn update_workspace(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsForUpdate<WorkspaceUpdate>,
+) -> Result<Workspace> {
+    let ParamsForUpdate { id, data } = params;
+    PermissionBmc::require_in_workspace(&ctx, &mm, id, Permissions::MANAGE_WORKSPACE).await?;
+    WorkspaceBmc::update(&ctx, &mm, id, data).await?;
+    let workspace = WorkspaceBmc::get(&ctx, &mm, id).await?;
+    Ok(workspace)
+}
+
+/// Deleting a workspace removes all of its rooms, so it is reserved to the owner.
+pub async fn delete_workspace(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<Workspace> {
+    let ParamsIded { id } = params;
+    if PermissionBmc::role_in_workspace(&ctx, &mm, id).await? != Some(Role::Owner) {
+        return Err(model::Error::WorkspacePermissionDenied {
+            workspace_id: id,
+            missing: Permissions::empty(),
+        }
+        .into());
+    }
+
+    let workspace = WorkspaceBmc::get(&ctx, &mm, id).await?;
+    WorkspaceBmc::delete(&ctx, &mm, id).await?;
+    Ok(workspace)
+}
+
+pub async fn leave_workspace(ctx: Ctx, mm: ModelManager, params: ParamsIded) -> Result<i64> {
+    let ParamsIded { id } = params;
+    WorkspaceMemberBmc::remove(&ctx, &mm, id, ctx.user_id()).await?;
+    Ok(id)
+}
+
+/// Adds a user without an invite, so it is reserved to those who manage the workspace, and
+/// never adds someone who blocked the caller.
+pub async fn add_workspace_member(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsWorkspaceUser,
+) -> Result<Vec<WorkspaceMember>> {
+    let ParamsWorkspaceUser {
+        workspace_id,
+        user_id,
+    } = params;
+    PermissionBmc::require_in_workspace(&ctx, &mm, workspace_id, Permissions::MANAGE_WORKSPACE)
+        .await?;
+    UserBlockBmc::require_not_blocked_by(&ctx, &mm, user_id).await?;
+
+    WorkspaceMemberBmc::add(&ctx, &mm, workspace_id, user_id, Role::Member).await?;
+    let members = WorkspaceMemberBmc::list_by_workspace(&ctx, &mm, workspace_id).await?;
+    Ok(members)
+}
+
+pub async fn kick_from_workspace(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsWorkspaceUser,
+) -> Result<Vec<WorkspaceMember>> {
+    let ParamsWorkspaceUser {
+        workspace_id,
+        user_id,
+    } = params;
+    PermissionBmc::require_over_in_workspace(
+        &ctx,
+        &mm,
+        workspace_id,
+        Permissions::KICK_MEMBERS,
+        user_id,
+    )
+    .await?;
+
+    WorkspaceMemberBmc::remove(&ctx, &mm, workspace_id, user_id).await?;
+    let members = WorkspaceMemberBmc::list_by_workspace(&ctx, &mm, workspace_id).await?;
+    Ok(members)
+}
+
+pub async fn list_workspace_members(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsIded,
+) -> Result<Vec<WorkspaceMember>> {
+    let ParamsIded { id } = params;
+    if PermissionBmc::role_in_workspace(&ctx, &mm, id)
+        .await?
+        .is_none()
+    {
+        return Err(model::Error::WorkspaceAccessDenied { workspace_id: id }.into());
+    }
+
+    let members = WorkspaceMemberBmc::list_by_workspace(&ctx, &mm, id).await?;
+    Ok(members)
+}
+
+/// Assigns a workspace role, with the same rules as `set_member_role` for rooms.
+pub async fn set_workspace_member_role(
+    ctx: Ctx,
+    mm: ModelManager,
+    params: ParamsWorkspaceRole,
+) -> Result<Vec<WorkspaceMember>> {
+    let ParamsWorkspaceRole {
+        workspace_id,
+        user_id,
+        role,
+    } = params;
+    PermissionBmc::require_over_in_workspace(
+        &ctx,
+        &mm,
+        workspace_id,
+        Permissions::MANAGE_ROLES,
+        user_id,
+    )
+    .await?;
+
+    let own = PermissionBmc::role_in_workspace(&ctx, &mm, workspace_id).await?;
+    if !own.is_some_and(|own| own.outranks(role)) {
+        return Err(model::Error::WorkspacePermissionDenied {
+            workspace_id,
+            missing: Permissions::MANAGE_ROLES,
+        }
+        .into());
+    }
+
+    WorkspaceMemberBmc::set_role(&ctx, &mm, workspace_id, user_id, role).await?;
+    let members = WorkspaceMemberBmc::list_by_workspace(&ctx, &mm, workspace_id).await?;
+    Ok(members)
+}
@@ -0,0 +1,223 @@
+//! Local files that hold uploads while they are received and checked, before they go to the
+//! blob store.
+
+use crate::config;
+use crate::web::error::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+const HASH_BUF_BYTES: usize = 64 * 1024;
+
+/// Path of the staged bytes of upload `id`.
+pub fn staging_path(id: Uuid) -> PathBuf {
+    Path::new(&config().UPLOAD_STAGING_DIR).join(format!("{id}.part"))
+}
+
+/// Uploads whose staged bytes a request is writing. Staged files are local, so every chunk of
+/// an upload reaches this process.
+static WRITING: LazyLock<Mutex<HashSet<Uuid>>> = LazyLock::new(Default::default);
+
+/// The right to write the staged bytes of an upload, held by one request at a time and
+/// released when dropped.
+#[derive(Debug)]
+pub struct StagingWriteLock {
+    id: Uuid,
+}
+
+impl StagingWriteLock {
+    /// Fails with `Error::UploadChunkInProgress` while another request writes to upload `id`.
+    pub fn acquire(id: Uuid) -> Result<Self> {
+        let mut writing = WRITING.lock().unwrap_or_else(|err| err.into_inner());
+        if writing.insert(id) {
+            Ok(Self { id })
+        } else {
+            Err(Error::UploadChunkInProgress)
+        }
+    }
+}
+
+impl Drop for StagingWriteLock {
+    fn drop(&mut self) {
+        WRITING
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&self.id);
+    }
+}
+
+pub async fn create_staging_dir() -> Result<()> {
+    tokio::fs::create_dir_all(&config().UPLOAD_STAGING_DIR)
+        .await
+        .map_err(staging_error)
+}
+
+/// A complete staged upload, removed from disk when dropped.
+#[derive(Debug)]
+pub struct StagedFile {
+    path: PathBuf,
+    size: u64,
+    /// Hex SHA-256 of the content.
+    sha256: String,
+}
+
+impl StagedFile {
+    /// Takes ownership of the file already staged at `path`, hashing its content.
+    pub async fn from_path(path: PathBuf) -> Result<Self> {
+        let mut staged = Self {
+            path,
+            size: 0,
+            sha256: String::new(),
+        };
+
+        let mut file = File::open(&staged.path).await.map_err(staging_error)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; HASH_BUF_BYTES];
+        loop {
+            let read = file.read(&mut buf).await.map_err(staging_error)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            staged.size += read as u64;
+        }
+        staged.sha256 = format!("{:x}", hasher.finalize());
+
+        Ok(staged)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// Up to `len` leading bytes.
+    pub async fn read_head(&self, len: usize) -> Result<Vec<u8>> {
+        let file = File::open(&self.path).await.map_err(staging_error)?;
+        let mut head = Vec::with_capacity(len);
+        file.take(len as u64)
+            .read_to_end(&mut head)
+            .await
+            .map_err(staging_error)?;
+
+        Ok(head)
+    }
+
+    pub async fn read_all(&self) -> Result<Vec<u8>> {
+        tokio::fs::read(&self.path).await.map_err(staging_error)
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Writes an upload to a new staged file as it arrives, hashing it on the way.
+pub struct StagingWriter {
+    file: File,
+    hasher: Sha256,
+    staged: StagedFile,
+}
+
+impl StagingWriter {
+    pub async fn create() -> Result<Self> {
+        create_staging_dir().await?;
+        let path = staging_path(Uuid::new_v4());
+        let file = File::create(&path).await.map_err(staging_error)?;
+
+        Ok(Self {
+            file,
+            hasher: Sha256::new(),
+            staged: StagedFile {
+                path,
+                size: 0,
+                sha256: String::new(),
+            },
+        })
+    }
+
+    /// Bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.staged.size
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file.write_all(chunk).await.map_err(staging_error)?;
+        self.hasher.update(chunk);
+        self.staged.size += chunk.len() as u64;
+
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<StagedFile> {
+        self.file.flush().await.map_err(staging_error)?;
+        self.staged.sha256 = format!("{:x}", self.hasher.finalize());
+
+        Ok(self.staged)
+    }
+}
+
+pub fn staging_error(err: std::io::Error) -> Error {
+    Error::UploadStaging(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_staging_writer_hashes_and_cleans_up() {
+        let mut writer = StagingWriter::create().await.unwrap();
+        writer.write(b"hello ").await.unwrap();
+        writer.write(b"world").await.unwrap();
+        let staged = writer.finish().await.unwrap();
+
+        assert_eq!(staged.size(), 11);
+        assert_eq!(
+            staged.sha256(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(staged.read_head(5).await.unwrap(), b"hello");
+
+        // Re-hashing the staged bytes gives the same digest
+        let path = staged.path().to_path_buf();
+        let copy = path.with_extension("copy");
+        std::fs::copy(&path, &copy).unwrap();
+        let rehashed = StagedFile::from_path(copy.clone()).await.unwrap();
+        assert_eq!(rehashed.sha256(), staged.sha256());
+
+        drop(staged);
+        drop(rehashed);
+        assert!(!path.exists());
+        assert!(!copy.exists());
+    }
+
+    #[test]
+    fn test_staging_write_lock_one_request_at_a_time() {
+        let fx_id = Uuid::new_v4();
+
+        let lock = StagingWriteLock::acquire(fx_id).unwrap();
+        assert!(matches!(
+            StagingWriteLock::acquire(fx_id),
+            Err(Error::UploadChunkInProgress)
+        ));
+        // Other uploads are not held up
+        StagingWriteLock::acquire(Uuid::new_v4()).unwrap();
+
+        drop(lock);
+        StagingWriteLock::acquire(fx_id).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+use crate::AppState;
+use crate::Ctx;
+use crate::config;
+use crate::media::scan::ScanVerdict;
+use crate::media::{self, UploadLimits, thumbnail};
+use crate::model::attachment::{
+    Attachment, AttachmentBmc, AttachmentDetails, AttachmentForCreate, AttachmentKind, ImageDetails,
+};
+use crate::model::blob::{Blob, BlobBmc, BlobForCreate, content_key};
+use crate::model::messages::MessageBmc;
+use crate::model::permission::{PermissionBmc, Permissions};
+use crate::model::quarantine::{QuarantineBmc, QuarantinedFileForCreate, quarantine_key};
+use crate::model::room::RoomBmc;
+use crate::model::{self, ModelManager, WsEvent};
+use crate::web::error::{Error, Result};
+use crate::web::staging::{StagedFile, StagingWriter};
+use axum::Json;
+use axum::extract::State;
+use axum::{http::StatusCode, response::IntoResponse};
+use axum_extra::extract::Multipart;
+use axum_extra::extract::multipart::{Field, MultipartError};
+use axum_extra::typed_header::TypedHeader;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Room left for the multipart framing and the other fields on top of the file itself.
+const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+/// Longest original file name kept, in characters.
+const MAX_FILE_NAME_CHARS: usize = 255;
+
+/// Request body limit of the upload routes.
+pub fn upload_body_limit() -> usize {
+    UploadLimits::from_config().max_upload_bytes() + MULTIPART_OVERHEAD
+}
+
+/// Attaches a file to one of the ctx user's messages. Returns the created `Attachment`.
+///
+/// The file is streamed to a staged file rather than buffered, see `upload_sessions` for
+/// uploads that can resume after a dropped connection. The `message_id` field must come
+/// before the `file` field, so nothing is written to disk for a message the user cannot
+/// attach to.
+pub async fn upload_attachment(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    TypedHeader(_cookies): TypedHeader<headers::Cookie>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse> {
+    let mm = &state.mm;
+    let limits = UploadLimits::from_config();
+    let mut message_id: Option<i64> = None;
+    let mut original_file_name: Option<String> = None;
+    // The message the file was checked against, with the file
+    let mut staged: Option<(i64, StagedFile)> = None;
+
+    tracing::debug!("UPLOAD ATTACHMENT: Received request to save file");
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| multipart_error(err, &limits))?
+    {
+        match field.name() {
+            Some("message_id") => {
+                let val = field
+                    .text()
+                    .await
+                    .map_err(|err| multipart_error(err, &limits))?;
+                message_id = val.parse::<i64>().ok();
+                tracing::debug!("UPLOAD ATTACHMENT: Message Id {:?}", &message_id);
+            }
+            Some("file") => {
+                let Some(mid) = message_id else {
+                    return Err(Error::UploadMissingFields);
+                };
+                require_attachable(&ctx, mm, mid).await?;
+
+                original_file_name = field.file_name().map(clean_file_name);
+                tracing::debug!(
+                    "UPLOAD ATTACHMENT: Original file name {:?}",
+                    &original_file_name
+                );
+                staged = Some((mid, stage_field(field, &limits).await?));
+            }
+            _ => {}
+        }
+    }
+
+    let (Some((mid, staged)), Some(file_name)) = (staged, original_file_name) else {
+        return Err(Error::UploadMissingFields);
+    };
+
+    let attachment = store_upload(&ctx, mm, mid, file_name, staged).await?;
+
+    Ok((StatusCode::OK, Json(attachment)).into_response())
+}
+
+/// Files are attached to the ctx user's own message, in a room they can still post to.
+pub async fn require_attachable(ctx: &Ctx, mm: &ModelManager, message_id: i64) -> Result<()> {
+    let (room_id, author_id) = MessageBmc::room_and_author(ctx, mm, message_id).await?;
+    if author_id != ctx.user_id() {
+        return Err(model::Error::PermissionDenied {
+            room_id,
+            missing: Permissions::empty(),
+        }
+        .into());
+    }
+    let room = RoomBmc::get_visible(ctx, mm, room_id).await?;
+    room.require_not_archived()?;
+    PermissionBmc::require(ctx, mm, room_id, Permissions::SEND_MESSAGES).await?;
+
+    Ok(())
+}
+
+/// Checks a complete staged upload and stores it, with its renditions for images, as an
+/// attachment of `message_id`.
+///
+/// The stored kind, type and extension come from the content, never from the client. Images
+/// and text are inspected whole in memory, other kinds are streamed to the blob store. Files
+/// are stored under their hash, content already stored for another attachment is reused.
+/// The file counts against the storage quotas of the ctx user and of the room. A file the
+/// content scanner flags is quarantined instead, failing with `Error::UploadQuarantined`.
+pub async fn store_upload(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    message_id: i64,
+    file_name: String,
+    staged: StagedFile,
+) -> Result<Attachment> {
+    let limits = UploadLimits::from_config();
+    let keep_icc = config().UPLOAD_KEEP_ICC_PROFILE;
+
+    let file = media::detect_file_head(&staged.read_head(media::DETECT_HEAD_BYTES).await?)?;
+    let max_bytes = limits.max_bytes(file.kind);
+    if staged.size() > max_bytes as u64 {
+        return Err(media::Error::TooLarge { max_bytes }.into());
+    }
+
+    // Scanned as received, before anything is stored or attached
+    if let ScanVerdict::Infected { signature } = mm.content_scanner().scan(staged.path()).await? {
+        let quarantine_id = quarantine(ctx, mm, message_id, file_name, &staged, signature).await?;
+        return Err(Error::UploadQuarantined { quarantine_id });
+    }
+
+    let mut upload = None;
+    if media::is_inspected(file.kind) {
+        let bytes = staged.read_all().await?;
+        upload = Some(
+            tokio::task::spawn_blocking(move || media::process_upload(bytes, &limits, keep_icc))
+                .await
+                .map_err(|err| media::Error::Undecodable(err.to_string()))??,
+        );
+    }
+    let file = upload.as_ref().map_or(file, |upload| upload.file);
+    let sanitized = upload.as_ref().is_some_and(|upload| upload.sanitized);
+    let (image, renditions) = upload
+        .as_mut()
+        .and_then(|upload| upload.image.take())
+        .map(|image| {
+            let thumbnail::Previews {
+                thumbnail,
+                preview,
+                blurhash,
+            } = image.previews;
+            let details = ImageDetails {
+                width: Some(image.width as i32),
+                height: Some(image.height as i32),
+                blurhash: Some(blurhash),
+                ..Default::default()
+            };
+            (details, [thumbnail.bytes, preview.bytes])
+        })
+        .unzip();
+
+    // Sanitizing rewrote the bytes, hash what is stored
+    let (sha256, size_bytes) = match &upload {
+        Some(upload) if upload.sanitized => (
+            format!("{:x}", Sha256::digest(&upload.bytes)),
+            upload.bytes.len() as i64,
+        ),
+        _ => (staged.sha256().to_string(), staged.size() as i64),
+    };
+    AttachmentBmc::require_quota(ctx, mm, message_id, size_bytes).await?;
+    let rendition_key = |size: u32| content_key(&sha256, &format!("_{size}.webp"));
+
+    let blob = BlobBmc::acquire(
+        ctx,
+        mm,
+        BlobForCreate {
+            sha256: sha256.clone(),
+            storage_key: content_key(&sha256, &format!(".{}", file.extension)),
+            thumbnail_key: image
+                .as_ref()
+                .map(|_| rendition_key(thumbnail::THUMBNAIL_SIZE)),
+            preview_key: image
+                .as_ref()
+                .map(|_| rendition_key(thumbnail::PREVIEW_SIZE)),
+            size_bytes,
+        },
+    )
+    .await?;
+
+    // Another upload of the content may still be storing it, storing the same files again
+    // is harmless while skipping them could leave the attachment without a file
+    let deduplicated = blob.stored;
+    if !blob.stored {
+        let content = match upload {
+            Some(upload) => StoredContent::Bytes(upload.bytes),
+            None => StoredContent::Staged(&staged),
+        };
+        let stored = match put_content(mm, &blob, file.content_type, content, renditions).await {
+            Ok(()) => BlobBmc::mark_stored(ctx, mm, &sha256)
+                .await
+                .map_err(Error::from),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = stored {
+            release_blob(ctx, mm, &sha256).await;
+            return Err(err);
+        }
+    }
+
+    let details = match image {
+        Some(image) => AttachmentDetails::Image(ImageDetails {
+            thumbnail_key: blob.thumbnail_key.clone(),
+            preview_key: blob.preview_key.clone(),
+            ..image
+        }),
+        None => match file.kind {
+            AttachmentKind::Document => AttachmentDetails::Document,
+            AttachmentKind::Text => AttachmentDetails::Text,
+            _ => AttachmentDetails::Archive,
+        },
+    };
+
+    tracing::debug!(
+        "Saving attachment: filename = {}, storage_key = {}, kind = {:?}, deduplicated = {}",
+        &file_name,
+        &blob.storage_key,
+        file.kind,
+        deduplicated
+    );
+
+    let attachment_c = AttachmentForCreate {
+        id: Uuid::new_v4(),
+        message_id,
+        filename: file_name,
+        content_type: file.content_type.to_string(),
+        size_bytes,
+        storage_key: blob.storage_key,
+        sha256: sha256.clone(),
+        sanitized,
+        details,
+    };
+    match AttachmentBmc::create(ctx, mm, attachment_c).await {
+        Ok(attachment) => Ok(attachment),
+        Err(err) => {
+            release_blob(ctx, mm, &sha256).await;
+            Err(err.into())
+        }
+    }
+}
+
+/// Drops the reference of a failed upload. A failure is only logged so the caller reports
+/// the original error, the janitor fixes the count later.
+async fn release_blob(ctx: &Ctx, mm: &ModelManager, sha256: &str) {
+    if let Err(err) = BlobBmc::release(ctx, mm, sha256).await {
+        tracing::warn!("Failed to release blob {sha256} after a failed upload: {err:?}");
+    }
+}
+
+/// Keeps a flagged upload for review, out of the room, and notifies the room's moderators.
+/// Returns the quarantine id.
+async fn quarantine(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    message_id: i64,
+    file_name: String,
+    staged: &StagedFile,
+    signature: String,
+) -> Result<Uuid> {
+    let (room_id, _) = MessageBmc::room_and_author(ctx, mm, message_id).await?;
+    let id = Uuid::new_v4();
+    let storage_key = quarantine_key(id);
+    mm.blob_store()
+        .put_file(&storage_key, staged.path(), "application/octet-stream")
+        .await
+        .map_err(model::Error::from)?;
+
+    let file = QuarantineBmc::create(
+        ctx,
+        mm,
+        QuarantinedFileForCreate {
+            id,
+            room_id,
+            filename: file_name,
+            size_bytes: staged.size() as i64,
+            sha256: staged.sha256().to_string(),
+            storage_key,
+            signature,
+        },
+    )
+    .await?;
+    tracing::warn!(
+        "Quarantined upload: id = {}, user_id = {}, room_id = {}, signature = {}",
+        file.id,
+        ctx.user_id(),
+        room_id,
+        &file.signature
+    );
+
+    let room = RoomBmc::get(ctx, mm, room_id).await?;
+    let moderators =
+        RoomBmc::user_ids_with_permission(ctx, mm, &room, Permissions::DELETE_MESSAGES).await?;
+    let event = serde_json::to_string(&WsEvent::UploadQuarantined {
+        room_id,
+        quarantine_id: file.id,
+        user_id: ctx.user_id(),
+        filename: file.filename,
+        signature: file.signature,
+    })?;
+    mm.ws_broadcast
+        .broadcast_to_users(&moderators, &event)
+        .await;
+
+    Ok(file.id)
+}
+
+/// The stored file of a content not stored yet, already in memory or still staged.
+enum StoredContent<'a> {
+    Bytes(Vec<u8>),
+    Staged(&'a StagedFile),
+}
+
+/// Stores the files of an acquired content not stored yet.
+async fn put_content(
+    mm: &ModelManager,
+    blob: &Blob,
+    content_type: &str,
+    content: StoredContent<'_>,
+    renditions: Option<[Vec<u8>; 2]>,
+) -> Result<()> {
+    let blobs = mm.blob_store();
+    match content {
+        StoredContent::Bytes(bytes) => blobs.put(&blob.storage_key, bytes, content_type).await,
+        StoredContent::Staged(staged) => {
+            blobs
+                .put_file(&blob.storage_key, staged.path(), content_type)
+                .await
+        }
+    }
+    .map_err(model::Error::from)?;
+
+    if let (Some([thumbnail, preview]), Some(thumbnail_key), Some(preview_key)) =
+        (renditions, &blob.thumbnail_key, &blob.preview_key)
+    {
+        for (key, bytes) in [(thumbnail_key, thumbnail), (preview_key, preview)] {
+            blobs
+                .put(key, bytes, thumbnail::RENDITION_CONTENT_TYPE)
+                .await
+                .map_err(model::Error::from)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the base name of the client's file name (some browsers send a full path), capped
+/// to `MAX_FILE_NAME_CHARS`.
+pub fn clean_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    let base: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILE_NAME_CHARS)
+        .collect();
+
+    if base.is_empty() {
+        "file".to_string()
+    } else {
+        base
+    }
+}
+
+/// Streams the field to a staged file, failing as soon as it grows past what any kind of file
+/// may weigh.
+async fn stage_field(mut field: Field, limits: &UploadLimits) -> Result<StagedFile> {
+    let max_bytes = limits.max_upload_bytes();
+    let mut writer = StagingWriter::create().await?;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| multipart_error(err, limits))?
+    {
+        if writer.size() + chunk.len() as u64 > max_bytes as u64 {
+            return Err(media::Error::TooLarge { max_bytes }.into());
+        }
+        writer.write(&chunk).await?;
+    }
+
+    writer.finish().await
+}
+
+/// Reports a request cut off by the body limit as too large rather than malformed.
+fn multipart_error(err: MultipartError, limits: &UploadLimits) -> Error {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        media::Error::TooLarge {
+            max_bytes: limits.max_upload_bytes(),
+        }
+        .into()
+    } else {
+        Error::UploadInvalidMultipart
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_file_name() {
+        assert_eq!(clean_file_name("C:\\fakepath\\report.pdf"), "report.pdf");
+        assert_eq!(clean_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(clean_file_name(" server\n.log "), "server.log");
+        assert_eq!(clean_file_name("dir/"), "file");
+        assert_eq!(clean_file_name(&"a".repeat(300)).len(), MAX_FILE_NAME_CHARS);
+    }
+}
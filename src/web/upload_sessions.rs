@@ -0,0 +1,400 @@
+//! Resumable uploads. The client opens a session announcing the file size, `PUT`s the file in
+//! chunks, each starting at the `Upload-Offset` the session is at, then finalizes the session
+//! into an attachment. After a dropped connection, `GET` the session to learn the offset to
+//! resume from.
+
+use crate::AppState;
+use crate::Ctx;
+use crate::media::{self, UploadLimits};
+use crate::model::attachment::AttachmentBmc;
+use crate::model::upload_session::{UploadSession, UploadSessionBmc, UploadSessionForCreate};
+use crate::model::{self, ModelManager};
+use crate::web::error::{Error, Result};
+use crate::web::staging::{
+    StagedFile, StagingWriteLock, create_staging_dir, staging_error, staging_path,
+};
+use crate::web::upload_attachments::{clean_file_name, require_attachable, store_upload};
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::io::SeekFrom;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// Offset of the first byte of a chunk in the whole file.
+pub const UPLOAD_OFFSET: &str = "upload-offset";
+
+#[derive(Deserialize)]
+pub struct ParamsUploadSession {
+    pub message_id: i64,
+    pub filename: String,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the whole file, checked when the session is finalized.
+    pub sha256: Option<String>,
+}
+
+pub async fn create_upload_session(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    Json(params): Json<ParamsUploadSession>,
+) -> Result<Json<UploadSession>> {
+    let ParamsUploadSession {
+        message_id,
+        filename,
+        size_bytes,
+        sha256,
+    } = params;
+    let mm = &state.mm;
+
+    let max_bytes = UploadLimits::from_config().max_upload_bytes();
+    if size_bytes > max_bytes as i64 {
+        return Err(media::Error::TooLarge { max_bytes }.into());
+    }
+    let sha256 = sha256.map(|hash| hash.to_ascii_lowercase());
+    if size_bytes <= 0
+        || sha256
+            .as_ref()
+            .is_some_and(|hash| hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Err(Error::UploadInvalidParams);
+    }
+    require_attachable(&ctx, mm, message_id).await?;
+    // Checked again once the file is in, the quota may have filled up meanwhile
+    AttachmentBmc::require_quota(&ctx, mm, message_id, size_bytes).await?;
+
+    let session = UploadSessionBmc::create(
+        &ctx,
+        mm,
+        UploadSessionForCreate {
+            message_id,
+            filename: clean_file_name(&filename),
+            size_bytes,
+            sha256,
+        },
+    )
+    .await?;
+
+    Ok(Json(session))
+}
+
+pub async fn get_upload_session(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<UploadSession>> {
+    let session = UploadSessionBmc::get(&ctx, &state.mm, id).await?;
+
+    Ok(Json(session))
+}
+
+/// Appends the request body at `Upload-Offset`, which must be the session's `received_bytes`.
+///
+/// The bytes received before a connection drops are kept, so the next chunk resumes where this
+/// one stopped. A chunk sent while another one of the session is still being written fails
+/// with `UploadChunkInProgress`, the client retries once it is done. Returns the session with
+/// its new offset.
+pub async fn put_upload_chunk(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<UploadSession>> {
+    let mm = &state.mm;
+    // Held until the offset is recorded, so chunks never write over each other
+    let _write_lock = StagingWriteLock::acquire(id)?;
+    let session = UploadSessionBmc::get(&ctx, mm, id).await?;
+    let offset = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+        .ok_or(Error::UploadInvalidParams)?;
+    if offset != session.received_bytes {
+        return Err(model::Error::UploadOffsetMismatch {
+            id,
+            expected: session.received_bytes,
+        }
+        .into());
+    }
+
+    create_staging_dir().await?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(staging_path(id))
+        .await
+        .map_err(staging_error)?;
+    // Drops what a chunk that was never recorded left past the offset
+    file.set_len(offset as u64).await.map_err(staging_error)?;
+    file.seek(SeekFrom::Start(offset as u64))
+        .await
+        .map_err(staging_error)?;
+
+    let mut received = offset;
+    let mut stream = body.into_data_stream();
+    let mut interrupted = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                interrupted = Some(Error::UploadStaging(err.to_string()));
+                break;
+            }
+        };
+        if received + chunk.len() as i64 > session.size_bytes {
+            interrupted = Some(
+                media::Error::TooLarge {
+                    max_bytes: session.size_bytes as usize,
+                }
+                .into(),
+            );
+            break;
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            interrupted = Some(staging_error(err));
+            break;
+        }
+        received += chunk.len() as i64;
+    }
+
+    // Only what reached the disk is recorded
+    file.flush().await.map_err(staging_error)?;
+    file.sync_data().await.map_err(staging_error)?;
+    let session = UploadSessionBmc::advance(&ctx, mm, id, offset, received).await?;
+
+    match interrupted {
+        Some(err) => Err(err),
+        None => Ok(Json(session)),
+    }
+}
+
+/// Turns a complete session into an attachment. The session is closed whatever the outcome,
+/// a file that fails the checks has to be uploaded again.
+pub async fn finalize_upload_session(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let mm = &state.mm;
+    let session = UploadSessionBmc::get(&ctx, mm, id).await?;
+    if !session.is_complete() {
+        return Err(model::Error::UploadSessionIncomplete {
+            id,
+            received_bytes: session.received_bytes,
+            size_bytes: session.size_bytes,
+        }
+        .into());
+    }
+
+    let res = finalize(&ctx, mm, session).await;
+    UploadSessionBmc::delete(&ctx, mm, id).await?;
+
+    Ok((StatusCode::OK, Json(res?)).into_response())
+}
+
+async fn finalize(
+    ctx: &Ctx,
+    mm: &ModelManager,
+    session: UploadSession,
+) -> Result<model::attachment::Attachment> {
+    // Owns the staged bytes from here, they are removed once stored or rejected
+    let staged = StagedFile::from_path(staging_path(session.id)).await?;
+    if session
+        .sha256
+        .as_ref()
+        .is_some_and(|expected| expected != staged.sha256())
+    {
+        return Err(Error::UploadChecksumMismatch);
+    }
+    require_attachable(ctx, mm, session.message_id).await?;
+
+    store_upload(ctx, mm, session.message_id, session.filename, staged).await
+}
+
+pub async fn delete_upload_session(
+    ctx: Ctx,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    let mm = &state.mm;
+    let session = UploadSessionBmc::get(&ctx, mm, id).await?;
+    UploadSessionBmc::delete(&ctx, mm, session.id).await?;
+    let _ = tokio::fs::remove_file(staging_path(session.id)).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils;
+    use crate::model::workspace::member::WorkspaceMemberBmc;
+    use anyhow::Result;
+    use axum::http::HeaderValue;
+    use bytes::Bytes;
+    use serial_test::serial;
+    use sha2::{Digest, Sha256};
+
+    /// A new member of the default workspace with a message of theirs in a new room.
+    async fn fx_author(state: &AppState, username: &str) -> Result<(Ctx, i64)> {
+        let mm = &state.mm;
+        let fx_user_id = _dev_utils::seed_user(mm, username).await?;
+        WorkspaceMemberBmc::add_to_auto_join(mm, fx_user_id).await?;
+        let fx_room_id = _dev_utils::seed_room(mm, username, "text").await?;
+        let (fx_message_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO messages (message_text, message_room_id, message_user_id)
+            VALUES ('file', $1, $2) RETURNING id
+            "#,
+        )
+        .bind(fx_room_id)
+        .bind(fx_user_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok((Ctx::new(fx_user_id)?, fx_message_id))
+    }
+
+    async fn create(
+        ctx: &Ctx,
+        state: &AppState,
+        message_id: i64,
+        sha256: Option<String>,
+    ) -> crate::web::error::Result<UploadSession> {
+        let Json(session) = create_upload_session(
+            ctx.clone(),
+            State(state.clone()),
+            Json(ParamsUploadSession {
+                message_id,
+                filename: "notes.txt".to_string(),
+                size_bytes: 10,
+                sha256,
+            }),
+        )
+        .await?;
+
+        Ok(session)
+    }
+
+    /// The web errors are not `std::error::Error`, tests report them through their debug form.
+    fn web_err(err: Error) -> anyhow::Error {
+        anyhow::anyhow!("{err:?}")
+    }
+
+    async fn put(
+        ctx: &Ctx,
+        state: &AppState,
+        id: Uuid,
+        offset: i64,
+        body: Body,
+    ) -> crate::web::error::Result<UploadSession> {
+        let mut headers = HeaderMap::new();
+        headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+        let Json(session) =
+            put_upload_chunk(ctx.clone(), State(state.clone()), Path(id), headers, body).await?;
+
+        Ok(session)
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_put_chunks_resume_ok() -> Result<()> {
+        // Setup
+        let state = AppState {
+            mm: _dev_utils::init_test().await,
+        };
+        let (ctx, fx_message_id) = fx_author(&state, "upload_put_resume").await?;
+        let session = create(&ctx, &state, fx_message_id, None)
+            .await
+            .map_err(web_err)?;
+        // The connection drops after the first 5 bytes
+        let fx_cut_body = Body::from_stream(futures::stream::iter([
+            Ok(Bytes::from_static(b"hello")),
+            Err(std::io::Error::other("connection reset")),
+        ]));
+
+        // Execute
+        let res = put(&ctx, &state, session.id, 0, fx_cut_body).await;
+
+        // Check
+        assert!(res.is_err());
+        let session = UploadSessionBmc::get(&ctx, &state.mm, session.id).await?;
+        assert_eq!(session.received_bytes, 5);
+        let res =
+            finalize_upload_session(ctx.clone(), State(state.clone()), Path(session.id)).await;
+        assert!(matches!(
+            res,
+            Err(Error::Model(model::Error::UploadSessionIncomplete {
+                received_bytes: 5,
+                size_bytes: 10,
+                ..
+            }))
+        ));
+        // A retry from the start is told where to resume
+        let res = put(&ctx, &state, session.id, 0, Body::from("hello")).await;
+        assert!(matches!(
+            res,
+            Err(Error::Model(model::Error::UploadOffsetMismatch {
+                expected: 5,
+                ..
+            }))
+        ));
+
+        // Execute
+        let session = put(&ctx, &state, session.id, 5, Body::from("world"))
+            .await
+            .map_err(web_err)?;
+
+        // Check
+        assert!(session.is_complete());
+        assert_eq!(
+            tokio::fs::read(staging_path(session.id)).await?,
+            b"helloworld"
+        );
+
+        // Clean
+        delete_upload_session(ctx, State(state), Path(session.id))
+            .await
+            .map_err(web_err)?;
+
+        Ok(())
+    }
+
+    #[serial]
+    #[tokio::test]
+    async fn test_finalize_checksum_mismatch_err() -> Result<()> {
+        // Setup
+        let state = AppState {
+            mm: _dev_utils::init_test().await,
+        };
+        let (ctx, fx_message_id) = fx_author(&state, "upload_checksum").await?;
+        let fx_other_sha256 = format!("{:x}", Sha256::digest(b"other file"));
+        let session = create(&ctx, &state, fx_message_id, Some(fx_other_sha256))
+            .await
+            .map_err(web_err)?;
+        put(&ctx, &state, session.id, 0, Body::from("helloworld"))
+            .await
+            .map_err(web_err)?;
+
+        // Execute
+        let res =
+            finalize_upload_session(ctx.clone(), State(state.clone()), Path(session.id)).await;
+
+        // Check
+        assert!(matches!(res, Err(Error::UploadChecksumMismatch)));
+        // The session is closed and its bytes are gone
+        assert!(matches!(
+            UploadSessionBmc::get(&ctx, &state.mm, session.id).await,
+            Err(model::Error::UploadSessionNotFound { .. })
+        ));
+        assert!(!staging_path(session.id).exists());
+
+        Ok(())
+    }
+}
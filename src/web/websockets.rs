@@ -1,6 +1,11 @@
 use crate::Ctx;
-use crate::model::ModelManager;
+use crate::config::config;
+use crate::model::messages;
 use crate::model::user::UserBmc;
+use crate::model::voice::relay::{decode_client_frame, encode_relay_frame};
+use crate::model::{ModelManager, WsCommand, WsEvent};
+use crate::web;
+use crate::web::rpc::{leave_all_voice, post_room_message, relay_rtc_signal};
 use axum::{
     extract::State,
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
@@ -19,10 +24,11 @@ pub async fn ws_handler(
 ) -> impl IntoResponse {
     let user_id = ctx.user_id();
 
-    match UserBmc::find_username_by_id(ctx, mm.clone(), user_id).await {
+    match UserBmc::find_username_by_id(ctx.clone(), mm.clone(), user_id).await {
         Ok(Some(username_only)) => {
             let username = username_only.username;
-            Ok(ws.on_upgrade(move |socket| handle_socket(socket, username, mm))) as Result<_, _>
+            Ok(ws.on_upgrade(move |socket| handle_socket(socket, ctx, username, mm)))
+                as Result<_, _>
         }
         Ok(None) => Err((StatusCode::UNAUTHORIZED, "User not found")),
         Err(e) => {
@@ -32,18 +38,19 @@ pub async fn ws_handler(
     }
 }
 
-async fn handle_socket(socket: WebSocket, user_id: String, mm: ModelManager) {
+async fn handle_socket(socket: WebSocket, ctx: Ctx, username: String, mm: ModelManager) {
+    let user_id = ctx.user_id();
     let (mut sender, mut receiver) = socket.split();
     let (tx, mut rx) = unbounded_channel::<Message>();
 
-    mm.ws_broadcast.register_user(user_id.clone(), tx).await;
+    let connection_id = mm.ws_broadcast.register_user(user_id, tx).await;
 
     // Forward messages from channel to the socket
-    let user_id_clone = user_id.clone();
+    let username_clone = username.clone();
     tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
             if sender.send(msg).await.is_err() {
-                eprintln!("Failed to send message to user {}", user_id_clone);
+                tracing::warn!("Failed to send message to user {}", username_clone);
                 break;
             }
         }
@@ -53,12 +60,80 @@ async fn handle_socket(socket: WebSocket, user_id: String, mm: ModelManager) {
     while let Some(Ok(msg)) = receiver.next().await {
         match msg {
             Message::Text(text) => {
-                println!("[{}] Received: {}", user_id, text);
+                tracing::debug!("[{}] Received: {}", username, text);
+                if let Err(err) = handle_command(&ctx, &mm, &text).await {
+                    reply_error(user_id, &mm, err).await;
+                }
+            }
+            Message::Binary(frame) if config().VOICE_RELAY_ENABLED => {
+                relay_audio(user_id, &mm, &frame).await;
             }
             Message::Close(_) => break,
             _ => {}
         }
     }
 
-    mm.ws_broadcast.unregister_user(&user_id).await;
+    // Another tab or a reconnect may still hold the user in their voice rooms
+    let disconnected = mm
+        .ws_broadcast
+        .unregister_user(user_id, connection_id)
+        .await;
+    if disconnected && let Err(err) = leave_all_voice(&ctx, &mm).await {
+        tracing::error!("Failed to leave voice rooms for user {user_id}: {err:?}");
+    }
+}
+
+async fn handle_command(ctx: &Ctx, mm: &ModelManager, text: &str) -> web::error::Result<()> {
+    let command: WsCommand = serde_json::from_str(text)?;
+
+    match command {
+        WsCommand::SendMessage { room_id, content } => {
+            let data = messages::Message {
+                message_text: content,
+                message_room_id: room_id,
+                message_user_id: ctx.user_id(),
+            };
+            post_room_message(ctx, mm, data).await?;
+        }
+        WsCommand::RtcSignal {
+            room_id,
+            to_user,
+            payload,
+        } => {
+            relay_rtc_signal(ctx, mm, room_id, to_user, payload).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the client-facing form of `err` back to the user who issued the command.
+async fn reply_error(user_id: i64, mm: &ModelManager, err: web::error::Error) {
+    let (_, client_error) = err.client_status_and_error();
+    let event = WsEvent::CommandFailed {
+        error: serde_json::to_value(client_error).unwrap_or_default(),
+    };
+
+    match serde_json::to_string(&event) {
+        Ok(json) => mm.ws_broadcast.broadcast_to_user(user_id, &json).await,
+        Err(e) => tracing::error!("Failed to serialize WsEvent::CommandFailed: {e}"),
+    }
+}
+
+/// Forwards an Opus frame to the other participants of the sender's voice room. Malformed
+/// frames and frames from users outside the room are dropped silently.
+async fn relay_audio(user_id: i64, mm: &ModelManager, frame: &[u8]) {
+    let Some((room_id, payload)) = decode_client_frame(frame) else {
+        return;
+    };
+
+    let recipients = mm.audio_relay.recipients(room_id, user_id).await;
+    if recipients.is_empty() {
+        return;
+    }
+
+    let relayed = encode_relay_frame(room_id, user_id, payload);
+    mm.ws_broadcast
+        .send_binary_to_users(&recipients, &relayed)
+        .await;
 }